@@ -0,0 +1,99 @@
+use extendr_api::prelude::*;
+
+use crate::helpers_community::*;
+
+/// Leiden community detection over a set of resolutions
+///
+/// @description Runs the Leiden (or Louvain) community detection on an
+/// undirected, weighted graph for every provided resolution. The resolutions
+/// are processed in parallel and each one uses the same seed. The modularity
+/// (with a resolution of 1) of each partition is calculated on the original
+/// graph. WARNING! Incorrect use can cause kernel crashes. Wrapper around the
+/// Rust functions with type checks are provided in the package.
+///
+/// @param from Integer vector. The (1-indexed) nodes the edges start from.
+/// @param to Integer vector. The (1-indexed) nodes the edges go to.
+/// @param weight Numeric vector. The (non-negative) edge weights.
+/// @param n_nodes Integer. Number of nodes in the graph.
+/// @param resolutions Numeric vector. The resolutions to test.
+/// @param objective String. The quality function to optimise. One of
+/// `c("modularity", "cpm")`.
+/// @param algorithm String. One of `c("leiden", "louvain")`.
+/// @param n_iterations Integer. Number of iterations of the algorithm. A
+/// negative number will iterate until the partition does not change anymore.
+/// @param beta Float. Randomness in the refinement step of the Leiden
+/// algorithm.
+/// @param seed Integer. Random seed.
+/// @param max_workers Integer. Number of threads to use for the resolutions.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item membership - Integer vector of the (1-indexed) community
+///   memberships of the nodes, concatenated over the resolutions.
+///   \item modularity - The modularity of the partition per resolution.
+///   \item quality - The value of the quality function per resolution.
+/// }
+///
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn rs_leiden_resolutions(
+    from: &[i32],
+    to: &[i32],
+    weight: &[f64],
+    n_nodes: usize,
+    resolutions: &[f64],
+    objective: &str,
+    algorithm: &str,
+    n_iterations: i32,
+    beta: f64,
+    seed: usize,
+    max_workers: usize,
+) -> extendr_api::Result<List> {
+    let quality = parse_community_quality(objective)
+        .ok_or_else(|| format!("Invalid quality function: {}", objective))?;
+    let algorithm = parse_community_algorithm(algorithm)
+        .ok_or_else(|| format!("Invalid community detection algorithm: {}", algorithm))?;
+    if weight.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return Err("The edge weights need to be finite and non-negative.".into());
+    }
+    if from
+        .iter()
+        .chain(to.iter())
+        .any(|i| *i < 1 || *i as usize > n_nodes)
+    {
+        return Err("The edges contain nodes outside of 1 to n_nodes.".into());
+    }
+
+    let from: Vec<usize> = from.iter().map(|i| (*i - 1) as usize).collect();
+    let to: Vec<usize> = to.iter().map(|i| (*i - 1) as usize).collect();
+    let graph = WeightedGraph::from_edges(n_nodes, &from, &to, weight);
+
+    let params = CommunityParams {
+        quality,
+        algorithm,
+        n_iterations,
+        beta,
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(max_workers)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let res =
+        pool.install(|| community_resolution_sweep(&graph, &params, resolutions, seed as u64));
+
+    Ok(list!(
+        membership = res
+            .iter()
+            .flat_map(|x| x.membership.iter().map(|c| (*c + 1) as i32))
+            .collect::<Vec<i32>>(),
+        modularity = res.iter().map(|x| x.modularity).collect::<Vec<f64>>(),
+        quality = res.iter().map(|x| x.quality).collect::<Vec<f64>>()
+    ))
+}
+
+extendr_module! {
+    mod fun_community;
+    fn rs_leiden_resolutions;
+}
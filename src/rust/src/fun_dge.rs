@@ -0,0 +1,420 @@
+use extendr_api::prelude::*;
+use faer::Mat;
+use statrs::distribution::{ContinuousCDF, StudentsT};
+
+use crate::helpers_combat::{combat, CombatParams};
+use crate::helpers_dge::{
+    calc_norm_factors, cpm, design_is_full_rank, ebayes, filter_by_expr, lm_fit,
+    parse_norm_factor_method, voom, voom_quality_weights, TmmParams,
+};
+use crate::utils_r_rust::{faer_to_r_matrix, r_matrix_to_faer};
+
+/// Moderated t-statistics via empirical Bayes
+///
+/// @description Fits a linear model per gene via a QR decomposition of the
+/// design matrix, applies the contrasts and moderates the gene-wise variances
+/// with an empirical Bayes prior (equivalent to `limma::lmFit()`,
+/// `limma::contrasts.fit()` and `limma::eBayes()` for data without missing
+/// values). Genes are fitted in parallel. WARNING! Incorrect use can cause
+/// kernel crashes. Wrapper around the Rust functions with type checks are
+/// provided in the package.
+///
+/// @param y Numeric matrix. The (log-scale) expression values with rows =
+/// genes and columns = samples.
+/// @param design Numeric matrix. The design matrix with rows = samples and
+/// columns = coefficients. Needs to have full column rank.
+/// @param contrasts Numeric matrix. The contrast matrix with rows =
+/// coefficients and columns = contrasts. Use the identity matrix to test the
+/// coefficients of the design.
+/// @param weights Optional numeric matrix. Observation-level precision weights
+/// with the same dimensions as `y`, for example from `rs_voom()`. If provided,
+/// the models are fit via weighted least squares.
+/// @param proportion Float. The assumed proportion of differentially expressed
+/// genes (used for the B-statistic).
+/// @param conf_level Float. The confidence level for the confidence intervals
+/// of the log fold changes. Needs to be between 0 and 1.
+///
+/// @return A list with:
+/// \itemize{
+///   \item logfc - Matrix of the estimated contrasts (genes x contrasts).
+///   \item t - Matrix of the moderated t-statistics.
+///   \item pvals - Matrix of the two-sided p-values.
+///   \item b - Matrix of the log-odds of differential expression.
+///   \item ci_lower - Matrix of the lower bounds of the confidence intervals.
+///   \item ci_upper - Matrix of the upper bounds of the confidence intervals.
+///   \item ave_expr - The average expression per gene.
+///   \item sigma - The residual standard deviation per gene.
+///   \item s2_post - The posterior variance per gene.
+///   \item df_total - The total degrees of freedom per gene.
+///   \item var_prior - The prior variance of the non-zero contrasts.
+///   \item s2_prior - The prior variance of the residuals.
+///   \item df_prior - The prior degrees of freedom.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_limma_ebayes(
+    y: RMatrix<f64>,
+    design: RMatrix<f64>,
+    contrasts: RMatrix<f64>,
+    weights: Option<RMatrix<f64>>,
+    proportion: f64,
+    conf_level: f64,
+) -> extendr_api::Result<List> {
+    let y = r_matrix_to_faer(&y);
+    let weights = weights.as_ref().map(r_matrix_to_faer);
+    let design = r_matrix_to_faer(&design);
+    let contrasts = r_matrix_to_faer(&contrasts);
+
+    if y.ncols() != design.nrows() {
+        return Err("The number of samples in y and the design need to be the same.".into());
+    }
+    if design.ncols() != contrasts.nrows() {
+        return Err(
+            "The number of rows of the contrasts need to equal the columns of the design.".into(),
+        );
+    }
+    if weights.is_some_and(|w| w.shape() != y.shape()) {
+        return Err("The weights need to have the same dimensions as y.".into());
+    }
+    if !design_is_full_rank(design) {
+        return Err("The design matrix does not have full column rank.".into());
+    }
+    if conf_level <= 0.0 || conf_level >= 1.0 {
+        return Err("The confidence level needs to be between 0 and 1.".into());
+    }
+
+    let fit = lm_fit(y, design, weights, contrasts);
+    if fit.df_residual.iter().all(|df| *df <= 0.0) {
+        return Err("No residual degrees of freedom in the linear model fits.".into());
+    }
+    let res = ebayes(&fit, proportion);
+
+    let (n_genes, n_contrasts) = fit.coefficients.shape();
+    let margin: Vec<f64> = res
+        .df_total
+        .iter()
+        .zip(&res.s2_post)
+        .map(|(df, s2)| {
+            if *df > 0.0 {
+                let t_dist = StudentsT::new(0.0, 1.0, *df).unwrap();
+                t_dist.inverse_cdf(1.0 - (1.0 - conf_level) / 2.0) * s2.sqrt()
+            } else {
+                f64::NAN
+            }
+        })
+        .collect();
+    let ci_lower = Mat::from_fn(n_genes, n_contrasts, |i, j| {
+        fit.coefficients[(i, j)] - margin[i] * fit.stdev_unscaled[(i, j)]
+    });
+    let ci_upper = Mat::from_fn(n_genes, n_contrasts, |i, j| {
+        fit.coefficients[(i, j)] + margin[i] * fit.stdev_unscaled[(i, j)]
+    });
+
+    Ok(list!(
+        logfc = faer_to_r_matrix(fit.coefficients.as_ref()),
+        t = faer_to_r_matrix(res.t.as_ref()),
+        pvals = faer_to_r_matrix(res.pvals.as_ref()),
+        b = faer_to_r_matrix(res.lods.as_ref()),
+        ci_lower = faer_to_r_matrix(ci_lower.as_ref()),
+        ci_upper = faer_to_r_matrix(ci_upper.as_ref()),
+        ave_expr = fit.amean,
+        sigma = fit.sigma,
+        s2_post = res.s2_post,
+        df_total = res.df_total,
+        var_prior = res.var_prior,
+        s2_prior = res.s2_prior,
+        df_prior = res.df_prior
+    ))
+}
+
+/// voom precision weights
+///
+/// @description Transforms the counts to log2 counts per million and estimates
+/// observation-level precision weights from the mean-variance trend
+/// (equivalent to `limma::voom()` without normalisation between the samples).
+/// Optionally, relative sample quality weights are estimated additionally
+/// (akin to `limma::voomWithQualityWeights()`) via the gene-by-gene update
+/// algorithm. WARNING! Incorrect use can cause kernel crashes. Wrapper around
+/// the Rust functions with type checks are provided in the package.
+///
+/// @param counts Numeric matrix. The raw counts with rows = genes and columns
+/// = samples.
+/// @param lib_size Numeric vector. The (effective) library sizes of the
+/// samples.
+/// @param design Numeric matrix. The design matrix with rows = samples and
+/// columns = coefficients. Needs to have full column rank.
+/// @param span Float. The span of the lowess smoother.
+/// @param quality_weights Boolean. Shall the sample quality weights be
+/// estimated and incorporated into the weights.
+/// @param prior_n Float. The number of prior genes for the estimation of the
+/// sample weights. Ignored if `quality_weights = FALSE`.
+///
+/// @return A list with:
+/// \itemize{
+///   \item e - Matrix of the log2 counts per million.
+///   \item weights - Matrix of the precision weights.
+///   \item sample_weights - The sample weights (1 if
+///   `quality_weights = FALSE`).
+/// }
+///
+/// @export
+#[extendr]
+fn rs_voom(
+    counts: RMatrix<f64>,
+    lib_size: Vec<f64>,
+    design: RMatrix<f64>,
+    span: f64,
+    quality_weights: bool,
+    prior_n: f64,
+) -> extendr_api::Result<List> {
+    let counts = r_matrix_to_faer(&counts);
+    let design = r_matrix_to_faer(&design);
+
+    if counts.ncols() != design.nrows() || counts.ncols() != lib_size.len() {
+        return Err(
+            "The number of samples in counts, lib_size and the design need to be the same.".into(),
+        );
+    }
+    if !design_is_full_rank(design) {
+        return Err("The design matrix does not have full column rank.".into());
+    }
+
+    let (e, weights, sample_weights) = if quality_weights {
+        let res = voom_quality_weights(counts, &lib_size, design, span, prior_n);
+        (res.e, res.weights, res.sample_weights)
+    } else {
+        let (e, weights) = voom(counts, &lib_size, design, None, span);
+        (e, weights, vec![1.0; counts.ncols()])
+    };
+
+    Ok(list!(
+        e = faer_to_r_matrix(e.as_ref()),
+        weights = faer_to_r_matrix(weights.as_ref()),
+        sample_weights = sample_weights
+    ))
+}
+
+/// Calculate normalisation factors for count data
+///
+/// @description Calculates scaling factors to convert the raw library sizes
+/// to effective library sizes (equivalent to `edgeR::normLibSizes()`).
+/// Supports the trimmed mean of M-values (TMM), the upper-quartile and the
+/// DESeq-style relative log expression (RLE) methods. WARNING! Incorrect use
+/// can cause kernel crashes. Wrapper around the Rust functions with type
+/// checks are provided in the package.
+///
+/// @param counts Numeric matrix. The raw counts with rows = genes and columns
+/// = samples.
+/// @param method String. One of `c("TMM", "upperquartile", "RLE", "none")`.
+/// @param logratio_trim Float. The proportion of the M-values to trim from
+/// both sides for TMM.
+/// @param sum_trim Float. The proportion of the A-values to trim from both
+/// sides for TMM.
+/// @param do_weighting Boolean. Shall the TMM use precision weights.
+/// @param a_cutoff Float. The minimum A-value of a gene to be included in TMM.
+///
+/// @return The normalisation factors of the samples, scaled to multiply to
+/// one.
+///
+/// @export
+#[extendr]
+fn rs_calc_norm_factors(
+    counts: RMatrix<f64>,
+    method: &str,
+    logratio_trim: f64,
+    sum_trim: f64,
+    do_weighting: bool,
+    a_cutoff: f64,
+) -> extendr_api::Result<Vec<f64>> {
+    let norm_method = parse_norm_factor_method(method)
+        .ok_or_else(|| format!("Invalid normalisation method: {}", method))?;
+    let counts = r_matrix_to_faer(&counts);
+    let tmm_params = TmmParams {
+        logratio_trim,
+        sum_trim,
+        do_weighting,
+        a_cutoff,
+    };
+
+    Ok(calc_norm_factors(counts, &norm_method, &tmm_params))
+}
+
+/// Counts per million
+///
+/// @description Calculates the counts per million, optionally on the log2
+/// scale with a prior count (equivalent to `edgeR::cpm()`). WARNING! Incorrect
+/// use can cause kernel crashes. Wrapper around the Rust functions with type
+/// checks are provided in the package.
+///
+/// @param counts Numeric matrix. The raw counts with rows = genes and columns
+/// = samples.
+/// @param lib_size Numeric vector. The (effective) library sizes of the
+/// samples.
+/// @param log Boolean. Shall the log2 CPM be returned.
+/// @param prior_count Float. The average count to add to each observation to
+/// avoid taking the log of zero. Scaled by the relative library size of the
+/// sample. Ignored if `log = FALSE`.
+///
+/// @return The (log2) counts per million.
+///
+/// @export
+#[extendr]
+fn rs_cpm(
+    counts: RMatrix<f64>,
+    lib_size: &[f64],
+    log: bool,
+    prior_count: f64,
+) -> extendr_api::Result<RArray<f64, [usize; 2]>> {
+    let counts = r_matrix_to_faer(&counts);
+    if counts.ncols() != lib_size.len() {
+        return Err("The number of samples in counts and lib_size need to be the same.".into());
+    }
+    let res = cpm(counts, lib_size, log, prior_count);
+
+    Ok(faer_to_r_matrix(res.as_ref()))
+}
+
+/// Filter genes by expression
+///
+/// @description Identifies the genes with sufficiently large counts to be
+/// retained in a differential expression analysis (equivalent to
+/// `edgeR::filterByExpr()` with a group vector). WARNING! Incorrect use can
+/// cause kernel crashes. Wrapper around the Rust functions with type checks
+/// are provided in the package.
+///
+/// @param counts Numeric matrix. The raw counts with rows = genes and columns
+/// = samples.
+/// @param group String vector. The experimental group of each sample.
+/// @param lib_size Numeric vector. The (effective) library sizes of the
+/// samples.
+/// @param min_count Float. The minimum count required in at least some
+/// samples.
+/// @param min_total_count Float. The minimum total count required.
+/// @param large_n Float. The number of samples per group that is considered
+/// to be large.
+/// @param min_prop Float. The minimum proportion of samples in the smallest
+/// group that express the gene, applied beyond `large_n`.
+///
+/// @return A boolean vector indicating which genes to keep.
+///
+/// @export
+#[extendr]
+fn rs_filter_by_expr(
+    counts: RMatrix<f64>,
+    group: Vec<String>,
+    lib_size: &[f64],
+    min_count: f64,
+    min_total_count: f64,
+    large_n: f64,
+    min_prop: f64,
+) -> extendr_api::Result<Vec<bool>> {
+    let counts = r_matrix_to_faer(&counts);
+    if counts.ncols() != lib_size.len() || counts.ncols() != group.len() {
+        return Err(
+            "The number of samples in counts, group and lib_size need to be the same.".into(),
+        );
+    }
+
+    Ok(filter_by_expr(
+        counts,
+        &group,
+        lib_size,
+        (min_count, min_total_count),
+        (large_n, min_prop),
+    ))
+}
+
+/// ComBat batch correction
+///
+/// @description Empirical Bayes batch correction (equivalent to
+/// `sva::ComBat()`). The location and scale of each batch is estimated per
+/// gene on standardised data, shrunk towards a parametric or non-parametric
+/// prior across the genes and removed. Genes with NAs or zero variance within
+/// a batch are returned unchanged. WARNING! Incorrect use can cause kernel
+/// crashes. Wrapper around the Rust functions with type checks are provided in
+/// the package.
+///
+/// @param x Numeric matrix. Rows = genes, columns = samples.
+/// @param batch Integer vector. The batch of each sample (1-indexed).
+/// @param covariates Optional numeric matrix. Rows = samples, columns =
+/// covariates (without intercept) whose effects shall be preserved.
+/// @param par_prior Boolean. Shall parametric priors be used. Otherwise, the
+/// non-parametric priors are used.
+/// @param mean_only Boolean. Shall only the mean of the batches be adjusted.
+/// Set automatically if a batch has only a single sample.
+/// @param ref_batch Optional integer. The reference batch (1-indexed). The
+/// data of this batch is not adjusted and the other batches are adjusted
+/// towards it.
+///
+/// @return A list with:
+/// \itemize{
+///   \item corrected - The batch corrected matrix.
+///   \item gamma_hat - Matrix of the estimated batch locations (genes x
+///   batches).
+///   \item delta_hat - Matrix of the estimated batch scales.
+///   \item gamma_star - Matrix of the empirical Bayes batch locations.
+///   \item delta_star - Matrix of the empirical Bayes batch scales.
+///   \item gamma_bar - The prior mean of the batch locations.
+///   \item t2 - The prior variance of the batch locations.
+///   \item a_prior - The shape of the inverse gamma prior of the scales.
+///   \item b_prior - The scale of the inverse gamma prior of the scales.
+///   \item mean_only - Boolean. Were only the locations adjusted.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_combat(
+    x: RMatrix<f64>,
+    batch: Vec<i32>,
+    covariates: Option<RMatrix<f64>>,
+    par_prior: bool,
+    mean_only: bool,
+    ref_batch: Option<i32>,
+) -> extendr_api::Result<List> {
+    let dat = r_matrix_to_faer(&x);
+    let covariates = covariates.as_ref().map(r_matrix_to_faer);
+
+    if batch.len() != dat.ncols() {
+        return Err("The length of batch needs to equal the number of samples.".into());
+    }
+    if covariates.is_some_and(|c| c.nrows() != dat.ncols()) {
+        return Err("The covariates need one row per sample.".into());
+    }
+    if batch.iter().any(|&b| b < 1) {
+        return Err("The batches need to be 1-indexed.".into());
+    }
+    // R is 1-indexed
+    let batch: Vec<usize> = batch.iter().map(|&b| (b - 1) as usize).collect();
+    let ref_batch = ref_batch.map(|r| (r - 1) as usize);
+
+    let params = CombatParams {
+        par_prior,
+        mean_only,
+        ref_batch,
+    };
+    let res = combat(dat, &batch, covariates, &params)?;
+
+    Ok(list!(
+        corrected = faer_to_r_matrix(res.corrected.as_ref()),
+        gamma_hat = faer_to_r_matrix(res.gamma_hat.as_ref()),
+        delta_hat = faer_to_r_matrix(res.delta_hat.as_ref()),
+        gamma_star = faer_to_r_matrix(res.gamma_star.as_ref()),
+        delta_star = faer_to_r_matrix(res.delta_star.as_ref()),
+        gamma_bar = res.gamma_bar,
+        t2 = res.t2,
+        a_prior = res.a_prior,
+        b_prior = res.b_prior,
+        mean_only = res.mean_only
+    ))
+}
+
+extendr_module! {
+    mod fun_dge;
+    fn rs_limma_ebayes;
+    fn rs_voom;
+    fn rs_calc_norm_factors;
+    fn rs_cpm;
+    fn rs_filter_by_expr;
+    fn rs_combat;
+}
@@ -3,11 +3,19 @@ use rayon::prelude::*;
 
 use crate::helpers_geom_elim::*;
 use crate::helpers_hypergeom::*;
+use crate::utils_multiple_testing::{p_adjust, parse_p_adjust_method};
 use crate::utils_r_rust::r_list_to_str_vec;
 use crate::utils_rust::flatten_vector;
 
 /// A type alias that can be returned by par_iter() functions.
-type GoElimLevelResultsIter = (Vec<String>, Vec<f64>, Vec<f64>, Vec<u64>, Vec<u64>);
+type GoElimLevelResultsIter = (
+    Vec<String>,
+    Vec<f64>,
+    Vec<f64>,
+    Vec<f64>,
+    Vec<u64>,
+    Vec<u64>,
+);
 
 /// Run a single hypergeometric test.
 ///
@@ -20,10 +28,14 @@ type GoElimLevelResultsIter = (Vec<String>, Vec<f64>, Vec<f64>, Vec<u64>, Vec<u6
 /// @param gene_sets A list of strings that represent the gene sets to test against.
 /// @param gene_universe A character vector representing the gene universe from
 /// which the target genes and gene sets are sampled from.
+/// @param adjust_method String. The multiple testing correction to apply. One
+/// of `c("BH", "bonferroni", "holm", "hochberg", "hommel", "BY",
+/// "storey_smoother", "storey_bootstrap")`.
 ///
 /// @return A list containing:
 ///  \itemize{
 ///   \item pvals - The p-values from the hypergeometric test
+///   \item fdr - The adjusted p-values
 ///   \item odds_ratios - The calculated odds ratios
 ///   \item overlap - The size of the overlap
 ///   \item gene_set_lengths - The length of the gene sets.
@@ -35,13 +47,18 @@ fn rs_hypergeom_test(
     target_genes: Vec<String>,
     gene_sets: List,
     gene_universe: Vec<String>,
+    adjust_method: &str,
 ) -> extendr_api::Result<List> {
     let gene_sets = r_list_to_str_vec(gene_sets)?;
+    let adjust_method = parse_p_adjust_method(adjust_method)
+        .ok_or_else(|| format!("Invalid multiple testing method: {}", adjust_method))?;
 
     let res: HypergeomResult = hypergeom_helper(&target_genes, &gene_sets, &gene_universe);
+    let fdr = p_adjust(&res.0, &adjust_method);
 
     Ok(list!(
         pvals = res.0,
+        fdr = fdr,
         odds_ratios = res.1,
         hits = res.2,
         gene_set_lengths = res.3
@@ -61,10 +78,14 @@ fn rs_hypergeom_test(
 /// against.
 /// @param gene_universe A character vector representing the gene universe from
 /// which the target genes and gene sets are sampled from.
+/// @param adjust_method String. The multiple testing correction to apply
+/// per target gene set. One of `c("BH", "bonferroni", "holm", "hochberg",
+/// "hommel", "BY", "storey_smoother", "storey_bootstrap")`.
 ///
 /// @return A list containing:
 ///  \itemize{
 ///   \item pvals - The p-values from the hypergeometric test
+///   \item fdr - The adjusted p-values (per target gene set)
 ///   \item odds ratios - The calculated odds ratios
 ///   \item overlap - The size of the overlap
 ///   \item gene_set_lengths - The length of the gene sets.
@@ -76,9 +97,12 @@ fn rs_hypergeom_test_list(
     target_genes_list: List,
     gene_sets: List,
     gene_universe: Vec<String>,
+    adjust_method: &str,
 ) -> extendr_api::Result<List> {
     let gene_sets = r_list_to_str_vec(gene_sets)?;
     let target_genes_list = r_list_to_str_vec(target_genes_list)?;
+    let adjust_method = parse_p_adjust_method(adjust_method)
+        .ok_or_else(|| format!("Invalid multiple testing method: {}", adjust_method))?;
 
     let res: Vec<HypergeomResult> = target_genes_list
         .par_iter()
@@ -89,11 +113,13 @@ fn rs_hypergeom_test_list(
         .collect();
 
     let mut pvals = Vec::with_capacity(res.len());
+    let mut fdrs = Vec::with_capacity(res.len());
     let mut odds_ratios = Vec::with_capacity(res.len());
     let mut hits = Vec::with_capacity(res.len());
     let mut gene_set_lengths = Vec::with_capacity(res.len());
 
     for (pval, odds_ratio, hit, gene_set_length) in res {
+        fdrs.push(p_adjust(&pval, &adjust_method));
         pvals.push(pval);
         odds_ratios.push(odds_ratio);
         hits.push(hit);
@@ -101,12 +127,14 @@ fn rs_hypergeom_test_list(
     }
 
     let pvals: Vec<_> = flatten_vector(pvals);
+    let fdrs: Vec<_> = flatten_vector(fdrs);
     let odds_ratios: Vec<_> = flatten_vector(odds_ratios);
     let hits: Vec<_> = flatten_vector(hits);
     let gene_set_lengths: Vec<_> = flatten_vector(gene_set_lengths);
 
     Ok(list!(
         pvals = pvals,
+        fdr = fdrs,
         odds_ratios = odds_ratios,
         hits = hits,
         gene_set_lengths = gene_set_lengths
@@ -132,6 +160,9 @@ fn rs_hypergeom_test_list(
 /// tested.
 /// @param elim_threshold p-value below which the elimination procedure shall be
 /// applied to the ancestors.
+/// @param adjust_method String. The multiple testing correction to apply. One
+/// of `c("BH", "bonferroni", "holm", "hochberg", "hommel", "BY",
+/// "storey_smoother", "storey_bootstrap")`.
 /// @param debug boolean that will provide additional console information for
 /// debugging purposes.
 ///
@@ -139,6 +170,7 @@ fn rs_hypergeom_test_list(
 ///  \itemize{
 ///   \item go_ids - The gene ontology identifier.
 ///   \item pvals - The calculated odds ratios.
+///   \item fdr - The adjusted p-values.
 ///   \item odds_ratios - The calculated odds ratios.
 ///   \item overlap - The size of the overlap.
 ///   \item gene_set_lengths - The length of the gene sets.
@@ -146,6 +178,7 @@ fn rs_hypergeom_test_list(
 ///
 /// @export
 #[extendr]
+#[allow(clippy::too_many_arguments)]
 fn rs_gse_geom_elim(
     target_genes: Vec<String>,
     levels: Vec<String>,
@@ -153,8 +186,11 @@ fn rs_gse_geom_elim(
     gene_universe_length: u64,
     min_genes: usize,
     elim_threshold: f64,
+    adjust_method: &str,
     debug: bool,
 ) -> extendr_api::Result<List> {
+    let adjust_method = parse_p_adjust_method(adjust_method)
+        .ok_or_else(|| format!("Invalid multiple testing method: {}", adjust_method))?;
     let (go_to_gene, ancestors_map, levels_map) = prepare_go_data(go_obj)?;
 
     let mut go_obj = GeneOntology {
@@ -188,6 +224,7 @@ fn rs_gse_geom_elim(
 
     let go_ids: Vec<_> = flatten_vector(go_ids);
     let pvals: Vec<_> = flatten_vector(pvals);
+    let fdr = p_adjust(&pvals, &adjust_method);
     let odds_ratios: Vec<_> = flatten_vector(odds_ratios);
     let hits: Vec<_> = flatten_vector(hits);
     let gene_set_lengths: Vec<_> = flatten_vector(gene_set_lengths);
@@ -195,6 +232,7 @@ fn rs_gse_geom_elim(
     Ok(list!(
         go_ids = go_ids,
         pvals = pvals,
+        fdr = fdr,
         odds_ratios = odds_ratios,
         hits = hits,
         gene_set_lengths = gene_set_lengths
@@ -222,6 +260,9 @@ fn rs_gse_geom_elim(
 /// tested.
 /// @param elim_threshold p-value below which the elimination procedure shall
 /// be applied to the ancestors.
+/// @param adjust_method String. The multiple testing correction to apply
+/// per target gene set. One of `c("BH", "bonferroni", "holm", "hochberg",
+/// "hommel", "BY", "storey_smoother", "storey_bootstrap")`.
 /// @param debug boolean that will provide additional console information for
 /// debugging purposes.
 ///
@@ -229,6 +270,7 @@ fn rs_gse_geom_elim(
 ///  \itemize{
 ///   \item go_ids - The gene ontology identifier.
 ///   \item pvals - The calculated odds ratios.
+///   \item fdr - The adjusted p-values.
 ///   \item odds_ratios - The calculated odds ratios.
 ///   \item overlap - The size of the overlap.
 ///   \item gene_set_lengths - The length of the gene sets.
@@ -239,6 +281,7 @@ fn rs_gse_geom_elim(
 ///
 /// @export
 #[extendr]
+#[allow(clippy::too_many_arguments)]
 fn rs_gse_geom_elim_list(
     target_genes_list: List,
     levels: Vec<String>,
@@ -246,8 +289,11 @@ fn rs_gse_geom_elim_list(
     gene_universe_length: u64,
     min_genes: usize,
     elim_threshold: f64,
+    adjust_method: &str,
     debug: bool,
 ) -> extendr_api::Result<List> {
+    let adjust_method = parse_p_adjust_method(adjust_method)
+        .ok_or_else(|| format!("Invalid multiple testing method: {}", adjust_method))?;
     // Prepare various variables
     let target_genes_list = r_list_to_str_vec(target_genes_list)?;
 
@@ -292,25 +338,28 @@ fn rs_gse_geom_elim_list(
             // Flatten the vectors
             let go_ids: Vec<_> = flatten_vector(go_ids);
             let pvals: Vec<_> = flatten_vector(pvals);
+            let fdr = p_adjust(&pvals, &adjust_method);
             let odds_ratios: Vec<_> = flatten_vector(odds_ratios);
             let hits: Vec<_> = flatten_vector(hits);
             let gene_set_lengths: Vec<_> = flatten_vector(gene_set_lengths);
 
-            (go_ids, pvals, odds_ratios, hits, gene_set_lengths)
+            (go_ids, pvals, fdr, odds_ratios, hits, gene_set_lengths)
         })
         .collect();
 
     let mut go_ids_final: Vec<Vec<_>> = Vec::with_capacity(res.len());
     let mut pvals_final = Vec::with_capacity(res.len());
+    let mut fdr_final = Vec::with_capacity(res.len());
     let mut odds_ratios_final = Vec::with_capacity(res.len());
     let mut hits_final = Vec::with_capacity(res.len());
     let mut gene_set_lengths_final = Vec::with_capacity(res.len());
     let mut no_tests = Vec::with_capacity(res.len());
 
-    for (go_ids, pval, odds_ratio, hit, gene_set_length) in res {
+    for (go_ids, pval, fdr, odds_ratio, hit, gene_set_length) in res {
         no_tests.push(go_ids.len());
         go_ids_final.push(go_ids);
         pvals_final.push(pval);
+        fdr_final.push(fdr);
         odds_ratios_final.push(odds_ratio);
         hits_final.push(hit);
         gene_set_lengths_final.push(gene_set_length);
@@ -318,6 +367,7 @@ fn rs_gse_geom_elim_list(
 
     let go_ids_final: Vec<_> = flatten_vector(go_ids_final);
     let pvals_final: Vec<_> = flatten_vector(pvals_final);
+    let fdr_final: Vec<_> = flatten_vector(fdr_final);
     let odds_ratios_final: Vec<_> = flatten_vector(odds_ratios_final);
     let hits_final: Vec<_> = flatten_vector(hits_final);
     let gene_set_lengths_final: Vec<_> = flatten_vector(gene_set_lengths_final);
@@ -325,6 +375,7 @@ fn rs_gse_geom_elim_list(
     Ok(list!(
         go_ids = go_ids_final,
         pvals = pvals_final,
+        fdr = fdr_final,
         odds_ratios = odds_ratios_final,
         hits = hits_final,
         gene_set_lengths = gene_set_lengths_final,
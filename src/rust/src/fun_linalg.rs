@@ -1,6 +1,9 @@
 use extendr_api::prelude::*;
 
+use crate::helpers_cor_blocked::*;
 use crate::helpers_linalg::*;
+use crate::helpers_mi::*;
+use crate::helpers_pcor::*;
 use crate::utils_r_rust::{faer_to_r_matrix, r_matrix_to_faer};
 use crate::utils_rust::{nested_vector_to_faer_mat, upper_triangle_indices};
 
@@ -30,19 +33,29 @@ fn rs_covariance(x: RMatrix<f64>) -> extendr_api::RArray<f64, [usize; 2]> {
 /// functions with type checks are provided in the package.
 ///
 /// @param x R matrix with doubles.
-/// @param spearman Shall the Spearman correlation be calculated instead of
-/// Pearson.
+/// @param method String. One of
+/// `c("pearson", "spearman", "bicor", "kendall")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Set to 1 for the standard biweight midcorrelation (the default if
+/// `NULL`).
 ///
 /// @returns The correlation matrix.
 ///
 /// @export
 #[extendr]
-fn rs_cor(x: RMatrix<f64>, spearman: bool) -> extendr_api::RArray<f64, [usize; 2]> {
+fn rs_cor(
+    x: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+) -> extendr_api::Result<extendr_api::RArray<f64, [usize; 2]>> {
+    let method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
     let mat = r_matrix_to_faer(&x);
 
-    let cor = column_correlation(mat, spearman);
+    let cor = column_correlation(mat, &method);
 
-    faer_to_r_matrix(cor.as_ref())
+    Ok(faer_to_r_matrix(cor.as_ref()))
 }
 
 /// Calculate the column wise correlations.
@@ -53,8 +66,12 @@ fn rs_cor(x: RMatrix<f64>, spearman: bool) -> extendr_api::RArray<f64, [usize; 2
 /// the package.
 ///
 /// @param x R matrix with doubles.
-/// @param spearman Shall the Spearman correlation be calculated instead of
-/// Pearson.
+/// @param method String. One of
+/// `c("pearson", "spearman", "bicor", "kendall")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Set to 1 for the standard biweight midcorrelation (the default if
+/// `NULL`).
 /// @param shift Shall a shift be applied to the matrix. 0 = the diagonal will
 /// be included. 1 = the diagonal will not be included.
 ///
@@ -63,10 +80,17 @@ fn rs_cor(x: RMatrix<f64>, spearman: bool) -> extendr_api::RArray<f64, [usize; 2
 ///
 /// @export
 #[extendr]
-fn rs_cor_upper_triangle(x: RMatrix<f64>, spearman: bool, shift: usize) -> Vec<f64> {
+fn rs_cor_upper_triangle(
+    x: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+    shift: usize,
+) -> extendr_api::Result<Vec<f64>> {
+    let method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
     // Calculate the correlations
     let mat = r_matrix_to_faer(&x);
-    let cor = column_correlation(mat, spearman);
+    let cor = column_correlation(mat, &method);
     let upper_triangle_indices = upper_triangle_indices(mat.ncols(), shift);
     let mut cor_flat = Vec::new();
     for (&r, &c) in upper_triangle_indices
@@ -77,7 +101,378 @@ fn rs_cor_upper_triangle(x: RMatrix<f64>, spearman: bool, shift: usize) -> Vec<f
         cor_flat.push(*cor.get(r, c));
     }
 
-    cor_flat
+    Ok(cor_flat)
+}
+
+/// Calculate the column wise correlations on pairwise-complete observations.
+///
+/// @description Calculates the correlations of the columns using for every
+/// pair only the rows in which both values are finite (NA, NaN and Inf are
+/// ignored). Returns the upper triangle together with the number of
+/// observations, p-values and confidence intervals for every pair.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Can contain NAs.
+/// @param method String. One of
+/// `c("pearson", "spearman", "bicor", "kendall")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Set to 1 for the standard biweight midcorrelation (the default if
+/// `NULL`).
+/// @param shift Shall a shift be applied to the matrix. 0 = the diagonal will
+/// be included. 1 = the diagonal will not be included.
+/// @param conf_level Numeric. Confidence level of the Fisher z confidence
+/// intervals.
+///
+/// @return A list containing (all in the upper triangle layout):
+///  \itemize{
+///   \item cor - The correlation coefficients. NA if one of the columns is
+///   constant on the complete observations.
+///   \item n - The number of complete observations per pair.
+///   \item p_val - The p-values based on the Student t-test of the
+///   coefficients with n - 2 degrees of freedom. For Kendall, the normal
+///   approximation of tau-b with the tie-corrected variance is used.
+///   \item ci_lower - The lower bound of the Fisher z confidence interval.
+///   \item ci_upper - The upper bound of the Fisher z confidence interval.
+///   The variance of the transformed coefficients is 1 / (n - 3) for Pearson
+///   and bicor, 1.06 / (n - 3) for Spearman and 0.437 / (n - 4) for Kendall.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_cor_pairwise_complete(
+    x: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+    shift: usize,
+    conf_level: f64,
+) -> extendr_api::Result<List> {
+    let method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
+    let mat = r_matrix_to_faer(&x);
+
+    let res = pairwise_complete_correlation(mat, &method, shift, conf_level);
+
+    Ok(list!(
+        cor = res.cor,
+        n = res.n.iter().map(|&n| n as i32).collect::<Vec<i32>>(),
+        p_val = res.p_vals,
+        ci_lower = res.ci_lower,
+        ci_upper = res.ci_upper
+    ))
+}
+
+/// Calculate sparse correlation edges via a blocked correlation engine.
+///
+/// @description Calculates the correlations of the columns tile by tile
+/// without materialising the full correlation matrix and only keeps the
+/// pairs passing the thresholds as a sparse edge list. Optionally, the full
+/// upper triangle can be written to a memory-mapped file.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles.
+/// @param method String. One of `c("pearson", "spearman", "bicor")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Set to 1 for the standard biweight midcorrelation (the default if
+/// `NULL`).
+/// @param block_size Integer. Number of columns per tile.
+/// @param min_cor Numeric. Minimum (absolute) correlation for a pair to be
+/// kept.
+/// @param top_k Optional integer. If provided, only the top_k partners per
+/// column are kept.
+/// @param absolute Boolean. Shall the absolute correlation be used for the
+/// thresholds.
+/// @param spill_path Optional string. Path to the file to which the upper
+/// triangle (without diagonal, row-major, as doubles) is written.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item from - The (1-indexed) first column of the pair.
+///   \item to - The (1-indexed) second column of the pair.
+///   \item cor - The correlation coefficient.
+/// }
+///
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn rs_cor_blocked(
+    x: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+    block_size: usize,
+    min_cor: f64,
+    top_k: Option<usize>,
+    absolute: bool,
+    spill_path: Option<String>,
+) -> extendr_api::Result<List> {
+    let method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
+    let mat = r_matrix_to_faer(&x);
+    let params = BlockedCorParams {
+        block_size,
+        min_cor,
+        top_k,
+        absolute,
+    };
+
+    let edges = blocked_correlation(mat, &method, &params, spill_path.as_deref())?;
+
+    Ok(list!(
+        from = edges
+            .from
+            .iter()
+            .map(|&i| i as i32 + 1)
+            .collect::<Vec<i32>>(),
+        to = edges.to.iter().map(|&i| i as i32 + 1).collect::<Vec<i32>>(),
+        cor = edges.cor
+    ))
+}
+
+/// Graphical lasso with model selection
+///
+/// @description Fits the graphical lasso (block coordinate descent) on the
+/// correlation matrix of the columns over a log-spaced lambda path (with warm
+/// starts) and selects the model either via the extended BIC or via StARS.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param n_lambda Integer. Number of lambdas on the path.
+/// @param lambda_min_ratio Numeric. Smallest lambda as ratio of the largest
+/// lambda (that yields the empty graph).
+/// @param selection String. One of `c("ebic", "stars")`.
+/// @param ebic_gamma Numeric. The gamma parameter of the extended BIC.
+/// @param stars_beta Numeric. The instability threshold for StARS.
+/// @param stars_subsamples Integer. Number of subsamples for StARS.
+/// @param seed Integer. Random seed for the subsampling.
+/// @param max_iter Integer. Maximum number of iterations.
+/// @param tol Numeric. Convergence tolerance.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item lambdas - The lambda path.
+///   \item n_edges - The number of edges per lambda.
+///   \item criterion - The EBIC or (monotonised) StARS instability per
+///   lambda.
+///   \item selected - The (1-indexed) position of the selected lambda.
+///   \item from - The (1-indexed) first feature of the edges of the selected
+///   model.
+///   \item to - The (1-indexed) second feature of the edges.
+///   \item pcor - The partial correlations of the edges.
+///   \item precision - The precision matrix entries of the edges.
+/// }
+///
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn rs_glasso(
+    x: RMatrix<f64>,
+    n_lambda: usize,
+    lambda_min_ratio: f64,
+    selection: String,
+    ebic_gamma: f64,
+    stars_beta: f64,
+    stars_subsamples: usize,
+    seed: usize,
+    max_iter: usize,
+    tol: f64,
+) -> extendr_api::Result<List> {
+    let selection = parse_glasso_selection(&selection)
+        .ok_or_else(|| format!("Invalid model selection: {}", selection))?;
+    let mat = r_matrix_to_faer(&x);
+    let params = GlassoParams {
+        n_lambda,
+        lambda_min_ratio,
+        ebic_gamma,
+        stars_beta,
+        stars_subsamples,
+        max_iter,
+        tol,
+    };
+
+    let res = glasso_select(mat, &selection, &params, seed as u64);
+    let edges = precision_to_edges(res.theta.as_ref(), 0.0);
+
+    Ok(list!(
+        lambdas = res.lambdas,
+        n_edges = res.n_edges.iter().map(|&n| n as i32).collect::<Vec<i32>>(),
+        criterion = res.criterion,
+        selected = res.selected as i32 + 1,
+        from = edges
+            .from
+            .iter()
+            .map(|&i| i as i32 + 1)
+            .collect::<Vec<i32>>(),
+        to = edges.to.iter().map(|&i| i as i32 + 1).collect::<Vec<i32>>(),
+        pcor = edges.pcor,
+        precision = edges.precision
+    ))
+}
+
+/// Shrinkage partial correlations
+///
+/// @description Calculates the partial correlations between the columns via
+/// the shrinkage approach of Schaefer and Strimmer, equivalent to
+/// `corpcor::pcor.shrink()`, and returns the pairs with an absolute partial
+/// correlation above the threshold.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param min_pcor Numeric. Minimum absolute partial correlation for a pair
+/// to be returned.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item lambda - The estimated shrinkage intensity.
+///   \item from - The (1-indexed) first feature of the pair.
+///   \item to - The (1-indexed) second feature of the pair.
+///   \item pcor - The partial correlations.
+///   \item precision - The precision matrix entries (of the standardised
+///   data).
+/// }
+///
+/// @export
+#[extendr]
+fn rs_pcor_shrink(x: RMatrix<f64>, min_pcor: f64) -> List {
+    let mat = r_matrix_to_faer(&x);
+
+    let (_, omega, lambda) = pcor_shrink(mat);
+    let edges = precision_to_edges(omega.as_ref(), min_pcor);
+
+    list!(
+        lambda = lambda,
+        from = edges
+            .from
+            .iter()
+            .map(|&i| i as i32 + 1)
+            .collect::<Vec<i32>>(),
+        to = edges.to.iter().map(|&i| i as i32 + 1).collect::<Vec<i32>>(),
+        pcor = edges.pcor,
+        precision = edges.precision
+    )
+}
+
+/// Calculate the column wise mutual information.
+///
+/// @description Calculates the mutual information (in nats) between the
+/// columns based on the B-spline estimator of Daub, et al. Every sample is
+/// assigned to `spline_order` neighbouring bins with the weights of the
+/// B-spline basis functions. The pairs are processed in parallel. This
+/// function will return the upper triangle. WARNING! Incorrect use can cause
+/// kernel crashes. Wrapper around the Rust functions with type checks are
+/// provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param n_bins Integer. Number of bins.
+/// @param spline_order Integer. Order of the B-spline functions. 1 corresponds
+/// to simple binning. Needs to be between 1 and n_bins.
+/// @param shift Shall a shift be applied to the matrix. 0 = the diagonal will
+/// be included (containing the entropies). 1 = the diagonal will not be
+/// included.
+///
+/// @returns The upper triangle of the mutual information matrix iterating
+/// through the rows.
+///
+/// @export
+#[extendr]
+fn rs_mutual_information(
+    x: RMatrix<f64>,
+    n_bins: usize,
+    spline_order: usize,
+    shift: usize,
+) -> extendr_api::Result<Vec<f64>> {
+    if spline_order < 1 || spline_order > n_bins {
+        return Err("The spline order needs to be between 1 and n_bins.".into());
+    }
+    let mat = r_matrix_to_faer(&x);
+    let mi = mutual_information_matrix(mat, n_bins, spline_order);
+    let (rows, cols) = upper_triangle_indices(mat.ncols(), shift);
+
+    Ok(rows
+        .iter()
+        .zip(cols.iter())
+        .map(|(&r, &c)| mi[(r, c)])
+        .collect())
+}
+
+/// Mutual information networks
+///
+/// @description Calculates the B-spline mutual information between the
+/// columns and infers a network from it. `"mi"` returns the raw mutual
+/// information, `"clr"` applies the context likelihood of relatedness
+/// background correction (z-scores of every value against the mutual
+/// information of both features with all other features) and `"aracne"`
+/// removes the weakest edge of every triplet based on the data processing
+/// inequality. Pairs with a weight above `min_weight` are returned as sparse
+/// edges.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param n_bins Integer. Number of bins.
+/// @param spline_order Integer. Order of the B-spline functions. Needs to be
+/// between 1 and n_bins.
+/// @param method String. One of `c("mi", "clr", "aracne")`.
+/// @param eps Numeric. Tolerance of the data processing inequality. Only
+/// relevant for ARACNE.
+/// @param min_weight Numeric. Only edges with a weight above this value are
+/// returned.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item from - The (1-indexed) first feature of the pair.
+///   \item to - The (1-indexed) second feature of the pair.
+///   \item weight - The edge weight (the CLR score for CLR, otherwise the
+///   mutual information).
+///   \item mi - The mutual information of the pair.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_mi_network(
+    x: RMatrix<f64>,
+    n_bins: usize,
+    spline_order: usize,
+    method: String,
+    eps: f64,
+    min_weight: f64,
+) -> extendr_api::Result<List> {
+    let network_method = parse_mi_network_method(&method)
+        .ok_or_else(|| format!("Invalid mutual information network method: {}", method))?;
+    if spline_order < 1 || spline_order > n_bins {
+        return Err("The spline order needs to be between 1 and n_bins.".into());
+    }
+    let mat = r_matrix_to_faer(&x);
+
+    let mi = mutual_information_matrix(mat, n_bins, spline_order);
+    let weights = match network_method {
+        MiNetworkMethod::Mi => {
+            let mut weights = mi.clone();
+            for i in 0..weights.ncols() {
+                weights[(i, i)] = 0.0;
+            }
+            weights
+        }
+        MiNetworkMethod::Clr => clr(mi.as_ref()),
+        MiNetworkMethod::Aracne => aracne(mi.as_ref(), eps),
+    };
+    let (from, to, weight) = upper_triangle_edges(weights.as_ref(), min_weight);
+    let edge_mi: Vec<f64> = from
+        .iter()
+        .zip(to.iter())
+        .map(|(&i, &j)| mi[(i, j)])
+        .collect();
+
+    Ok(list!(
+        from = from.iter().map(|&i| i as i32 + 1).collect::<Vec<i32>>(),
+        to = to.iter().map(|&i| i as i32 + 1).collect::<Vec<i32>>(),
+        weight = weight,
+        mi = edge_mi
+    ))
 }
 
 /// Calculate the column wise differential correlation between two sets of data.
@@ -91,8 +486,12 @@ fn rs_cor_upper_triangle(x: RMatrix<f64>, spearman: bool, shift: usize) -> Vec<f
 ///
 /// @param x_a R matrix a to be used for the differential correlation analysis.
 /// @param x_b R matrix a to be used for the differential correlation analysis.
-/// @param spearman Shall the Spearman correlation be calculated instead of
-/// Pearson.
+/// @param method String. One of
+/// `c("pearson", "spearman", "bicor", "kendall")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Set to 1 for the standard biweight midcorrelation (the default if
+/// `NULL`).
 ///
 /// @return A list containing:
 ///  \itemize{
@@ -107,29 +506,36 @@ fn rs_cor_upper_triangle(x: RMatrix<f64>, spearman: bool, shift: usize) -> Vec<f
 ///
 /// @export
 #[extendr]
-fn rs_differential_cor(x_a: RMatrix<f64>, x_b: RMatrix<f64>, spearman: bool) -> List {
+fn rs_differential_cor(
+    x_a: RMatrix<f64>,
+    x_b: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+) -> extendr_api::Result<List> {
     assert!(
         x_a.ncols() == x_b.ncols(),
         "Input matrices must have the same number of columns. Found {} columns in first matrix and {} in second.",
         x_a.ncols(),
         x_b.ncols(),
       );
+    let method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
     let n_sample_a = x_a.nrows();
     let n_sample_b = x_b.nrows();
     let mat_a = r_matrix_to_faer(&x_a);
     let mat_b = r_matrix_to_faer(&x_b);
 
-    let cor_a = column_correlation(mat_a, spearman);
-    let cor_b = column_correlation(mat_b, spearman);
+    let cor_a = column_correlation(mat_a, &method);
+    let cor_b = column_correlation(mat_b, &method);
 
-    let diff_cor = calculate_diff_correlation(&cor_a, &cor_b, n_sample_a, n_sample_b, spearman);
+    let diff_cor = calculate_diff_correlation(&cor_a, &cor_b, n_sample_a, n_sample_b, &method);
 
-    list!(
+    Ok(list!(
         r_a = diff_cor.r_a,
         r_b = diff_cor.r_b,
         z_score = diff_cor.z_score,
         p_val = diff_cor.p_vals
-    )
+    ))
 }
 
 /// Calculate the contrastive PCA
@@ -310,6 +716,65 @@ fn rs_rbf_iterate_epsilons(
     Ok(faer_to_r_matrix(band_width_data.as_ref()))
 }
 
+/// Quantile normalisation
+///
+/// @description Quantile normalises the columns of a matrix, i.e., every
+/// column gets the same distribution (the mean of the sorted columns). Ties
+/// are handled like in `preprocessCore::normalize.quantiles()`. The columns
+/// are processed in parallel. WARNING! Incorrect use can cause kernel crashes.
+/// Wrapper around the Rust functions with type checks are provided in the
+/// package.
+///
+/// @param x Numeric matrix. Rows = features, columns = samples. May not
+/// contain NAs and needs at least one row and one column.
+///
+/// @returns The quantile normalised matrix.
+///
+/// @export
+#[extendr]
+fn rs_quantile_normalise(
+    x: RMatrix<f64>,
+) -> extendr_api::Result<extendr_api::RArray<f64, [usize; 2]>> {
+    let mat = r_matrix_to_faer(&x);
+    if mat.nrows() == 0 || mat.ncols() == 0 {
+        return Err("The matrix needs at least one row and one column.".into());
+    }
+    let res = quantile_normalise(mat);
+
+    Ok(faer_to_r_matrix(res.as_ref()))
+}
+
+/// Rank-based inverse normal transformation
+///
+/// @description Transforms the columns of a matrix to normally distributed
+/// values based on their ranks via
+/// `qnorm((rank - offset) / (n - 2 * offset + 1))`. Ties receive the average
+/// rank, NAs are kept. The columns are processed in parallel. WARNING!
+/// Incorrect use can cause kernel crashes. Wrapper around the Rust functions
+/// with type checks are provided in the package.
+///
+/// @param x Numeric matrix. Rows = samples, columns = features. Needs at least
+/// one row and one column.
+/// @param offset Float. The offset of the ranks. `3/8` corresponds to Blom's
+/// and `0.5` to the Rankit transformation.
+///
+/// @returns The transformed matrix.
+///
+/// @export
+#[extendr]
+fn rs_inverse_normal_transform(
+    x: RMatrix<f64>,
+    offset: f64,
+) -> extendr_api::Result<extendr_api::RArray<f64, [usize; 2]>> {
+    let mat = r_matrix_to_faer(&x);
+    if mat.nrows() == 0 || mat.ncols() == 0 {
+        return Err("The matrix needs at least one row and one column.".into());
+    }
+    let res = inverse_normal_transform(mat, offset);
+
+    Ok(faer_to_r_matrix(res.as_ref()))
+}
+
 extendr_module! {
   mod fun_linalg;
   fn rs_covariance;
@@ -317,7 +782,15 @@ extendr_module! {
   fn rs_prcomp;
   fn rs_random_svd;
   fn rs_cor_upper_triangle;
+  fn rs_cor_pairwise_complete;
+  fn rs_cor_blocked;
+  fn rs_glasso;
+  fn rs_pcor_shrink;
+  fn rs_mutual_information;
+  fn rs_mi_network;
   fn rs_rbf_iterate_epsilons;
   fn rs_differential_cor;
   fn rs_contrastive_pca;
+  fn rs_quantile_normalise;
+  fn rs_inverse_normal_transform;
 }
@@ -1,9 +1,16 @@
 use extendr_api::prelude::*;
+use faer::Mat;
 use rayon::prelude::*;
+use std::collections::HashSet;
 
 use crate::helpers_rbh::*;
-use crate::utils_r_rust::{r_nested_list_to_btree_nest, NestedBtreeMap};
+use crate::utils_multiple_testing::calc_fdr;
+use crate::utils_r_rust::{
+    btree_weight_nest_to_sets, r_named_matrix_data, r_nested_list_to_btree_nest,
+    r_nested_list_to_btree_weight_nest, NamedMatrix, NestedBtreeMap, NestedBtreeWeightMap,
+};
 use crate::utils_rust::flatten_vector;
+use crate::utils_stats::parse_weighted_sim_type;
 
 /// Structure to store the RBH results.
 #[derive(Clone, Debug)]
@@ -13,23 +20,59 @@ pub struct RbhResult {
     pub origin_modules: Vec<String>,
     pub target_modules: Vec<String>,
     pub similarities: Vec<f64>,
+    pub pvals: Vec<f64>,
+    pub pair_idx: Vec<usize>,
+    pub all_pvals: Vec<f64>,
+}
+
+/// Structure to store the RBH results of the loadings.
+#[derive(Clone, Debug)]
+pub struct LoadingRbhResult {
+    pub origin: String,
+    pub target: String,
+    pub shared_genes: usize,
+    pub origin_components: Vec<String>,
+    pub target_components: Vec<String>,
+    pub similarities: Vec<f64>,
+    pub correlations: Vec<f64>,
 }
 
 /// Generate reciprocal best hits based on set similarities
 ///
 /// @description This function takes a nested list that contains gene modules/
 /// sets derived from various methods and generate identifies reciprocal best
-/// hits between gene modules/sets across the different origins. WARNING!
-/// Incorrect use can cause kernel crashes. Wrapper around the Rust functions
-/// with type checks are provided in the package.
+/// hits between gene modules/sets across the different origins. Optionally,
+/// hypergeometric p-values of the overlaps can be calculated for every compared
+/// pair of gene modules; the Benjamini-Hochberg correction is applied over all
+/// compared pairs. If the gene modules carry gene weights (for example loadings
+/// or module membership), the modules can be matched by weighted similarities.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
 ///
 /// @param module_list A nested named list. The outer list should contain the
 /// origin of the gene modules, the inner list the names of the gene modules and
-/// the respective genes in them.
+/// the respective genes in them. If `weighted_similarity` is provided, the
+/// inner list needs to contain named numeric vectors with the genes as names
+/// and the gene weights as values.
 /// @param overlap_coefficient Shall the overlap coefficient instead of the
-/// Jaccard similarity be used.
+/// Jaccard similarity be used. Ignored if `weighted_similarity` is provided.
 /// @param min_similarity Minimum similarity that should exist between any two
-/// given gene modules to actually calculate RBH pairs.
+/// given gene modules to actually calculate RBH pairs. Ignored if `max_fdr` is
+/// provided.
+/// @param matching_type String. One of `c("rbh", "hungarian")`. `"rbh"` will
+/// only keep strict reciprocal best hits. `"hungarian"` will solve the optimal
+/// one-to-one (maximum weight) assignment between the gene modules of two
+/// origins. Similarities below `min_similarity` cannot be matched.
+/// @param universe_size Optional integer. Size of the gene universe. If
+/// provided, hypergeometric p-values for the overlaps will be calculated. Needs
+/// to be at least the number of unique genes in the modules.
+/// @param max_fdr Optional float. If provided (requires `universe_size`), the
+/// RBH pairs will be filtered on the BH-adjusted p-values instead of
+/// `min_similarity`.
+/// @param weighted_similarity Optional string. One of
+/// `c("ruzicka", "overlap", "cosine")`. If provided, the weighted Jaccard
+/// (Ruzicka), weighted overlap coefficient or cosine similarity on the absolute
+/// gene weights will be used.
 /// @param debug Boolean Boolean that activates print messages for debugging
 /// purposes.
 ///
@@ -43,16 +86,66 @@ pub struct RbhResult {
 ///   \item target_modules - Names of the gene modules from the target.
 ///   \item similarity - The similarities between the two respective gene
 ///   modules.
+///   \item pvals - The hypergeometric p-values of the overlaps. `NULL` if no
+///   `universe_size` was provided.
+///   \item fdr - The BH-adjusted p-values (over all compared module pairs).
+///   `NULL` if no `universe_size` was provided.
 /// }
 /// @export
 #[extendr]
+#[allow(clippy::too_many_arguments)]
 fn rs_rbh_sets(
     module_list: List,
     overlap_coefficient: bool,
     min_similarity: f64,
+    matching_type: &str,
+    universe_size: Option<usize>,
+    max_fdr: Option<f64>,
+    weighted_similarity: Option<String>,
     debug: bool,
 ) -> extendr_api::Result<List> {
-    let module_list: NestedBtreeMap = r_nested_list_to_btree_nest(module_list)?;
+    let matching_type = parse_matching_type(matching_type)
+        .ok_or_else(|| format!("Invalid matching type: {}", matching_type))?;
+    if max_fdr.is_some() && universe_size.is_none() {
+        return Err(Error::Other(
+            "Filtering on the FDR requires the universe_size".to_string(),
+        ));
+    }
+    let weighted_similarity = weighted_similarity
+        .map(|s| {
+            parse_weighted_sim_type(&s).ok_or_else(|| format!("Invalid weighted similarity: {}", s))
+        })
+        .transpose()?;
+    let (module_list, module_weights): (NestedBtreeMap, Option<NestedBtreeWeightMap>) =
+        if weighted_similarity.is_some() {
+            let module_weights = r_nested_list_to_btree_weight_nest(module_list)?;
+            (
+                btree_weight_nest_to_sets(&module_weights),
+                Some(module_weights),
+            )
+        } else {
+            (r_nested_list_to_btree_nest(module_list)?, None)
+        };
+    // Genes outside of the universe would yield invalid hypergeometric tests
+    if let Some(universe_size) = universe_size {
+        let all_genes: HashSet<&String> = module_list
+            .values()
+            .flat_map(|modules| modules.values().flatten())
+            .collect();
+        if all_genes.len() > universe_size {
+            return Err(Error::Other(format!(
+                "The modules contain {} genes, more than the universe_size of {}",
+                all_genes.len(),
+                universe_size
+            )));
+        }
+    }
+    // Significance-based filtering replaces the similarity threshold
+    let min_similarity = if max_fdr.is_some() {
+        0.0
+    } else {
+        min_similarity
+    };
     // Pull out all the keys
     let origins: Vec<String> = module_list.keys().cloned().collect();
 
@@ -83,32 +176,84 @@ fn rs_rbh_sets(
                 .map(|target| {
                     let target_module_data = module_list.get(target).unwrap();
 
-                    let rbh_res = calculate_rbh_set(
-                        origin_module_data,
-                        target_module_data,
-                        overlap_coefficient,
-                        min_similarity,
-                        debug,
-                    );
+                    let sim_mat = match (&module_weights, &weighted_similarity) {
+                        (Some(module_weights), Some(sim_type)) => weighted_similarity_matrix(
+                            module_weights.get(origin_module).unwrap(),
+                            module_weights.get(target).unwrap(),
+                            sim_type,
+                        ),
+                        _ => set_similarity_matrix(
+                            origin_module_data,
+                            target_module_data,
+                            overlap_coefficient,
+                        ),
+                    };
+
+                    let rbh_res = match matching_type {
+                        MatchingType::Rbh => calculate_rbh_set(
+                            origin_module_data,
+                            target_module_data,
+                            sim_mat.as_ref(),
+                            min_similarity,
+                            debug,
+                        ),
+                        MatchingType::Hungarian => calculate_hungarian_set(
+                            origin_module_data,
+                            target_module_data,
+                            sim_mat.as_ref(),
+                            min_similarity,
+                            debug,
+                        ),
+                    };
+
+                    let pval_mat = universe_size.map(|universe_size| {
+                        set_hypergeom_pval_matrix(
+                            origin_module_data,
+                            target_module_data,
+                            universe_size as u64,
+                        )
+                    });
+                    let names_origin: Vec<&String> = origin_module_data.keys().collect();
+                    let names_targets: Vec<&String> = target_module_data.keys().collect();
 
                     let mut origin_modules = Vec::new();
                     let mut target_modules = Vec::new();
                     let mut similarities = Vec::new();
+                    let mut pvals = Vec::new();
+                    let mut pair_idx = Vec::new();
 
                     for res in rbh_res {
-                        if res.sim > min_similarity {
+                        if res.sim >= min_similarity {
                             origin_modules.push(res.t1.to_string());
                             target_modules.push(res.t2.to_string());
                             similarities.push(res.sim);
+
+                            if let Some(pval_mat) = &pval_mat {
+                                let r = names_origin.iter().position(|x| *x == res.t1).unwrap();
+                                let c = names_targets.iter().position(|x| *x == res.t2).unwrap();
+                                pvals.push(pval_mat[(r, c)]);
+                                pair_idx.push(c + r * names_targets.len());
+                            }
                         }
                     }
 
+                    let all_pvals: Vec<f64> = match &pval_mat {
+                        Some(pval_mat) => pval_mat
+                            .row_iter()
+                            .flat_map(|row| row.iter().copied().collect::<Vec<f64>>())
+                            .collect(),
+                        None => Vec::new(),
+                    };
+
                     RbhResult {
                         origin: origin_module.to_string(),
                         target: target.to_string(),
                         origin_modules,
                         target_modules,
                         similarities,
+                        pvals,
+                        pair_idx,
+                        all_pvals,
                     }
                 })
                 .collect();
@@ -120,37 +265,369 @@ fn rs_rbh_sets(
     // Flatten and extract relevant data.
     let rbh_results_flatten: Vec<_> = flatten_vector(rbh_results);
 
+    // The multiple testing correction is done over all compared module pairs
+    let all_pvals: Vec<f64> = rbh_results_flatten
+        .iter()
+        .flat_map(|module| module.all_pvals.iter().copied())
+        .collect();
+    let all_fdr = calc_fdr(&all_pvals);
+
     let mut origin = Vec::new();
     let mut target = Vec::new();
     let mut comparisons = Vec::new();
     let mut origin_modules = Vec::new();
     let mut target_modules = Vec::new();
     let mut similarity = Vec::new();
+    let mut pvals = Vec::new();
+    let mut fdr = Vec::new();
+
+    let mut offset = 0;
 
     for module in rbh_results_flatten {
+        let mut comparisons_i = 0;
+
+        for i in 0..module.similarities.len() {
+            let fdr_i = module.pair_idx.get(i).map(|idx| all_fdr[offset + idx]);
+
+            if let (Some(max_fdr), Some(fdr_i)) = (max_fdr, fdr_i) {
+                if fdr_i > max_fdr {
+                    continue;
+                }
+            }
+
+            origin_modules.push(module.origin_modules[i].clone());
+            target_modules.push(module.target_modules[i].clone());
+            similarity.push(module.similarities[i]);
+            if let Some(fdr_i) = fdr_i {
+                pvals.push(module.pvals[i]);
+                fdr.push(fdr_i);
+            }
+            comparisons_i += 1;
+        }
+
+        offset += module.all_pvals.len();
+
         origin.push(module.origin);
         target.push(module.target);
-        origin_modules.push(module.origin_modules);
-        target_modules.push(module.target_modules);
-        similarity.push(module.similarities.clone());
-        comparisons.push(module.similarities.len());
+        comparisons.push(comparisons_i);
+    }
+
+    if universe_size.is_some() {
+        Ok(list!(
+            origin = origin,
+            target = target,
+            comparisons = comparisons,
+            origin_modules = origin_modules,
+            target_modules = target_modules,
+            similarity = similarity,
+            pvals = pvals,
+            fdr = fdr
+        ))
+    } else {
+        Ok(list!(
+            origin = origin,
+            target = target,
+            comparisons = comparisons,
+            origin_modules = origin_modules,
+            target_modules = target_modules,
+            similarity = similarity,
+            pvals = r!(NULL),
+            fdr = r!(NULL)
+        ))
+    }
+}
+
+/// Identify consensus modules from reciprocal best hits
+///
+/// @description This function takes the reciprocal best hits between gene
+/// modules from different origins (see `rs_rbh_sets()`) and identifies groups
+/// of modules that form cliques or near-cliques in the RBH graph spanning at
+/// least `min_origins` origins. For each group, the consensus gene set is
+/// returned with the number of origins (modules) that contain each gene.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param module_list A nested named list. The outer list should contain the
+/// origin of the gene modules, the inner list the names of the gene modules and
+/// the respective genes in them.
+/// @param origin String vector. The origin of the RBH edges.
+/// @param origin_modules String vector. The gene module names of the origin.
+/// @param target String vector. The target of the RBH edges.
+/// @param target_modules String vector. The gene module names of the target.
+/// @param similarity Numeric vector. The similarities of the RBH edges.
+/// @param min_origins Integer. Minimum number of origins a consensus group
+/// needs to span.
+/// @param min_density Float between 0 and 1. Each module added to a clique
+/// needs to have RBH edges to at least this fraction of the group members.
+/// 1 = only cliques will be returned.
+/// @param min_support Integer. Minimum number of origins that need to contain
+/// a gene for it to be part of the consensus gene set.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item member_group - Integer vector with the consensus group of the
+///   modules.
+///   \item member_origin - The origin of the modules in the consensus groups.
+///   \item member_module - The names of the modules in the consensus groups.
+///   \item group_similarity - The mean similarity of the RBH edges within
+///   the consensus groups.
+///   \item gene_group - Integer vector with the consensus group of the genes.
+///   \item genes - The genes of the consensus gene sets.
+///   \item gene_support - The number of origins containing the gene.
+/// }
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn rs_rbh_consensus_modules(
+    module_list: List,
+    origin: Vec<String>,
+    origin_modules: Vec<String>,
+    target: Vec<String>,
+    target_modules: Vec<String>,
+    similarity: Vec<f64>,
+    min_origins: usize,
+    min_density: f64,
+    min_support: usize,
+) -> extendr_api::Result<List> {
+    let module_list: NestedBtreeMap = r_nested_list_to_btree_nest(module_list)?;
+
+    let graph = RbhGraph::new(
+        &origin,
+        &origin_modules,
+        &target,
+        &target_modules,
+        &similarity,
+    );
+
+    let groups = graph.consensus_groups(min_origins, min_density);
+
+    let mut member_group = Vec::new();
+    let mut member_origin = Vec::new();
+    let mut member_module = Vec::new();
+    let mut group_similarity = Vec::new();
+    let mut gene_group = Vec::new();
+    let mut genes = Vec::new();
+    let mut gene_support = Vec::new();
+
+    for (i, (group, sim)) in groups.iter().enumerate() {
+        let mut modules: Vec<&HashSet<String>> = Vec::with_capacity(group.len());
+
+        for &node in group {
+            let (node_origin, node_module) = &graph.nodes[node];
+            let module = module_list
+                .get(node_origin)
+                .and_then(|x| x.get(node_module))
+                .ok_or_else(|| {
+                    format!(
+                        "Module {} of origin {} not found in the module_list",
+                        node_module, node_origin
+                    )
+                })?;
+            modules.push(module);
+
+            // R is 1-indexed
+            member_group.push(i + 1);
+            member_origin.push(node_origin.clone());
+            member_module.push(node_module.clone());
+        }
+
+        group_similarity.push(*sim);
+
+        let (group_genes, support) = consensus_gene_support(&modules, min_support);
+        gene_group.extend(std::iter::repeat_n(i + 1, group_genes.len()));
+        genes.extend(group_genes);
+        gene_support.extend(support);
     }
 
-    let origin_modules: Vec<_> = flatten_vector(origin_modules);
-    let target_modules: Vec<_> = flatten_vector(target_modules);
-    let similarity: Vec<_> = flatten_vector(similarity);
+    Ok(list!(
+        member_group = member_group,
+        member_origin = member_origin,
+        member_module = member_module,
+        group_similarity = group_similarity,
+        gene_group = gene_group,
+        genes = genes,
+        gene_support = gene_support
+    ))
+}
+
+/// Generate reciprocal best hits based on loading correlations
+///
+/// @description This function takes a named list of loading matrices (for
+/// example ICA source signals or PCA loadings) from different origins and
+/// identifies reciprocal best hits between the components across the
+/// different origins. The matrices are aligned on their shared genes and the
+/// absolute Pearson or Spearman correlations between the components are used
+/// as similarities. WARNING! Incorrect use can cause kernel crashes. Wrapper
+/// around the Rust functions with type checks are provided in the package.
+///
+/// @param loading_list A named list of numeric matrices with the genes as row
+/// names and the components as column names.
+/// @param spearman Boolean. Shall Spearman correlation be used instead of
+/// Pearson.
+/// @param min_similarity Minimum absolute correlation that should exist
+/// between any two given components to actually calculate RBH pairs.
+/// @param min_shared_genes Integer. Minimum number of shared genes between
+/// two origins to compare them. Needs to be at least 3.
+/// @param matching_type String. One of `c("rbh", "hungarian")`. `"rbh"` will
+/// only keep strict reciprocal best hits. `"hungarian"` will solve the optimal
+/// one-to-one (maximum weight) assignment between the components of two
+/// origins.
+/// @param debug Boolean Boolean that activates print messages for debugging
+/// purposes.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item origin - The name of the origin of the components.
+///   \item target - The name of the target of the components.
+///   \item comparisons - Integer vector indicating how many RBH hits were
+///   identified in this comparison
+///   \item shared_genes - Integer vector with the number of shared genes in
+///   this comparison.
+///   \item origin_components - Names of the components from the origin.
+///   \item target_components - Names of the components from the target.
+///   \item similarity - The absolute correlations between the two respective
+///   components.
+///   \item correlation - The signed correlations between the two respective
+///   components.
+/// }
+/// @export
+#[extendr]
+fn rs_rbh_loadings(
+    loading_list: List,
+    spearman: bool,
+    min_similarity: f64,
+    min_shared_genes: usize,
+    matching_type: &str,
+    debug: bool,
+) -> extendr_api::Result<List> {
+    let matching_type = parse_matching_type(matching_type)
+        .ok_or_else(|| format!("Invalid matching type: {}", matching_type))?;
+
+    if min_shared_genes < 3 {
+        return Err("min_shared_genes needs to be at least 3.".into());
+    }
+
+    let mut origins: Vec<String> = Vec::with_capacity(loading_list.len());
+    let mut loadings: Vec<NamedMatrix> = Vec::with_capacity(loading_list.len());
+
+    for (n, obj) in loading_list {
+        let loading_data = r_named_matrix_data(obj)
+            .map_err(|e| format!("Failed to convert loadings of '{}': {}", n, e))?;
+        origins.push(n.to_string());
+        loadings.push(loading_data);
+    }
+
+    let pairs: Vec<(usize, usize)> = (0..origins.len())
+        .flat_map(|i| (i + 1..origins.len()).map(move |j| (i, j)))
+        .collect();
+
+    let rbh_results: Vec<LoadingRbhResult> = pairs
+        .par_iter()
+        .map(|&(i, j)| {
+            let (genes_origin, comp_origin, mat_origin) = &loadings[i];
+            let (genes_target, comp_target, mat_target) = &loadings[j];
+
+            let (cor_mat, shared_genes) = loading_correlations(
+                genes_origin,
+                mat_origin.as_ref(),
+                genes_target,
+                mat_target.as_ref(),
+                spearman,
+            );
+
+            let mut res = LoadingRbhResult {
+                origin: origins[i].clone(),
+                target: origins[j].clone(),
+                shared_genes,
+                origin_components: Vec::new(),
+                target_components: Vec::new(),
+                similarities: Vec::new(),
+                correlations: Vec::new(),
+            };
+
+            if shared_genes < min_shared_genes {
+                if debug {
+                    println!(
+                        "Only {} shared genes between {} and {}. Skipping comparison.",
+                        shared_genes, res.origin, res.target
+                    )
+                }
+                return res;
+            }
+
+            let sim_mat = Mat::from_fn(cor_mat.nrows(), cor_mat.ncols(), |r, c| {
+                cor_mat[(r, c)].abs()
+            });
+            let names_origin: Vec<&String> = comp_origin.iter().collect();
+            let names_targets: Vec<&String> = comp_target.iter().collect();
+
+            let rbh_res = match matching_type {
+                MatchingType::Rbh => rbh_from_similarity(
+                    &names_origin,
+                    &names_targets,
+                    sim_mat.as_ref(),
+                    min_similarity,
+                    debug,
+                ),
+                MatchingType::Hungarian => hungarian_from_similarity(
+                    &names_origin,
+                    &names_targets,
+                    sim_mat.as_ref(),
+                    min_similarity,
+                    debug,
+                ),
+            };
+
+            for triplet in rbh_res {
+                if triplet.sim >= min_similarity {
+                    let r = comp_origin.iter().position(|x| x == triplet.t1).unwrap();
+                    let c = comp_target.iter().position(|x| x == triplet.t2).unwrap();
+                    res.origin_components.push(triplet.t1.to_string());
+                    res.target_components.push(triplet.t2.to_string());
+                    res.similarities.push(triplet.sim);
+                    res.correlations.push(cor_mat[(r, c)]);
+                }
+            }
+
+            res
+        })
+        .collect();
+
+    let mut origin = Vec::new();
+    let mut target = Vec::new();
+    let mut comparisons = Vec::new();
+    let mut shared_genes = Vec::new();
+    let mut origin_components = Vec::new();
+    let mut target_components = Vec::new();
+    let mut similarity = Vec::new();
+    let mut correlation = Vec::new();
+
+    for res in rbh_results {
+        origin.push(res.origin);
+        target.push(res.target);
+        comparisons.push(res.similarities.len());
+        shared_genes.push(res.shared_genes);
+        origin_components.extend(res.origin_components);
+        target_components.extend(res.target_components);
+        similarity.extend(res.similarities);
+        correlation.extend(res.correlations);
+    }
 
     Ok(list!(
         origin = origin,
         target = target,
         comparisons = comparisons,
-        origin_modules = origin_modules,
-        target_modules = target_modules,
-        similarity = similarity
+        shared_genes = shared_genes,
+        origin_components = origin_components,
+        target_components = target_components,
+        similarity = similarity,
+        correlation = correlation
     ))
 }
 
 extendr_module! {
     mod fun_rbh;
     fn rs_rbh_sets;
+    fn rs_rbh_loadings;
+    fn rs_rbh_consensus_modules;
 }
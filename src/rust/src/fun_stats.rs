@@ -4,11 +4,23 @@ use rayon::prelude::*;
 use std::collections::HashSet;
 // use std::sync::{Arc, Mutex};
 
-use crate::helpers_hypergeom::hypergeom_pval;
+use crate::helpers_auc::{auc_delong, auc_exact, delong_test};
 use crate::helpers_linalg::{col_means, col_sds};
-use crate::utils_r_rust::{r_list_to_str_vec, r_matrix_to_faer};
-use crate::utils_rust::{flatten_vector, string_vec_to_set};
-use crate::utils_stats::{hedge_g_effect, set_similarity, split_vector_randomly, EffectSizeRes};
+use crate::helpers_minhash::lsh_similar_sets;
+use crate::utils_hypergeom::{dhyper, phyper};
+use crate::utils_meta_analysis::{
+    combine_pvals, meta_analysis, parse_meta_analysis_method, parse_pval_combination, CombinedPval,
+    MetaAnalysisRes,
+};
+use crate::utils_multiple_testing::{
+    calc_fdr, calc_qvalues, p_adjust, parse_p_adjust_method, parse_pi0_method,
+};
+use crate::utils_r_rust::{r_list_to_str_vec, r_list_to_weight_maps, r_matrix_to_faer};
+use crate::utils_rust::{flatten_vector, intern_string_vecs, recycled_length, string_vec_to_set};
+use crate::utils_stats::{
+    hedge_g_effect, parse_weighted_sim_type, set_similarity, set_similarity_upper_triangle,
+    split_vector_randomly, weighted_set_similarity, EffectSizeRes,
+};
 
 // use std::collections::HashSet;
 // use crate::utils_r_rust::r_list_to_str_vec;
@@ -52,7 +64,9 @@ fn rs_fast_auc(pos_scores: Vec<f64>, neg_scores: Vec<f64>, iters: usize, seed: u
 /// @param size_pos The size of the hits represented in the score_vec.
 /// @param random_iters Number of random AUCs to generate.
 /// @param auc_iters Number of random iterations to approximate the AUCs.
-/// Recommended size: 10000L.
+/// Recommended size: 10000L. Ignored if `exact` is set to `TRUE`.
+/// @param exact Boolean. Shall the exact, rank-based AUC be calculated instead
+/// of the approximation.
 /// @param seed Seed.
 ///
 /// @return A vector of random AUCs based the score vector and size of the
@@ -65,6 +79,7 @@ fn rs_create_random_aucs(
     size_pos: usize,
     random_iters: usize,
     auc_iters: usize,
+    exact: bool,
     seed: u64,
 ) -> Vec<f64> {
     let iter_vec: Vec<usize> = (0..random_iters).collect();
@@ -74,13 +89,107 @@ fn rs_create_random_aucs(
         .map(|x| {
             let scores = split_vector_randomly(score_vec.clone(), size_pos, *x as u64 + seed);
 
-            rs_fast_auc(scores.0, scores.1, auc_iters, *x as u64 + 1 + seed)
+            if exact {
+                auc_exact(&scores.0, &scores.1)
+            } else {
+                rs_fast_auc(scores.0, scores.1, auc_iters, *x as u64 + 1 + seed)
+            }
         })
         .collect();
 
     random_aucs
 }
 
+/// Exact AUC calculation
+///
+/// @description This function calculates the exact AUC (Mann-Whitney U
+/// statistic) based on the ranks of the scores. Ties between hits and non-hits
+/// are counted as 0.5.
+///
+/// @param pos_scores The scores of your hits.
+/// @param neg_scores The scores of your non-hits.
+///
+/// @return The AUC.
+///
+/// @export
+#[extendr]
+fn rs_exact_auc(pos_scores: Vec<f64>, neg_scores: Vec<f64>) -> f64 {
+    auc_exact(&pos_scores, &neg_scores)
+}
+
+/// AUC with DeLong confidence intervals
+///
+/// @description This function calculates the exact AUC together with the
+/// DeLong variance and the respective confidence interval.
+///
+/// @param pos_scores The scores of your hits.
+/// @param neg_scores The scores of your non-hits.
+/// @param conf_level Float. The confidence level of the interval, e.g., 0.95.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item auc - The exact AUC.
+///   \item variance - The DeLong variance of the AUC.
+///   \item ci_lower - The lower bound of the confidence interval.
+///   \item ci_upper - The upper bound of the confidence interval.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_auc_delong(pos_scores: Vec<f64>, neg_scores: Vec<f64>, conf_level: f64) -> List {
+    let res = auc_delong(&pos_scores, &neg_scores, conf_level);
+
+    list!(
+        auc = res.auc,
+        variance = res.variance,
+        ci_lower = res.ci_lower,
+        ci_upper = res.ci_upper
+    )
+}
+
+/// DeLong test for two correlated AUCs
+///
+/// @description This function tests if two AUCs derived from two different
+/// scores on the same samples are different, based on the DeLong method. The
+/// hits and non-hits need to be in the same order for both scores.
+///
+/// @param pos_scores_1 The scores of your hits for the first score.
+/// @param neg_scores_1 The scores of your non-hits for the first score.
+/// @param pos_scores_2 The scores of your hits for the second score.
+/// @param neg_scores_2 The scores of your non-hits for the second score.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item auc_1 - The exact AUC of the first score.
+///   \item auc_2 - The exact AUC of the second score.
+///   \item z - The Z-score of the difference.
+///   \item pval - The two-sided p-value.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_delong_test(
+    pos_scores_1: Vec<f64>,
+    neg_scores_1: Vec<f64>,
+    pos_scores_2: Vec<f64>,
+    neg_scores_2: Vec<f64>,
+) -> extendr_api::Result<List> {
+    if pos_scores_1.len() != pos_scores_2.len() || neg_scores_1.len() != neg_scores_2.len() {
+        return Err(Error::Other(
+            "The two scores need to be measured on the same samples".to_string(),
+        ));
+    }
+
+    let res = delong_test(&pos_scores_1, &neg_scores_1, &pos_scores_2, &neg_scores_2);
+
+    Ok(list!(
+        auc_1 = res.auc_1,
+        auc_2 = res.auc_2,
+        z = res.z,
+        pval = res.pval
+    ))
+}
+
 /// Calculate the Hedge's G effect
 ///
 /// @description Calculates the Hedge's G effect for two sets of matrices. The
@@ -124,6 +233,132 @@ fn rs_hedges_g(mat_a: RMatrix<f64>, mat_b: RMatrix<f64>, small_sample_correction
     list!(effect_sizes = es, standard_errors = se)
 }
 
+/// Combine p-values
+///
+/// @description Combines the p-values per row of the matrix, for example the
+/// p-values of a set of genes (rows) over several cohorts (columns). NA
+/// p-values are excluded from the combination. WARNING! Incorrect use can
+/// cause kernel crashes. Wrapper around the Rust functions with type checks
+/// are provided in the package.
+///
+/// @param pvals Numeric matrix. The p-values with rows = tests and columns =
+/// the p-values to combine.
+/// @param weights Optional numeric vector. The weights for each column. Only
+/// used for Stouffer, Cauchy and harmonic mean p-value combinations. If NULL,
+/// equal weights are used.
+/// @param method String. One of `c("fisher", "stouffer", "cauchy",
+/// "harmonic")`.
+///
+/// @return A list with:
+/// \itemize{
+///   \item statistic - The test statistic per row, i.e., Fisher's chi-squared
+///   statistic, Stouffer's Z, the Cauchy statistic or the harmonic mean
+///   p-value.
+///   \item pvals - The combined p-value per row.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_combine_pvals(
+    pvals: RMatrix<f64>,
+    weights: Option<Vec<f64>>,
+    method: &str,
+) -> extendr_api::Result<List> {
+    let method = parse_pval_combination(method)
+        .ok_or_else(|| format!("Invalid p-value combination method: {}", method))?;
+    let pvals = r_matrix_to_faer(&pvals);
+
+    if let Some(weights) = &weights {
+        if weights.len() != pvals.ncols() {
+            return Err("The length of the weights needs to equal the number of columns.".into());
+        }
+    }
+
+    let res: Vec<CombinedPval> = (0..pvals.nrows())
+        .into_par_iter()
+        .map(|i| {
+            let row: Vec<f64> = pvals.row(i).iter().copied().collect();
+            combine_pvals(&row, weights.as_deref(), &method)
+        })
+        .collect();
+
+    let (statistic, combined_pvals): (Vec<f64>, Vec<f64>) = res.into_iter().unzip();
+
+    Ok(list!(statistic = statistic, pvals = combined_pvals))
+}
+
+/// Meta-analysis of effect sizes
+///
+/// @description Inverse variance meta-analysis of effect sizes and their
+/// standard errors (for example from `rs_hedges_g()`) per row. Supports fixed
+/// effects and random effects with the between-study variance estimated via
+/// DerSimonian-Laird or REML. Studies with NA values are excluded for the
+/// respective row. WARNING! Incorrect use can cause kernel crashes. Wrapper
+/// around the Rust functions with type checks are provided in the package.
+///
+/// @param effect_sizes Numeric matrix. The effect sizes with rows = features
+/// and columns = studies.
+/// @param standard_errors Numeric matrix. The standard errors of the effect
+/// sizes. Same dimensions as `effect_sizes`.
+/// @param method String. One of `c("fixed", "dl", "reml")`.
+/// @param conf_level Float. The confidence level for the confidence intervals.
+///
+/// @return A list with:
+/// \itemize{
+///   \item estimate - The pooled effect size.
+///   \item se - The standard error of the pooled effect size.
+///   \item z - The Z-score of the pooled effect size.
+///   \item pvals - The two-sided p-value.
+///   \item ci_lower - The lower bound of the confidence interval.
+///   \item ci_upper - The upper bound of the confidence interval.
+///   \item tau2 - The estimated between-study variance (0 for fixed effects).
+///   \item q - Cochran's Q statistic.
+///   \item q_pvals - The p-value of the Q statistic.
+///   \item i2 - The I2 heterogeneity statistic in percent.
+///   \item k - The number of studies included.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_meta_analysis(
+    effect_sizes: RMatrix<f64>,
+    standard_errors: RMatrix<f64>,
+    method: &str,
+    conf_level: f64,
+) -> extendr_api::Result<List> {
+    let method = parse_meta_analysis_method(method)
+        .ok_or_else(|| format!("Invalid meta-analysis method: {}", method))?;
+    let effect_sizes = r_matrix_to_faer(&effect_sizes);
+    let standard_errors = r_matrix_to_faer(&standard_errors);
+
+    if effect_sizes.shape() != standard_errors.shape() {
+        return Err("The effect sizes and standard errors need the same dimensions.".into());
+    }
+
+    let res: Vec<MetaAnalysisRes> = (0..effect_sizes.nrows())
+        .into_par_iter()
+        .map(|i| {
+            let y: Vec<f64> = effect_sizes.row(i).iter().copied().collect();
+            let se: Vec<f64> = standard_errors.row(i).iter().copied().collect();
+            meta_analysis(&y, &se, &method, conf_level)
+        })
+        .collect();
+
+    Ok(list!(
+        estimate = res.iter().map(|r| r.estimate).collect::<Vec<_>>(),
+        se = res.iter().map(|r| r.se).collect::<Vec<_>>(),
+        z = res.iter().map(|r| r.z).collect::<Vec<_>>(),
+        pvals = res.iter().map(|r| r.pval).collect::<Vec<_>>(),
+        ci_lower = res.iter().map(|r| r.ci_lower).collect::<Vec<_>>(),
+        ci_upper = res.iter().map(|r| r.ci_upper).collect::<Vec<_>>(),
+        tau2 = res.iter().map(|r| r.tau2).collect::<Vec<_>>(),
+        q = res.iter().map(|r| r.q).collect::<Vec<_>>(),
+        q_pvals = res.iter().map(|r| r.q_pval).collect::<Vec<_>>(),
+        i2 = res.iter().map(|r| r.i2).collect::<Vec<_>>(),
+        k = res.iter().map(|r| r.k).collect::<Vec<_>>()
+    ))
+}
+
 /// Calculate a BH-based FDR
 ///
 /// @description Rust implementation that will be faster if you have an
@@ -136,51 +371,184 @@ fn rs_hedges_g(mat_a: RMatrix<f64>, mat_b: RMatrix<f64>, small_sample_correction
 /// @export
 #[extendr]
 fn rs_fdr_adjustment(pvals: &[f64]) -> Vec<f64> {
-    let n = pvals.len();
-    let n_f64 = n as f64;
+    calc_fdr(pvals)
+}
 
-    let mut indexed_pval: Vec<(usize, f64)> =
-        pvals.par_iter().enumerate().map(|(i, &x)| (i, x)).collect();
+/// Adjust p-values for multiple testing
+///
+/// @description Rust implementation of various multiple testing corrections.
+/// Equivalent to `stats::p.adjust()` for the shared methods. Additionally,
+/// Storey q-values can be calculated with pi0 being estimated either via a
+/// smoother or via bootstrapping. NA p-values are excluded from the correction
+/// and returned as NA.
+///
+/// @param pvals Numeric vector. The p-values you wish to adjust.
+/// @param method String. One of `c("bonferroni", "holm", "hochberg",
+/// "hommel", "BH", "fdr", "BY", "storey_smoother", "storey_bootstrap")`.
+///
+/// @return The adjusted p-values.
+///
+/// @export
+#[extendr]
+fn rs_p_adjust(pvals: &[f64], method: &str) -> extendr_api::Result<Vec<f64>> {
+    let method = parse_p_adjust_method(method)
+        .ok_or_else(|| format!("Invalid multiple testing method: {}", method))?;
 
-    indexed_pval
-        .sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(p_adjust(pvals, &method))
+}
 
-    let adj_pvals_tmp: Vec<f64> = indexed_pval
-        .par_iter()
-        .enumerate()
-        .map(|(i, (_, p))| (n_f64 / (i + 1) as f64) * p)
-        .collect();
+/// Calculate Storey q-values
+///
+/// @description Calculates the q-values according to Storey and Tibshirani
+/// and returns the estimated proportion of true null hypotheses alongside.
+/// pi0 is estimated over a grid of lambdas from 0.05 to 0.95 either via a
+/// cubic smoothing spline (3 degrees of freedom) or via the bootstrap mean
+/// squared error, equivalent to `qvalue::qvalue()`. NA p-values are excluded
+/// and returned as NA.
+///
+/// @param pvals Numeric vector. The p-values.
+/// @param pi0_method String. One of `c("smoother", "bootstrap")`.
+///
+/// @return A list with:
+/// \itemize{
+///   \item qvals - The q-values.
+///   \item pi0 - The estimated proportion of true null hypotheses.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_storey_qvalues(pvals: &[f64], pi0_method: &str) -> extendr_api::Result<List> {
+    let pi0_method = parse_pi0_method(pi0_method)
+        .ok_or_else(|| format!("Invalid pi0 estimation method: {}", pi0_method))?;
+
+    let valid_idx: Vec<usize> = (0..pvals.len()).filter(|&i| !pvals[i].is_nan()).collect();
+    let valid: Vec<f64> = valid_idx.iter().map(|&i| pvals[i]).collect();
 
-    let mut current_min = adj_pvals_tmp[n - 1].min(1.0);
-    let mut monotonic_adj = vec![current_min; n];
+    let (qvals_valid, pi0) = calc_qvalues(&valid, &pi0_method);
 
-    for i in (0..n - 1).rev() {
-        current_min = current_min.min(adj_pvals_tmp[i]).min(1.0);
-        monotonic_adj[i] = current_min;
+    let mut qvals = vec![f64::NAN; pvals.len()];
+    for (q, &i) in qvals_valid.into_iter().zip(valid_idx.iter()) {
+        qvals[i] = q;
     }
 
-    let mut adj_pvals = vec![0.0; n];
+    Ok(list!(qvals = qvals, pi0 = pi0))
+}
 
-    for (i, &(original_idx, _)) in indexed_pval.iter().enumerate() {
-        adj_pvals[original_idx] = monotonic_adj[i];
-    }
+/// Check the parameters of the hypergeometric distribution. Like R, m, n and
+/// k need to be finite, non-negative integers with k <= m + n.
+fn valid_hyper_params(m: f64, n: f64, k: f64) -> bool {
+    [m, n, k]
+        .iter()
+        .all(|v| v.is_finite() && *v >= 0.0 && v.fract() == 0.0)
+        && k <= m + n
+}
 
-    adj_pvals
+/// Calculate the hypergeometric distribution function in Rust
+///
+/// @description Vectorised version of the hypergeometric distribution
+/// function. The shorter arguments are recycled to the length of the longest.
+/// The requested tail is summed in log space; hence, extreme tail
+/// probabilities do not underflow if `log_p` is set to `TRUE`. NA/NaN inputs
+/// are propagated. Invalid parameters (negative, non-integer or non-finite m,
+/// n or k, or k > m + n) return NaN.
+///
+/// @param q Numeric vector. Number of white balls drawn out of urn.
+/// @param m Numeric vector. Number of white balls in the urn.
+/// @param n Numeric vector. Number of black balls in the urn.
+/// @param k Numeric vector. The number of balls drawn out of the urn.
+/// @param lower_tail Boolean. If `TRUE` P(X <= q), otherwise P(X > q).
+/// @param log_p Boolean. Shall the probabilities be returned as log(p).
+///
+/// @return The (log) probabilities.
+///
+/// @export
+#[extendr]
+fn rs_phyper(
+    q: &[f64],
+    m: &[f64],
+    n: &[f64],
+    k: &[f64],
+    lower_tail: bool,
+    log_p: bool,
+) -> Vec<f64> {
+    let len = recycled_length(&[q.len(), m.len(), n.len(), k.len()]);
+
+    (0..len)
+        .into_par_iter()
+        .map(|i| {
+            let args = [
+                q[i % q.len()],
+                m[i % m.len()],
+                n[i % n.len()],
+                k[i % k.len()],
+            ];
+            // Return the NA/NaN itself to keep the NA payload for R
+            if let Some(na) = args.iter().find(|v| v.is_nan()) {
+                return *na;
+            }
+            if !valid_hyper_params(args[1], args[2], args[3]) {
+                return f64::NAN;
+            }
+            phyper(
+                args[0].floor() as i64,
+                args[1] as u64,
+                args[2] as u64,
+                args[3] as u64,
+                lower_tail,
+                log_p,
+            )
+        })
+        .collect()
 }
 
-/// Calculate the hypergeometric rest in Rust
+/// Calculate the hypergeometric probability mass function in Rust
+///
+/// @description Vectorised version of the hypergeometric probability mass
+/// function. The shorter arguments are recycled to the length of the longest.
+/// NA/NaN inputs are propagated. Invalid parameters (negative, non-integer or
+/// non-finite m, n or k, or k > m + n) return NaN.
 ///
-/// @param q Number of white balls drawn out of urn.
-/// @param m Number of white balls in the urn.
-/// @param n Number of black balls in the urn.
-/// @param k The number of balls drawn out of the urn.
+/// @param x Numeric vector. Number of white balls drawn out of urn.
+/// @param m Numeric vector. Number of white balls in the urn.
+/// @param n Numeric vector. Number of black balls in the urn.
+/// @param k Numeric vector. The number of balls drawn out of the urn.
+/// @param log_p Boolean. Shall the probabilities be returned as log(p).
 ///
-/// @return P-value (with lower.tail set to False)
+/// @return The (log) probabilities.
 ///
 /// @export
 #[extendr]
-fn rs_phyper(q: u64, m: u64, n: u64, k: u64) -> f64 {
-    hypergeom_pval(q, m, n, k)
+fn rs_dhyper(x: &[f64], m: &[f64], n: &[f64], k: &[f64], log_p: bool) -> Vec<f64> {
+    let len = recycled_length(&[x.len(), m.len(), n.len(), k.len()]);
+
+    (0..len)
+        .into_par_iter()
+        .map(|i| {
+            let args = [
+                x[i % x.len()],
+                m[i % m.len()],
+                n[i % n.len()],
+                k[i % k.len()],
+            ];
+            // Return the NA/NaN itself to keep the NA payload for R
+            if let Some(na) = args.iter().find(|v| v.is_nan()) {
+                return *na;
+            }
+            if !valid_hyper_params(args[1], args[2], args[3]) {
+                return f64::NAN;
+            }
+            if args[0].fract() != 0.0 {
+                return if log_p { f64::NEG_INFINITY } else { 0.0 };
+            }
+            dhyper(
+                args[0] as i64,
+                args[1] as u64,
+                args[2] as u64,
+                args[3] as u64,
+                log_p,
+            )
+        })
+        .collect()
 }
 
 /// Set similarities
@@ -211,11 +579,18 @@ fn rs_set_similarity(s_1: Vec<String>, s_2: Vec<String>, overlap_coefficient: bo
 /// Set similarities over list
 ///
 /// This function calculates the Jaccard or similarity index between a one given
-/// string vector and list of vectors.
+/// string vector and list of vectors. Alternatively, weighted set similarities
+/// can be calculated if the gene sets carry gene weights.
 ///
 /// @param s_1_list The String vector against which to calculate the set similarities.
 /// @param s_2_list A List of vector against which to calculate the set similarities.
 /// @param overlap_coefficient Boolean. Use the overlap coefficient instead of the Jaccard similarity be calculated.
+/// Ignored if `weighted_similarity` is provided.
+/// @param weighted_similarity Optional string. One of
+/// `c("ruzicka", "overlap", "cosine")`. If provided, both lists need to contain
+/// named numeric vectors (genes as names, weights as values) and the weighted
+/// Jaccard (Ruzicka), weighted overlap coefficient or cosine similarity on the
+/// absolute gene weights will be calculated.
 ///
 /// @export
 #[extendr]
@@ -223,7 +598,28 @@ fn rs_set_similarity_list(
     s_1_list: List,
     s_2_list: List,
     overlap_coefficient: bool,
+    weighted_similarity: Option<String>,
 ) -> extendr_api::Result<Vec<f64>> {
+    if let Some(weighted_similarity) = weighted_similarity {
+        let sim_type = parse_weighted_sim_type(&weighted_similarity)
+            .ok_or_else(|| format!("Invalid weighted similarity: {}", weighted_similarity))?;
+
+        let w1_vec = r_list_to_weight_maps(s_1_list)?;
+        let w2_vec = r_list_to_weight_maps(s_2_list)?;
+
+        let res: Vec<Vec<f64>> = w1_vec
+            .iter()
+            .map(|w1| {
+                w2_vec
+                    .iter()
+                    .map(|w2| weighted_set_similarity(w1, w2, &sim_type))
+                    .collect()
+            })
+            .collect();
+
+        return Ok(flatten_vector(res));
+    }
+
     let s1_vec = r_list_to_str_vec(s_1_list)?;
     let s2_vec = r_list_to_str_vec(s_2_list)?;
 
@@ -246,13 +642,115 @@ fn rs_set_similarity_list(
     Ok(res)
 }
 
+/// All-vs-all set similarities of a list
+///
+/// @description This function calculates the Jaccard or similarity index
+/// between all the gene sets within a single list. The genes are interned to
+/// integers and the intersections are calculated on sorted vectors in
+/// parallel. The function will return the upper triangle of the symmetric
+/// similarity matrix, see `rs_upper_triangle_to_dense()` to
+/// reconstruct the dense matrix.
+///
+/// @param s_list A list of character vectors. The gene sets.
+/// @param overlap_coefficient Boolean. Use the overlap coefficient instead of
+/// the Jaccard similarity be calculated.
+/// @param shift Integer. 0 = the diagonal will be included. 1 = the diagonal
+/// will not be included.
+///
+/// @return The upper triangle of the similarity matrix iterating through the
+/// rows.
+///
+/// @export
+#[extendr]
+fn rs_set_similarity_all(
+    s_list: List,
+    overlap_coefficient: bool,
+    shift: usize,
+) -> extendr_api::Result<Vec<f64>> {
+    let s_vec = r_list_to_str_vec(s_list)?;
+    let interned = intern_string_vecs(&s_vec);
+
+    Ok(set_similarity_upper_triangle(
+        &interned,
+        overlap_coefficient,
+        shift,
+    ))
+}
+
+/// Approximate set similarities over lists via MinHash/LSH
+///
+/// @description This function identifies all pairs between two lists of gene
+/// sets that have a Jaccard similarity above a given threshold. Instead of
+/// calculating the similarity for all pairs, MinHash signatures are generated
+/// and locality sensitive hashing (LSH) with banding is used to find candidate
+/// pairs. The number of bands and rows is chosen to minimise false positives
+/// and false negatives at the threshold. Candidate pairs are subsequently
+/// verified with the exact Jaccard similarity. Useful for very large gene set
+/// collections. Being an approximation, pairs with a similarity close to the
+/// threshold can be missed.
+///
+/// @param s_1_list A list of character vectors. The first collection of gene
+/// sets.
+/// @param s_2_list A list of character vectors. The second collection of gene
+/// sets.
+/// @param threshold Float. Minimum Jaccard similarity of the pairs to return.
+/// Needs to be in (0, 1].
+/// @param num_perm Integer. Number of hash functions for the MinHash signature
+/// (at least 1). Higher values reduce the number of missed pairs. Recommended
+/// size: 128L.
+/// @param seed Integer. Seed for the hash functions.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item idx_1 - The index positions (1-indexed) in s_1_list.
+///   \item idx_2 - The index positions (1-indexed) in s_2_list.
+///   \item similarity - The exact Jaccard similarity between the two sets.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_set_similarity_lsh(
+    s_1_list: List,
+    s_2_list: List,
+    threshold: f64,
+    num_perm: usize,
+    seed: u64,
+) -> extendr_api::Result<List> {
+    if num_perm < 1 {
+        return Err("The num_perm needs to be at least 1.".into());
+    }
+    if !(threshold > 0.0 && threshold <= 1.0) {
+        return Err("The threshold needs to be in (0, 1].".into());
+    }
+    let s1_vec = r_list_to_str_vec(s_1_list)?;
+    let s2_vec = r_list_to_str_vec(s_2_list)?;
+
+    let (idx_1, idx_2, similarity) = lsh_similar_sets(&s1_vec, &s2_vec, threshold, num_perm, seed);
+
+    // R is 1-indexed
+    let idx_1: Vec<usize> = idx_1.iter().map(|i| i + 1).collect();
+    let idx_2: Vec<usize> = idx_2.iter().map(|i| i + 1).collect();
+
+    Ok(list!(idx_1 = idx_1, idx_2 = idx_2, similarity = similarity))
+}
+
 extendr_module! {
     mod fun_stats;
     fn rs_set_similarity_list;
+    fn rs_set_similarity_lsh;
+    fn rs_set_similarity_all;
     fn rs_set_similarity;
     fn rs_fast_auc;
     fn rs_create_random_aucs;
+    fn rs_exact_auc;
+    fn rs_auc_delong;
+    fn rs_delong_test;
     fn rs_hedges_g;
+    fn rs_combine_pvals;
+    fn rs_meta_analysis;
     fn rs_fdr_adjustment;
+    fn rs_p_adjust;
+    fn rs_storey_qvalues;
     fn rs_phyper;
+    fn rs_dhyper;
 }
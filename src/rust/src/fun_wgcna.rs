@@ -0,0 +1,389 @@
+use extendr_api::prelude::*;
+
+use crate::helpers_linalg::parse_cor_method;
+use crate::helpers_wgcna::*;
+use crate::utils_r_rust::{faer_to_r_matrix, r_matrix_to_faer};
+
+/// Pick the soft threshold power
+///
+/// @description Calculates for every soft threshold power the fit of the
+/// resulting network to a scale-free topology and connectivity statistics,
+/// equivalent to `WGCNA::pickSoftThreshold()`. The function works on the upper
+/// triangle of the correlation matrix and the powers are processed in
+/// parallel. WARNING! Incorrect use can cause kernel crashes. Wrapper around
+/// the Rust functions with type checks are provided in the package.
+///
+/// @param cor Numeric vector. The upper triangle of the correlation matrix.
+/// @param powers Numeric vector. The soft threshold powers to test.
+/// @param original_dim Integer. The original dimensions of the symmetric
+/// correlation matrix.
+/// @param shift Integer. Was the matrix shifted up (0 = diagonal included; 1
+/// diagonal not included).
+/// @param adjacency_type String. One of
+/// `c("unsigned", "signed", "signed_hybrid")`.
+/// @param n_breaks Integer. Number of bins for the connectivity distribution.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item power - The tested powers.
+///   \item sft_r2 - The scale-free fit R2, multiplied with the negative sign of
+///   the slope.
+///   \item slope - The slope of log10(p(k)) ~ log10(k).
+///   \item truncated_r2 - The adjusted R2 of the truncated exponential fit.
+///   \item mean_k - The mean connectivity.
+///   \item median_k - The median connectivity.
+///   \item max_k - The maximum connectivity.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_soft_threshold_powers(
+    cor: &[f64],
+    powers: &[f64],
+    original_dim: usize,
+    shift: usize,
+    adjacency_type: &str,
+    n_breaks: usize,
+) -> extendr_api::Result<List> {
+    let adjacency_type = parse_adjacency_type(adjacency_type)
+        .ok_or_else(|| format!("Invalid adjacency type: {}", adjacency_type))?;
+
+    let res = soft_threshold_powers(cor, shift, original_dim, powers, &adjacency_type, n_breaks);
+
+    Ok(list!(
+        power = res.iter().map(|x| x.power).collect::<Vec<f64>>(),
+        sft_r2 = res.iter().map(|x| x.sft_r2).collect::<Vec<f64>>(),
+        slope = res.iter().map(|x| x.slope).collect::<Vec<f64>>(),
+        truncated_r2 = res.iter().map(|x| x.truncated_r2).collect::<Vec<f64>>(),
+        mean_k = res.iter().map(|x| x.mean_k).collect::<Vec<f64>>(),
+        median_k = res.iter().map(|x| x.median_k).collect::<Vec<f64>>(),
+        max_k = res.iter().map(|x| x.max_k).collect::<Vec<f64>>()
+    ))
+}
+
+/// Generate the soft thresholded adjacency matrix
+///
+/// @description Generates the WGCNA adjacency matrix from the upper triangle
+/// of the correlation matrix. `"unsigned"` uses `abs(cor)^power`, `"signed"`
+/// `((1 + cor) / 2)^power` and `"signed_hybrid"` `cor^power` for positive
+/// correlations and 0 otherwise. The diagonal is set to 1.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param cor Numeric vector. The upper triangle of the correlation matrix.
+/// @param power Numeric. The soft threshold power.
+/// @param original_dim Integer. The original dimensions of the symmetric
+/// correlation matrix.
+/// @param shift Integer. Was the matrix shifted up (0 = diagonal included; 1
+/// diagonal not included).
+/// @param adjacency_type String. One of
+/// `c("unsigned", "signed", "signed_hybrid")`.
+///
+/// @return The adjacency matrix.
+///
+/// @export
+#[extendr]
+fn rs_adjacency(
+    cor: &[f64],
+    power: f64,
+    original_dim: usize,
+    shift: usize,
+    adjacency_type: &str,
+) -> extendr_api::Result<extendr_api::RArray<f64, [usize; 2]>> {
+    let adjacency_type = parse_adjacency_type(adjacency_type)
+        .ok_or_else(|| format!("Invalid adjacency type: {}", adjacency_type))?;
+
+    let adj = adjacency_matrix(cor, shift, original_dim, power, &adjacency_type);
+
+    Ok(faer_to_r_matrix(adj.as_ref()))
+}
+
+/// Calculate the topological overlap matrix
+///
+/// @description Calculates the topological overlap matrix (TOM) of an
+/// adjacency matrix, equivalent to the unsigned TOM of
+/// `WGCNA::TOMsimilarity()`. The shared neighbourhood is calculated via
+/// blocked matrix multiplications. WARNING! Incorrect use can cause kernel
+/// crashes. Wrapper around the Rust functions with type checks are provided in
+/// the package.
+///
+/// @param adj Numeric matrix. The symmetric adjacency matrix with values
+/// between 0 and 1. The diagonal is ignored.
+/// @param block_size Integer. Number of columns processed per block.
+///
+/// @return The topological overlap matrix.
+///
+/// @export
+#[extendr]
+fn rs_tom(adj: RMatrix<f64>, block_size: usize) -> extendr_api::RArray<f64, [usize; 2]> {
+    let adj = r_matrix_to_faer(&adj);
+    let tom = topological_overlap(adj, block_size);
+
+    faer_to_r_matrix(tom.as_ref())
+}
+
+/// Calculate the module eigengenes
+///
+/// @description Calculates the eigengene of every module, i.e., the first
+/// principal component of the standardised expression of the module members,
+/// equivalent to `WGCNA::moduleEigengenes()`. The sign of the eigengene is
+/// aligned with the average standardised expression of the module. Modules are
+/// processed in parallel. WARNING! Incorrect use can cause kernel crashes.
+/// Wrapper around the Rust functions with type checks are provided in the
+/// package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param modules Integer vector. The (1-indexed) module of every feature.
+/// Values < 1 or NA indicate features without module.
+/// @param fast_svd Boolean. Shall a randomised SVD be used. This is faster for
+/// large modules.
+/// @param seed Integer. Only relevant if fast_svd is set to `TRUE`.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item eigengenes - Matrix of samples x modules with the eigengenes (unit
+///   norm).
+///   \item var_explained - The proportion of variance of every module
+///   explained by its eigengene.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_module_eigengenes(
+    x: RMatrix<f64>,
+    modules: &[i32],
+    fast_svd: bool,
+    seed: usize,
+) -> extendr_api::Result<List> {
+    let mat = r_matrix_to_faer(&x);
+    if modules.len() != mat.ncols() {
+        return Err("x needs to have as many columns as there are modules.".into());
+    }
+    let (modules, n_modules) = parse_module_assignment(modules);
+
+    let res = module_eigengenes(mat, &modules, n_modules, fast_svd, seed);
+
+    Ok(list!(
+        eigengenes = faer_to_r_matrix(res.eigengenes.as_ref()),
+        var_explained = res.var_explained
+    ))
+}
+
+/// Calculate the module membership (kME)
+///
+/// @description Calculates the correlation of every feature with every module
+/// eigengene (kME) together with Student t-test p-values.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param eigengenes R matrix with doubles. Rows = samples, columns = module
+/// eigengenes.
+/// @param method String. One of `c("pearson", "spearman", "bicor")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Defaults to 1 (the standard biweight midcorrelation) if `NULL`.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item kme - Matrix of features x modules with the kME values.
+///   \item p_vals - Matrix of features x modules with the p-values (NA with
+///   two or fewer samples).
+/// }
+///
+/// @export
+#[extendr]
+fn rs_module_kme(
+    x: RMatrix<f64>,
+    eigengenes: RMatrix<f64>,
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+) -> extendr_api::Result<List> {
+    let cor_method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
+    let mat = r_matrix_to_faer(&x);
+    let eigengenes = r_matrix_to_faer(&eigengenes);
+    if mat.nrows() != eigengenes.nrows() {
+        return Err("x and the eigengenes need to have the same number of samples.".into());
+    }
+
+    let res = module_membership(mat, eigengenes, &cor_method)
+        .ok_or_else(|| "Kendall's tau is not supported for the kME.".to_string())?;
+
+    Ok(list!(
+        kme = faer_to_r_matrix(res.kme.as_ref()),
+        p_vals = faer_to_r_matrix(res.p_vals.as_ref())
+    ))
+}
+
+/// Calculate the intramodular connectivity
+///
+/// @description Calculates the whole network and the intramodular
+/// connectivity of every feature based on the soft thresholded correlations,
+/// equivalent to `WGCNA::intramodularConnectivity()`. The correlations are
+/// calculated in blocks, i.e., the adjacency matrix is never materialised.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x R matrix with doubles. Rows = samples, columns = features.
+/// @param modules Integer vector. The (1-indexed) module of every feature.
+/// Values < 1 or NA indicate features without module.
+/// @param method String. One of `c("pearson", "spearman", "bicor")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Defaults to 1 (the standard biweight midcorrelation) if `NULL`.
+/// @param power Numeric. The soft threshold power.
+/// @param adjacency_type String. One of
+/// `c("unsigned", "signed", "signed_hybrid")`.
+/// @param block_size Integer. Number of features processed per block.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item k_total - The connectivity with all other features.
+///   \item k_within - The connectivity with the other features of the module
+///   (NA for features without module).
+///   \item k_out - k_total - k_within.
+///   \item k_diff - k_within - k_out.
+/// }
+///
+/// @export
+#[extendr]
+fn rs_intramodular_connectivity(
+    x: RMatrix<f64>,
+    modules: &[i32],
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+    power: f64,
+    adjacency_type: &str,
+    block_size: usize,
+) -> extendr_api::Result<List> {
+    let cor_method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
+    let adjacency_type = parse_adjacency_type(adjacency_type)
+        .ok_or_else(|| format!("Invalid adjacency type: {}", adjacency_type))?;
+    let mat = r_matrix_to_faer(&x);
+    if modules.len() != mat.ncols() {
+        return Err("x needs to have as many columns as there are modules.".into());
+    }
+    let (modules, _) = parse_module_assignment(modules);
+
+    let res = intramodular_connectivity(
+        mat,
+        &modules,
+        &cor_method,
+        power,
+        &adjacency_type,
+        block_size,
+    )
+    .ok_or_else(|| {
+        "Kendall's tau is not supported for the intramodular connectivity.".to_string()
+    })?;
+
+    Ok(list!(
+        k_total = res.k_total,
+        k_within = res.k_within,
+        k_out = res.k_out,
+        k_diff = res.k_diff
+    ))
+}
+
+/// Module preservation statistics
+///
+/// @description Calculates module preservation statistics between a reference
+/// and a test data set (Langfelder, et al., 2011). For every module, four
+/// density-based (mean sign-aware correlation, mean adjacency, variance
+/// explained by the eigengene and mean sign-aware kME in the test data) and
+/// three connectivity-based statistics (correlations of the intramodular
+/// connectivity, the kME and the pairwise correlations between the reference
+/// and test data) are calculated. Z-scores are derived from random gene sets of
+/// the same size; the permutations are seeded and run in parallel.
+/// WARNING! Incorrect use can cause kernel crashes. Wrapper around the Rust
+/// functions with type checks are provided in the package.
+///
+/// @param x_ref R matrix with doubles. The reference data. Rows = samples,
+/// columns = features.
+/// @param x_test R matrix with doubles. The test data with the same features
+/// (in the same order) as x_ref.
+/// @param modules Integer vector. The (1-indexed) module of every feature.
+/// Values < 1 or NA indicate features without module. Needs to have the same
+/// length as the number of columns of x_ref and x_test.
+/// @param method String. One of
+/// `c("pearson", "spearman", "bicor", "kendall")`.
+/// @param max_p_outliers Only relevant for bicor. Maximum proportion of the
+/// samples on either side of the median that can be treated as outliers.
+/// Defaults to 1 (the standard biweight midcorrelation) if `NULL`.
+/// @param power Numeric. The soft threshold power.
+/// @param adjacency_type String. One of
+/// `c("unsigned", "signed", "signed_hybrid")`.
+/// @param n_perm Integer. Number of permutations.
+/// @param seed Integer. Random seed.
+///
+/// @return A list containing:
+///  \itemize{
+///   \item observed - Matrix of modules x statistics with the observed
+///   statistics (mean_cor, mean_adj, prop_var_explained, mean_kme, cor_kim,
+///   cor_kme, cor_cor).
+///   \item z_scores - Matrix of modules x statistics with the Z-scores.
+///   \item z_density - The median of the density Z-scores.
+///   \item z_connectivity - The median of the connectivity Z-scores.
+///   \item z_summary - The mean of Z density and Z connectivity.
+///   \item median_rank - The median rank of the observed statistics.
+/// }
+///
+/// @export
+#[extendr]
+#[allow(clippy::too_many_arguments)]
+fn rs_module_preservation(
+    x_ref: RMatrix<f64>,
+    x_test: RMatrix<f64>,
+    modules: &[i32],
+    method: String,
+    #[default = "NULL"] max_p_outliers: Option<f64>,
+    power: f64,
+    adjacency_type: &str,
+    n_perm: usize,
+    seed: usize,
+) -> extendr_api::Result<List> {
+    let cor_method = parse_cor_method(&method, max_p_outliers.unwrap_or(1.0))
+        .ok_or_else(|| format!("Invalid correlation method: {}", method))?;
+    let adjacency_type = parse_adjacency_type(adjacency_type)
+        .ok_or_else(|| format!("Invalid adjacency type: {}", adjacency_type))?;
+    let ref_mat = r_matrix_to_faer(&x_ref);
+    let test_mat = r_matrix_to_faer(&x_test);
+    if ref_mat.ncols() != modules.len() || test_mat.ncols() != modules.len() {
+        return Err("x_ref and x_test need to have as many columns as there are modules.".into());
+    }
+    let (modules, n_modules) = parse_module_assignment(modules);
+
+    let res = module_preservation(
+        ref_mat,
+        test_mat,
+        &modules,
+        n_modules,
+        &cor_method,
+        power,
+        &adjacency_type,
+        n_perm,
+        seed,
+    );
+
+    Ok(list!(
+        observed = faer_to_r_matrix(res.observed.as_ref()),
+        z_scores = faer_to_r_matrix(res.z_scores.as_ref()),
+        z_density = res.z_density,
+        z_connectivity = res.z_connectivity,
+        z_summary = res.z_summary,
+        median_rank = res.median_rank
+    ))
+}
+
+extendr_module! {
+    mod fun_wgcna;
+    fn rs_soft_threshold_powers;
+    fn rs_adjacency;
+    fn rs_tom;
+    fn rs_module_eigengenes;
+    fn rs_module_kme;
+    fn rs_intramodular_connectivity;
+    fn rs_module_preservation;
+}
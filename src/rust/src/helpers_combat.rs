@@ -0,0 +1,374 @@
+use faer::{
+    linalg::solvers::{PartialPivLu, Solve},
+    Mat, MatRef,
+};
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+use crate::helpers_dge::design_is_full_rank;
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the ComBat parameters. If par_prior is set, parametric
+/// priors are used, otherwise the non-parametric version. With mean_only only
+/// the location of the batches is adjusted. ref_batch is the (0-indexed)
+/// reference batch that is not adjusted.
+#[derive(Clone, Debug)]
+pub struct CombatParams {
+    pub par_prior: bool,
+    pub mean_only: bool,
+    pub ref_batch: Option<usize>,
+}
+
+/// Structure for the ComBat results. The batch parameter matrices are
+/// genes x batches; genes that were not adjusted are NaN.
+#[derive(Clone, Debug)]
+pub struct CombatRes {
+    pub corrected: Mat<f64>,
+    pub gamma_hat: Mat<f64>,
+    pub delta_hat: Mat<f64>,
+    pub gamma_star: Mat<f64>,
+    pub delta_star: Mat<f64>,
+    pub gamma_bar: Vec<f64>,
+    pub t2: Vec<f64>,
+    pub a_prior: Vec<f64>,
+    pub b_prior: Vec<f64>,
+    pub mean_only: bool,
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Mean and sample variance of a slice.
+fn mean_var(x: &[f64]) -> (f64, f64) {
+    let n = x.len() as f64;
+    let mean = x.iter().sum::<f64>() / n;
+    let var = x.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, var)
+}
+
+/// Method of moments estimate of the shape of the inverse gamma prior.
+fn a_prior(delta_hat: &[f64]) -> f64 {
+    let (m, s2) = mean_var(delta_hat);
+    (2.0 * s2 + m.powi(2)) / s2
+}
+
+/// Method of moments estimate of the scale of the inverse gamma prior.
+fn b_prior(delta_hat: &[f64]) -> f64 {
+    let (m, s2) = mean_var(delta_hat);
+    (m * s2 + m.powi(3)) / s2
+}
+
+/// Posterior mean of the batch location.
+fn post_mean(g_hat: f64, g_bar: f64, n: f64, d_star: f64, t2: f64) -> f64 {
+    (t2 * n * g_hat + d_star * g_bar) / (t2 * n + d_star)
+}
+
+/// Posterior mean of the batch scale.
+fn post_var(sum2: f64, n: f64, a: f64, b: f64) -> f64 {
+    (0.5 * sum2 + b) / (n / 2.0 + a - 1.0)
+}
+
+/// Iterative solution of the parametric empirical Bayes estimates of one
+/// batch (sva's it.sol()). s_data holds the standardised data of the batch
+/// (genes x samples of the batch). Returns the location and scale estimates.
+fn iterative_solution(
+    s_data: MatRef<f64>,
+    hat: (&[f64], &[f64]),
+    gamma_prior: (f64, f64),
+    delta_prior: (f64, f64),
+    conv: f64,
+) -> (Vec<f64>, Vec<f64>) {
+    let (g_hat, d_hat) = hat;
+    let (g_bar, t2) = gamma_prior;
+    let (a, b) = delta_prior;
+    let n = s_data.ncols() as f64;
+
+    let mut g_old = g_hat.to_vec();
+    let mut d_old = d_hat.to_vec();
+    let mut change = 1.0;
+
+    while change > conv {
+        let g_new: Vec<f64> = g_hat
+            .iter()
+            .zip(&d_old)
+            .map(|(g, d)| post_mean(*g, g_bar, n, *d, t2))
+            .collect();
+        let d_new: Vec<f64> = (0..s_data.nrows())
+            .map(|i| {
+                let sum2: f64 = s_data.row(i).iter().map(|x| (x - g_new[i]).powi(2)).sum();
+                post_var(sum2, n, a, b)
+            })
+            .collect();
+
+        // Same convergence criterion as sva::ComBat()
+        change = g_new
+            .iter()
+            .zip(&g_old)
+            .map(|(new, old)| (new - old).abs() / old)
+            .chain(
+                d_new
+                    .iter()
+                    .zip(&d_old)
+                    .map(|(new, old)| (new - old).abs() / old),
+            )
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        g_old = g_new;
+        d_old = d_new;
+    }
+
+    (g_old, d_old)
+}
+
+/// Non-parametric empirical Bayes estimates of one batch (sva's
+/// int.eprior()). For each gene, the location and scale are the averages of
+/// the estimates of all other genes weighted by the likelihood of the data of
+/// the gene under these. The likelihoods are normalised on the log scale to
+/// avoid underflow.
+fn integrated_prior(s_data: MatRef<f64>, g_hat: &[f64], d_hat: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let n = s_data.ncols() as f64;
+    let sums: Vec<(f64, f64)> = (0..s_data.nrows())
+        .map(|i| {
+            let row = s_data.row(i);
+            (row.iter().sum(), row.iter().map(|x| x * x).sum())
+        })
+        .collect();
+
+    (0..s_data.nrows())
+        .into_par_iter()
+        .map(|i| {
+            let (sum_x, sum_x2) = sums[i];
+            let log_lh: Vec<f64> = (0..g_hat.len())
+                .map(|j| {
+                    if j == i {
+                        return f64::NEG_INFINITY;
+                    }
+                    let sum2 = sum_x2 - 2.0 * g_hat[j] * sum_x + n * g_hat[j].powi(2);
+                    -n / 2.0 * (2.0 * PI * d_hat[j]).ln() - sum2 / (2.0 * d_hat[j])
+                })
+                .collect();
+            let max_log_lh = log_lh.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+            let (mut sum_lh, mut sum_g, mut sum_d) = (0.0, 0.0, 0.0);
+            for j in 0..g_hat.len() {
+                let lh = (log_lh[j] - max_log_lh).exp();
+                if lh.is_finite() {
+                    sum_lh += lh;
+                    sum_g += g_hat[j] * lh;
+                    sum_d += d_hat[j] * lh;
+                }
+            }
+
+            (sum_g / sum_lh, sum_d / sum_lh)
+        })
+        .unzip()
+}
+
+////////////
+// ComBat //
+////////////
+
+/// Empirical Bayes batch correction (sva::ComBat()). dat is genes x samples,
+/// batch the 0-indexed batch of each sample and covariates an optional
+/// samples x covariates matrix (without intercept) of effects to preserve.
+/// The data is standardised gene-wise, the batch locations and scales are
+/// estimated and shrunk towards their (parametric or non-parametric) priors
+/// and removed. Genes with non-finite values or zero variance within a batch
+/// are returned unchanged. If any batch has only one sample, only the
+/// location is adjusted. Returns an error if the covariates are confounded
+/// with the batches, i.e., the design does not have full column rank.
+pub fn combat(
+    dat: MatRef<f64>,
+    batch: &[usize],
+    covariates: Option<MatRef<f64>>,
+    params: &CombatParams,
+) -> Result<CombatRes, String> {
+    let (n_genes_total, n_samples) = dat.shape();
+    let n_batch = batch.iter().max().map_or(0, |b| b + 1);
+    let batches: Vec<Vec<usize>> = (0..n_batch)
+        .map(|b| (0..n_samples).filter(|&s| batch[s] == b).collect())
+        .collect();
+    let batch_sizes: Vec<f64> = batches.iter().map(|b| b.len() as f64).collect();
+    let mean_only = params.mean_only || batches.iter().any(|b| b.len() == 1);
+
+    // Design with the batch indicators (the reference batch column is all
+    // ones) and the covariates
+    let n_cov = covariates.map_or(0, |c| c.ncols());
+    let design = Mat::from_fn(n_samples, n_batch + n_cov, |s, j| {
+        if j < n_batch {
+            if batch[s] == j || params.ref_batch == Some(j) {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            covariates.unwrap()[(s, j - n_batch)]
+        }
+    });
+
+    if !design_is_full_rank(design.as_ref()) {
+        return Err(
+            "At least one covariate is confounded with batch. Please remove the confounded covariates."
+                .to_string(),
+        );
+    }
+
+    // Genes that can be adjusted
+    let genes: Vec<usize> = (0..n_genes_total)
+        .filter(|&g| {
+            let row = dat.row(g);
+            row.iter().all(|x| x.is_finite())
+                && batches.iter().filter(|b| b.len() > 1).all(|b| {
+                    let values: Vec<f64> = b.iter().map(|&s| row[s]).collect();
+                    mean_var(&values).1 > 0.0
+                })
+        })
+        .collect();
+    let n_genes = genes.len();
+    let y = Mat::from_fn(n_genes, n_samples, |g, s| dat[(genes[g], s)]);
+
+    // Standardise the data
+    let xtx = design.transpose() * &design;
+    let xty = design.transpose() * y.transpose();
+    let b_hat = PartialPivLu::new(xtx.as_ref()).solve(&xty);
+    let fitted = &design * &b_hat;
+
+    let grand_mean: Vec<f64> = (0..n_genes)
+        .map(|g| match params.ref_batch {
+            Some(r) => b_hat[(r, g)],
+            None => (0..n_batch)
+                .map(|b| batch_sizes[b] / n_samples as f64 * b_hat[(b, g)])
+                .sum(),
+        })
+        .collect();
+    let var_samples: Vec<usize> = match params.ref_batch {
+        Some(r) => batches[r].clone(),
+        None => (0..n_samples).collect(),
+    };
+    let var_pooled: Vec<f64> = (0..n_genes)
+        .map(|g| {
+            var_samples
+                .iter()
+                .map(|&s| (y[(g, s)] - fitted[(s, g)]).powi(2))
+                .sum::<f64>()
+                / var_samples.len() as f64
+        })
+        .collect();
+    let stand_mean = Mat::from_fn(n_genes, n_samples, |g, s| {
+        grand_mean[g]
+            + (n_batch..n_batch + n_cov)
+                .map(|k| design[(s, k)] * b_hat[(k, g)])
+                .sum::<f64>()
+    });
+    let s_data = Mat::from_fn(n_genes, n_samples, |g, s| {
+        (y[(g, s)] - stand_mean[(g, s)]) / var_pooled[g].sqrt()
+    });
+
+    // Batch effect estimates
+    let batch_design = design.get(.., 0..n_batch);
+    let btb = batch_design.transpose() * batch_design;
+    let bts = batch_design.transpose() * s_data.transpose();
+    let gamma_hat = PartialPivLu::new(btb.as_ref()).solve(&bts);
+    let delta_hat = Mat::from_fn(n_batch, n_genes, |b, g| {
+        if mean_only {
+            1.0
+        } else {
+            let values: Vec<f64> = batches[b].iter().map(|&s| s_data[(g, s)]).collect();
+            mean_var(&values).1
+        }
+    });
+
+    // Priors
+    let gamma_rows: Vec<Vec<f64>> = (0..n_batch)
+        .map(|b| gamma_hat.row(b).iter().copied().collect())
+        .collect();
+    let delta_rows: Vec<Vec<f64>> = (0..n_batch)
+        .map(|b| delta_hat.row(b).iter().copied().collect())
+        .collect();
+    let (gamma_bar, t2): (Vec<f64>, Vec<f64>) = gamma_rows.iter().map(|g| mean_var(g)).unzip();
+    let a_priors: Vec<f64> = delta_rows.iter().map(|d| a_prior(d)).collect();
+    let b_priors: Vec<f64> = delta_rows.iter().map(|d| b_prior(d)).collect();
+
+    // Empirical Bayes estimates
+    let mut gamma_star = Mat::<f64>::zeros(n_batch, n_genes);
+    let mut delta_star = Mat::<f64>::ones(n_batch, n_genes);
+    for b in 0..n_batch {
+        let batch_data = Mat::from_fn(n_genes, batches[b].len(), |g, s| s_data[(g, batches[b][s])]);
+        let (g_star, d_star) = if params.par_prior {
+            if mean_only {
+                let g_star = (0..n_genes)
+                    .map(|g| post_mean(gamma_rows[b][g], gamma_bar[b], 1.0, 1.0, t2[b]))
+                    .collect();
+                (g_star, vec![1.0; n_genes])
+            } else {
+                iterative_solution(
+                    batch_data.as_ref(),
+                    (&gamma_rows[b], &delta_rows[b]),
+                    (gamma_bar[b], t2[b]),
+                    (a_priors[b], b_priors[b]),
+                    1e-4,
+                )
+            }
+        } else {
+            let (g_star, d_star) =
+                integrated_prior(batch_data.as_ref(), &gamma_rows[b], &delta_rows[b]);
+            if mean_only {
+                (g_star, vec![1.0; n_genes])
+            } else {
+                (g_star, d_star)
+            }
+        };
+        for g in 0..n_genes {
+            gamma_star[(b, g)] = g_star[g];
+            delta_star[(b, g)] = d_star[g];
+        }
+    }
+    if let Some(r) = params.ref_batch {
+        for g in 0..n_genes {
+            gamma_star[(r, g)] = 0.0;
+            delta_star[(r, g)] = 1.0;
+        }
+    }
+
+    // Adjust the data
+    let batch_effect = batch_design * &gamma_star;
+    let mut corrected = dat.to_owned();
+    for (g, &gene) in genes.iter().enumerate() {
+        for s in 0..n_samples {
+            if params.ref_batch == Some(batch[s]) {
+                continue;
+            }
+            let adjusted =
+                (s_data[(g, s)] - batch_effect[(s, g)]) / delta_star[(batch[s], g)].sqrt();
+            corrected[(gene, s)] = adjusted * var_pooled[g].sqrt() + stand_mean[(g, s)];
+        }
+    }
+
+    // Batch parameters as genes x batches for all genes
+    let expand = |mat: &Mat<f64>| {
+        let mut res = Mat::from_fn(n_genes_total, n_batch, |_, _| f64::NAN);
+        for (g, &gene) in genes.iter().enumerate() {
+            for b in 0..n_batch {
+                res[(gene, b)] = mat[(b, g)];
+            }
+        }
+        res
+    };
+
+    Ok(CombatRes {
+        corrected,
+        gamma_hat: expand(&gamma_hat),
+        delta_hat: expand(&delta_hat),
+        gamma_star: expand(&gamma_star),
+        delta_star: expand(&delta_star),
+        gamma_bar,
+        t2,
+        a_prior: a_priors,
+        b_prior: b_priors,
+        mean_only,
+    })
+}
@@ -0,0 +1,549 @@
+use rand::prelude::*;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the quality functions of the community detection
+#[derive(Clone, Debug)]
+pub enum CommunityQuality {
+    Modularity,
+    Cpm,
+}
+
+/// Parsing the quality function
+pub fn parse_community_quality(s: &str) -> Option<CommunityQuality> {
+    match s.to_lowercase().as_str() {
+        "modularity" => Some(CommunityQuality::Modularity),
+        "cpm" => Some(CommunityQuality::Cpm),
+        _ => None,
+    }
+}
+
+/// Enum for the community detection algorithms
+#[derive(Clone, Debug)]
+pub enum CommunityAlgorithm {
+    Leiden,
+    Louvain,
+}
+
+/// Parsing the community detection algorithm
+pub fn parse_community_algorithm(s: &str) -> Option<CommunityAlgorithm> {
+    match s.to_lowercase().as_str() {
+        "leiden" => Some(CommunityAlgorithm::Leiden),
+        "louvain" => Some(CommunityAlgorithm::Louvain),
+        _ => None,
+    }
+}
+
+/////////////
+// Structs //
+/////////////
+
+/// Structure for the community detection parameters
+#[derive(Clone, Debug)]
+pub struct CommunityParams {
+    pub quality: CommunityQuality,
+    pub algorithm: CommunityAlgorithm,
+    pub n_iterations: i32,
+    pub beta: f64,
+}
+
+/// Structure for the community detection results of one resolution
+#[derive(Clone, Debug)]
+pub struct CommunityRes {
+    pub membership: Vec<usize>,
+    pub modularity: f64,
+    pub quality: f64,
+}
+
+/// Undirected, weighted graph stored as neighbour lists. Self loops are kept
+/// separately and count twice towards the strength of a node (as in igraph).
+#[derive(Clone, Debug)]
+pub struct WeightedGraph {
+    pub n_nodes: usize,
+    pub neighbours: Vec<Vec<(usize, f64)>>,
+    pub self_weights: Vec<f64>,
+    pub node_sizes: Vec<f64>,
+    pub strengths: Vec<f64>,
+    pub total_weight: f64,
+}
+
+impl WeightedGraph {
+    /// Generate the graph from 0-indexed edges. Multiple edges between the
+    /// same nodes are merged by summing their weights.
+    pub fn from_edges(n_nodes: usize, from: &[usize], to: &[usize], weight: &[f64]) -> Self {
+        let mut neighbours: Vec<Vec<(usize, f64)>> = vec![Vec::new(); n_nodes];
+        let mut self_weights = vec![0.0; n_nodes];
+        for ((&i, &j), &w) in from.iter().zip(to.iter()).zip(weight.iter()) {
+            if i == j {
+                self_weights[i] += 2.0 * w;
+            } else {
+                neighbours[i].push((j, w));
+                neighbours[j].push((i, w));
+            }
+        }
+
+        Self::new(neighbours, self_weights, vec![1.0; n_nodes])
+    }
+
+    /// Generate the graph and the derived node strengths
+    fn new(
+        mut neighbours: Vec<Vec<(usize, f64)>>,
+        self_weights: Vec<f64>,
+        node_sizes: Vec<f64>,
+    ) -> Self {
+        neighbours
+            .par_iter_mut()
+            .for_each(|nb| *nb = merge_edges(nb));
+        let strengths: Vec<f64> = neighbours
+            .iter()
+            .zip(self_weights.iter())
+            .map(|(nb, self_w)| nb.iter().map(|(_, w)| w).sum::<f64>() + self_w)
+            .collect();
+        let total_weight = strengths.iter().sum::<f64>();
+
+        Self {
+            n_nodes: neighbours.len(),
+            neighbours,
+            self_weights,
+            node_sizes,
+            strengths,
+            total_weight,
+        }
+    }
+
+    /// Weights of the nodes within the quality function
+    fn node_weights(&self, quality: &CommunityQuality) -> &[f64] {
+        match quality {
+            CommunityQuality::Modularity => &self.strengths,
+            CommunityQuality::Cpm => &self.node_sizes,
+        }
+    }
+
+    /// Collapse the nodes of the graph into the (0-indexed, consecutive)
+    /// communities.
+    fn aggregate(&self, membership: &[usize], n_communities: usize) -> Self {
+        let mut neighbours: Vec<Vec<(usize, f64)>> = vec![Vec::new(); n_communities];
+        let mut self_weights = vec![0.0; n_communities];
+        let mut node_sizes = vec![0.0; n_communities];
+        for v in 0..self.n_nodes {
+            let c = membership[v];
+            self_weights[c] += self.self_weights[v];
+            node_sizes[c] += self.node_sizes[v];
+            for &(u, w) in &self.neighbours[v] {
+                if membership[u] == c {
+                    self_weights[c] += w;
+                } else {
+                    neighbours[c].push((membership[u], w));
+                }
+            }
+        }
+
+        Self::new(neighbours, self_weights, node_sizes)
+    }
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Sort the neighbours and sum up the weights of duplicated ones
+fn merge_edges(neighbours: &[(usize, f64)]) -> Vec<(usize, f64)> {
+    let mut sorted = neighbours.to_vec();
+    sorted.sort_unstable_by_key(|(u, _)| *u);
+    let mut merged: Vec<(usize, f64)> = Vec::with_capacity(sorted.len());
+    for (u, w) in sorted {
+        match merged.last_mut() {
+            Some((last, last_w)) if *last == u => *last_w += w,
+            _ => merged.push((u, w)),
+        }
+    }
+
+    merged
+}
+
+/// Renumber the communities to consecutive ids in order of their first
+/// appearance. Returns the number of communities.
+fn renumber(membership: &mut [usize]) -> usize {
+    let mut new_ids = vec![usize::MAX; membership.len()];
+    let mut n_communities = 0;
+    for c in membership.iter_mut() {
+        if new_ids[*c] == usize::MAX {
+            new_ids[*c] = n_communities;
+            n_communities += 1;
+        }
+        *c = new_ids[*c];
+    }
+
+    n_communities
+}
+
+/// Scaling factor of the penalty term. The gain of moving node v into
+/// community c is w(v, c) - scale * a_v * A_c with a being the strengths for
+/// modularity and the node sizes for CPM.
+fn penalty_scale(graph: &WeightedGraph, quality: &CommunityQuality, resolution: f64) -> f64 {
+    match quality {
+        CommunityQuality::Modularity => resolution / graph.total_weight,
+        CommunityQuality::Cpm => resolution,
+    }
+}
+
+/// Quality of a partition, equivalent to igraph, i.e.,
+/// 1 / 2m * sum_ij (A_ij - gamma * k_i * k_j / 2m) * delta(c_i, c_j) for
+/// modularity and 1 / 2m * sum_ij (A_ij - gamma * n_i * n_j) * delta(c_i, c_j)
+/// for CPM.
+pub fn partition_quality(
+    graph: &WeightedGraph,
+    membership: &[usize],
+    quality: &CommunityQuality,
+    resolution: f64,
+) -> f64 {
+    if graph.total_weight <= 0.0 {
+        return f64::NAN;
+    }
+    let n_communities = membership.iter().max().map_or(0, |c| c + 1);
+    let node_weights = graph.node_weights(quality);
+    let mut internal = vec![0.0; n_communities];
+    let mut totals = vec![0.0; n_communities];
+    for v in 0..graph.n_nodes {
+        let c = membership[v];
+        totals[c] += node_weights[v];
+        internal[c] += graph.self_weights[v];
+        internal[c] += graph.neighbours[v]
+            .iter()
+            .filter(|(u, _)| membership[*u] == c)
+            .map(|(_, w)| w)
+            .sum::<f64>();
+    }
+    let scale = penalty_scale(graph, quality, resolution);
+
+    internal
+        .iter()
+        .zip(totals.iter())
+        .map(|(in_c, tot_c)| in_c - scale * tot_c * tot_c)
+        .sum::<f64>()
+        / graph.total_weight
+}
+
+////////////////////////
+// Leiden and Louvain //
+////////////////////////
+
+/// Fast local moving of the nodes (Traag, et al., 2019). Nodes are visited
+/// from a queue and moved into the neighbouring community with the largest
+/// gain (or an empty one if all gains are negative). Neighbours of moved nodes
+/// that are not part of the new community are added back to the queue.
+/// Returns whether any node was moved.
+fn move_nodes(
+    graph: &WeightedGraph,
+    membership: &mut [usize],
+    node_weights: &[f64],
+    scale: f64,
+    rng: &mut StdRng,
+) -> bool {
+    let n = graph.n_nodes;
+    let mut community_weights = vec![0.0; n];
+    let mut community_sizes = vec![0_usize; n];
+    for v in 0..n {
+        community_weights[membership[v]] += node_weights[v];
+        community_sizes[membership[v]] += 1;
+    }
+    let mut empty: Vec<usize> = (0..n).filter(|&c| community_sizes[c] == 0).collect();
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+    let mut queue: VecDeque<usize> = order.into();
+    let mut in_queue = vec![true; n];
+
+    let mut edge_weights = vec![0.0; n];
+    let mut touched = vec![false; n];
+    let mut neighbour_communities: Vec<usize> = Vec::new();
+    let mut changed = false;
+
+    while let Some(v) = queue.pop_front() {
+        in_queue[v] = false;
+        let current = membership[v];
+        let a_v = node_weights[v];
+
+        community_weights[current] -= a_v;
+        community_sizes[current] -= 1;
+        if community_sizes[current] == 0 {
+            empty.push(current);
+        }
+
+        for &(u, w) in &graph.neighbours[v] {
+            let c = membership[u];
+            if !touched[c] {
+                touched[c] = true;
+                neighbour_communities.push(c);
+            }
+            edge_weights[c] += w;
+        }
+
+        let mut best = current;
+        let mut best_gain = edge_weights[current] - scale * a_v * community_weights[current];
+        for &c in &neighbour_communities {
+            let gain = edge_weights[c] - scale * a_v * community_weights[c];
+            if gain > best_gain {
+                best = c;
+                best_gain = gain;
+            }
+        }
+        if best_gain < 0.0 {
+            best = *empty.last().unwrap();
+        }
+        if community_sizes[best] == 0 {
+            empty.pop();
+        }
+
+        for &c in &neighbour_communities {
+            edge_weights[c] = 0.0;
+            touched[c] = false;
+        }
+        neighbour_communities.clear();
+
+        community_weights[best] += a_v;
+        community_sizes[best] += 1;
+        membership[v] = best;
+
+        if best != current {
+            changed = true;
+            for &(u, _) in &graph.neighbours[v] {
+                if !in_queue[u] && membership[u] != best {
+                    queue.push_back(u);
+                    in_queue[u] = true;
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+/// Refinement of the partition (Traag, et al., 2019). Starting from singletons,
+/// well-connected nodes that are still on their own are merged into
+/// well-connected refined communities within their community. The refined
+/// community is chosen randomly with probabilities proportional to
+/// exp(gain / beta) over all non-negative gains.
+fn refine_partition(
+    graph: &WeightedGraph,
+    membership: &[usize],
+    node_weights: &[f64],
+    scale: f64,
+    beta: f64,
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    let n = graph.n_nodes;
+    let mut community_weights = vec![0.0; n];
+    for v in 0..n {
+        community_weights[membership[v]] += node_weights[v];
+    }
+
+    let mut refined: Vec<usize> = (0..n).collect();
+    let mut refined_weights = node_weights.to_vec();
+    let mut refined_sizes = vec![1_usize; n];
+    // Weight between a refined community and the rest of its community
+    let mut external_weights: Vec<f64> = (0..n)
+        .map(|v| {
+            graph.neighbours[v]
+                .iter()
+                .filter(|(u, _)| membership[*u] == membership[v])
+                .map(|(_, w)| w)
+                .sum()
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+
+    let mut edge_weights = vec![0.0; n];
+    let mut touched = vec![false; n];
+    let mut neighbour_communities: Vec<usize> = Vec::new();
+    let mut candidates: Vec<(usize, f64)> = Vec::new();
+
+    for v in order {
+        let own = refined[v];
+        let c = membership[v];
+        let a_v = node_weights[v];
+        if refined_sizes[own] != 1
+            || external_weights[own] < scale * a_v * (community_weights[c] - a_v)
+        {
+            continue;
+        }
+
+        for &(u, w) in &graph.neighbours[v] {
+            if membership[u] != c {
+                continue;
+            }
+            let r = refined[u];
+            if !touched[r] {
+                touched[r] = true;
+                neighbour_communities.push(r);
+            }
+            edge_weights[r] += w;
+        }
+
+        candidates.push((own, 0.0));
+        for &r in &neighbour_communities {
+            let r_weight = refined_weights[r];
+            let well_connected =
+                external_weights[r] >= scale * r_weight * (community_weights[c] - r_weight);
+            let gain = edge_weights[r] - scale * a_v * r_weight;
+            if well_connected && gain >= 0.0 {
+                candidates.push((r, gain));
+            }
+        }
+
+        let max_gain = candidates
+            .iter()
+            .fold(f64::NEG_INFINITY, |acc, (_, g)| acc.max(*g));
+        let probs: Vec<f64> = candidates
+            .iter()
+            .map(|(_, g)| ((g - max_gain) / beta).exp())
+            .collect();
+        let mut threshold = rng.random::<f64>() * probs.iter().sum::<f64>();
+        let mut chosen = candidates[candidates.len() - 1].0;
+        for ((r, _), p) in candidates.iter().zip(probs.iter()) {
+            if threshold < *p {
+                chosen = *r;
+                break;
+            }
+            threshold -= p;
+        }
+
+        if chosen != own {
+            refined[v] = chosen;
+            refined_weights[chosen] += a_v;
+            refined_weights[own] = 0.0;
+            refined_sizes[chosen] += 1;
+            refined_sizes[own] = 0;
+            external_weights[chosen] += external_weights[own] - 2.0 * edge_weights[chosen];
+        }
+
+        for &r in &neighbour_communities {
+            edge_weights[r] = 0.0;
+            touched[r] = false;
+        }
+        neighbour_communities.clear();
+        candidates.clear();
+    }
+
+    refined
+}
+
+/// One iteration of the Leiden (or Louvain) algorithm starting from the given
+/// membership. Returns whether any node was moved.
+fn community_iteration(
+    graph: &WeightedGraph,
+    membership: &mut [usize],
+    params: &CommunityParams,
+    scale: f64,
+    rng: &mut StdRng,
+) -> bool {
+    let mut aggregated: Option<WeightedGraph> = None;
+    let mut partition = membership.to_vec();
+    let mut node_map: Vec<usize> = (0..graph.n_nodes).collect();
+    let mut changed = false;
+
+    loop {
+        let current_graph = aggregated.as_ref().unwrap_or(graph);
+        let node_weights = current_graph.node_weights(&params.quality);
+
+        changed |= move_nodes(current_graph, &mut partition, node_weights, scale, rng);
+        let n_communities = renumber(&mut partition);
+        if n_communities == current_graph.n_nodes {
+            break;
+        }
+
+        let mut refined = match params.algorithm {
+            CommunityAlgorithm::Leiden => refine_partition(
+                current_graph,
+                &partition,
+                node_weights,
+                scale,
+                params.beta,
+                rng,
+            ),
+            CommunityAlgorithm::Louvain => partition.clone(),
+        };
+        let n_refined = renumber(&mut refined);
+        if n_refined == current_graph.n_nodes {
+            break;
+        }
+
+        // The aggregated nodes start in the community of their members
+        let mut aggregated_partition = vec![0; n_refined];
+        for (r, c) in refined.iter().zip(partition.iter()) {
+            aggregated_partition[*r] = *c;
+        }
+        for node in node_map.iter_mut() {
+            *node = refined[*node];
+        }
+
+        aggregated = Some(current_graph.aggregate(&refined, n_refined));
+        partition = aggregated_partition;
+    }
+
+    for (c, node) in membership.iter_mut().zip(node_map.iter()) {
+        *c = partition[*node];
+    }
+
+    changed
+}
+
+/// Community detection via the Leiden (Traag, et al., 2019) or Louvain
+/// (Blondel, et al., 2008) algorithm. A negative number of iterations will
+/// run the algorithm until the partition does not change anymore. Returns
+/// the 0-indexed membership.
+pub fn community_detection(
+    graph: &WeightedGraph,
+    params: &CommunityParams,
+    resolution: f64,
+    seed: u64,
+) -> Vec<usize> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut membership: Vec<usize> = (0..graph.n_nodes).collect();
+    if graph.total_weight <= 0.0 {
+        return membership;
+    }
+    let scale = penalty_scale(graph, &params.quality, resolution);
+
+    let mut iter = 0;
+    while params.n_iterations < 0 || iter < params.n_iterations {
+        let changed = community_iteration(graph, &mut membership, params, scale, &mut rng);
+        iter += 1;
+        if !changed && params.n_iterations < 0 {
+            break;
+        }
+    }
+    renumber(&mut membership);
+
+    membership
+}
+
+/// Run the community detection over a set of resolutions in parallel. Every
+/// resolution uses the same seed.
+pub fn community_resolution_sweep(
+    graph: &WeightedGraph,
+    params: &CommunityParams,
+    resolutions: &[f64],
+    seed: u64,
+) -> Vec<CommunityRes> {
+    resolutions
+        .par_iter()
+        .map(|&resolution| {
+            let membership = community_detection(graph, params, resolution, seed);
+            let modularity =
+                partition_quality(graph, &membership, &CommunityQuality::Modularity, 1.0);
+            let quality = partition_quality(graph, &membership, &params.quality, resolution);
+            CommunityRes {
+                membership,
+                modularity,
+                quality,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,195 @@
+use faer::MatRef;
+use memmap2::MmapMut;
+use rayon::prelude::*;
+use std::fs::OpenOptions;
+
+use crate::helpers_linalg::{unit_columns, CorMethod};
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the sparse correlation edges
+#[derive(Clone, Debug)]
+pub struct CorEdges {
+    pub from: Vec<usize>,
+    pub to: Vec<usize>,
+    pub cor: Vec<f64>,
+}
+
+/// Structure for the blocked correlation parameters
+#[derive(Clone, Debug)]
+pub struct BlockedCorParams {
+    pub block_size: usize,
+    pub min_cor: f64,
+    pub top_k: Option<usize>,
+    pub absolute: bool,
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Position of the first element of a row in the flat upper triangle (without
+/// diagonal) of a n x n matrix.
+fn upper_triangle_offset(row: usize, n: usize) -> usize {
+    row * (2 * n - row - 1) / 2
+}
+
+/// Only keep the k candidates with the highest score.
+fn truncate_top_k(candidates: &mut Vec<(usize, f64)>, k: usize, score: &impl Fn(f64) -> f64) {
+    if candidates.len() > k {
+        candidates.select_nth_unstable_by(k, |a, b| score(b.1).total_cmp(&score(a.1)));
+        candidates.truncate(k);
+    }
+}
+
+/// Create the memory-mapped spill file for the upper triangle.
+fn create_spill_file(path: &str, n_values: usize) -> Result<Option<MmapMut>, String> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| format!("Could not create the spill file {}: {}", path, e))?;
+    file.set_len((n_values * 8) as u64)
+        .map_err(|e| format!("Could not allocate the spill file {}: {}", path, e))?;
+    if n_values == 0 {
+        return Ok(None);
+    }
+    // Safety: the file was just created by us and is not modified elsewhere.
+    let mmap = unsafe { MmapMut::map_mut(&file) }
+        .map_err(|e| format!("Could not memory-map the spill file {}: {}", path, e))?;
+
+    Ok(Some(mmap))
+}
+
+//////////
+// Main //
+//////////
+
+/// Calculates the column-wise correlations in tiles of block_size x
+/// block_size without materialising the full correlation matrix. Row blocks
+/// are processed in parallel and every tile is a GEMM of the unit-scaled
+/// columns. Pairs with a (absolute) correlation >= min_cor are returned as
+/// sparse edges; if top_k is provided, only the top_k partners per column are
+/// kept (the union over both partners). Optionally, the full upper triangle
+/// (without diagonal, row-major, native f64) is written to a memory-mapped
+/// file.
+pub fn blocked_correlation(
+    mat: MatRef<f64>,
+    method: &CorMethod,
+    params: &BlockedCorParams,
+    spill_path: Option<&str>,
+) -> Result<CorEdges, String> {
+    let unit = unit_columns(mat, method)
+        .ok_or_else(|| "Kendall's tau cannot be calculated via the blocked engine.".to_string())?;
+    let n = unit.ncols();
+    let block_size = params.block_size.clamp(1, n.max(1));
+    let absolute = params.absolute;
+    let score = move |r: f64| if absolute { r.abs() } else { r };
+
+    let row_blocks: Vec<(usize, usize)> = (0..n)
+        .step_by(block_size)
+        .map(|start| (start, (start + block_size).min(n)))
+        .collect();
+
+    let mut mmap = match spill_path {
+        Some(path) => create_spill_file(path, n * n.saturating_sub(1) / 2)?,
+        None => None,
+    };
+
+    // Every row block writes into its own contiguous part of the spill file
+    let mut chunks: Vec<Option<&mut [u8]>> = Vec::with_capacity(row_blocks.len());
+    match mmap.as_mut() {
+        Some(map) => {
+            let mut rest: &mut [u8] = &mut map[..];
+            for &(start, end) in &row_blocks {
+                let len = (upper_triangle_offset(end, n) - upper_triangle_offset(start, n)) * 8;
+                let (chunk, tail) = rest.split_at_mut(len);
+                chunks.push(Some(chunk));
+                rest = tail;
+            }
+        }
+        None => chunks.extend(row_blocks.iter().map(|_| None)),
+    }
+
+    let block_edges: Vec<Vec<(usize, usize, f64)>> = row_blocks
+        .par_iter()
+        .zip(chunks.into_par_iter())
+        .map(|(&(start, end), mut chunk)| {
+            let rows = unit.get(.., start..end);
+            let chunk_offset = upper_triangle_offset(start, n);
+            // For the top k, the full rows are needed
+            let col_start = if params.top_k.is_some() { 0 } else { start };
+
+            let mut edges: Vec<(usize, usize, f64)> = Vec::new();
+            let mut candidates: Vec<Vec<(usize, f64)>> = vec![Vec::new(); end - start];
+
+            for col_block_start in (col_start..n).step_by(block_size) {
+                let col_block_end = (col_block_start + block_size).min(n);
+                let tile = rows.transpose() * unit.get(.., col_block_start..col_block_end);
+
+                for i in 0..(end - start) {
+                    let gene_i = start + i;
+                    for j in 0..(col_block_end - col_block_start) {
+                        let gene_j = col_block_start + j;
+                        if gene_j == gene_i {
+                            continue;
+                        }
+                        let r = tile[(i, j)];
+                        if gene_j > gene_i {
+                            if let Some(chunk) = chunk.as_deref_mut() {
+                                let idx = upper_triangle_offset(gene_i, n) + gene_j - gene_i - 1;
+                                let pos = (idx - chunk_offset) * 8;
+                                chunk[pos..pos + 8].copy_from_slice(&r.to_ne_bytes());
+                            }
+                        }
+                        // NaN would otherwise rank first in the top k
+                        if !r.is_finite() || score(r) < params.min_cor {
+                            continue;
+                        }
+                        match params.top_k {
+                            Some(_) => candidates[i].push((gene_j, r)),
+                            None if gene_j > gene_i => edges.push((gene_i, gene_j, r)),
+                            None => {}
+                        }
+                    }
+                    if let Some(k) = params.top_k {
+                        truncate_top_k(&mut candidates[i], k, &score);
+                    }
+                }
+            }
+
+            for (i, gene_candidates) in candidates.into_iter().enumerate() {
+                let gene_i = start + i;
+                for (gene_j, r) in gene_candidates {
+                    edges.push((gene_i.min(gene_j), gene_i.max(gene_j), r));
+                }
+            }
+
+            edges
+        })
+        .collect();
+
+    if let Some(map) = mmap.as_ref() {
+        map.flush()
+            .map_err(|e| format!("Could not flush the spill file: {}", e))?;
+    }
+
+    let mut edges: Vec<(usize, usize, f64)> = block_edges.into_iter().flatten().collect();
+    edges.par_sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    edges.dedup_by(|a, b| a.0 == b.0 && a.1 == b.1);
+
+    let mut from = Vec::with_capacity(edges.len());
+    let mut to = Vec::with_capacity(edges.len());
+    let mut cor = Vec::with_capacity(edges.len());
+    for (a, b, r) in edges {
+        from.push(a);
+        to.push(b);
+        cor.push(r);
+    }
+
+    Ok(CorEdges { from, to, cor })
+}
@@ -0,0 +1,826 @@
+use faer::{
+    linalg::solvers::{PartialPivLu, Solve, SolveLstsq},
+    Mat, MatRef,
+};
+use rayon::prelude::*;
+use statrs::distribution::{ContinuousCDF, StudentsT};
+use statrs::function::gamma::digamma;
+use std::collections::HashMap;
+
+use crate::utils_rust::{median, quantile, rank_vector};
+use crate::utils_stats::{approx_linear, lowess, trigamma, trigamma_inverse};
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the normalisation factor methods
+#[derive(Clone, Debug)]
+pub enum NormFactorMethod {
+    Tmm,
+    UpperQuartile,
+    Rle,
+    None,
+}
+
+/// Parsing the normalisation factor method
+pub fn parse_norm_factor_method(s: &str) -> Option<NormFactorMethod> {
+    match s.to_lowercase().as_str() {
+        "tmm" => Some(NormFactorMethod::Tmm),
+        "upperquartile" => Some(NormFactorMethod::UpperQuartile),
+        "rle" => Some(NormFactorMethod::Rle),
+        "none" => Some(NormFactorMethod::None),
+        _ => None,
+    }
+}
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the TMM parameters. logratio_trim and sum_trim are the
+/// proportions trimmed from both sides of the log ratios (M-values) and the
+/// absolute expression (A-values), a_cutoff the minimum A-value of a gene.
+#[derive(Clone, Debug)]
+pub struct TmmParams {
+    pub logratio_trim: f64,
+    pub sum_trim: f64,
+    pub do_weighting: bool,
+    pub a_cutoff: f64,
+}
+
+/// Structure for the linear model fit of a single gene with the contrasts
+/// already applied.
+#[derive(Clone, Debug)]
+pub struct GeneFit {
+    pub coefficients: Vec<f64>,
+    pub stdev_unscaled: Vec<f64>,
+    pub sigma: f64,
+    pub df_residual: f64,
+    pub amean: f64,
+}
+
+/// Structure for the linear model fit over all genes. The matrices are genes x
+/// contrasts.
+#[derive(Clone, Debug)]
+pub struct LinearModelFit {
+    pub coefficients: Mat<f64>,
+    pub stdev_unscaled: Mat<f64>,
+    pub sigma: Vec<f64>,
+    pub df_residual: Vec<f64>,
+    pub amean: Vec<f64>,
+}
+
+/// Structure for the empirical Bayes moderated statistics. The matrices are
+/// genes x contrasts.
+#[derive(Clone, Debug)]
+pub struct EbayesRes {
+    pub t: Mat<f64>,
+    pub pvals: Mat<f64>,
+    pub lods: Mat<f64>,
+    pub s2_post: Vec<f64>,
+    pub df_total: Vec<f64>,
+    pub s2_prior: f64,
+    pub df_prior: f64,
+    pub var_prior: Vec<f64>,
+}
+
+/// Structure for the voom results. The matrices are genes x samples.
+#[derive(Clone, Debug)]
+pub struct VoomRes {
+    pub e: Mat<f64>,
+    pub weights: Mat<f64>,
+    pub sample_weights: Vec<f64>,
+}
+
+/////////////////////
+// Linear modeling //
+/////////////////////
+
+/// Inverse of an upper triangular matrix via back substitution.
+fn upper_triangular_inverse(r: MatRef<f64>) -> Mat<f64> {
+    let p = r.nrows();
+    let mut inv = Mat::<f64>::zeros(p, p);
+    for j in 0..p {
+        inv[(j, j)] = 1.0 / r[(j, j)];
+        for i in (0..j).rev() {
+            let sum: f64 = (i + 1..=j).map(|k| r[(i, k)] * inv[(k, j)]).sum();
+            inv[(i, j)] = -sum / r[(i, i)];
+        }
+    }
+    inv
+}
+
+/// Checks if the design matrix has full column rank based on the diagonal of
+/// the R factor of the QR decomposition.
+pub fn design_is_full_rank(design: MatRef<f64>) -> bool {
+    if design.nrows() < design.ncols() {
+        return false;
+    }
+    let qr = design.qr();
+    let r = qr.thin_R();
+    let max_diag = (0..r.ncols()).map(|i| r[(i, i)].abs()).fold(0.0, f64::max);
+
+    (0..r.ncols()).all(|i| r[(i, i)].abs() > 1e-7 * max_diag)
+}
+
+/// Fits the linear model for a single gene via a QR decomposition of the
+/// (weighted) design matrix and applies the contrasts. Missing values are
+/// removed before fitting.
+pub fn fit_gene(
+    y: &[f64],
+    design: MatRef<f64>,
+    weights: Option<&[f64]>,
+    contrasts: MatRef<f64>,
+) -> GeneFit {
+    let n_coef = design.ncols();
+    let n_contrasts = contrasts.ncols();
+
+    let obs: Vec<usize> = (0..y.len())
+        .filter(|&i| y[i].is_finite() && weights.is_none_or(|w| w[i].is_finite() && w[i] > 0.0))
+        .collect();
+    let amean = obs.iter().map(|&i| y[i]).sum::<f64>() / obs.len() as f64;
+
+    if obs.len() < n_coef {
+        return GeneFit {
+            coefficients: vec![f64::NAN; n_contrasts],
+            stdev_unscaled: vec![f64::NAN; n_contrasts],
+            sigma: f64::NAN,
+            df_residual: 0.0,
+            amean,
+        };
+    }
+
+    let sqrt_w: Vec<f64> = obs
+        .iter()
+        .map(|&i| weights.map_or(1.0, |w| w[i].sqrt()))
+        .collect();
+    let x = Mat::from_fn(obs.len(), n_coef, |i, j| sqrt_w[i] * design[(obs[i], j)]);
+    let y_w = Mat::from_fn(obs.len(), 1, |i, _| sqrt_w[i] * y[obs[i]]);
+
+    let qr = x.qr();
+    let beta = qr.solve_lstsq(&y_w);
+
+    let fitted = &x * &beta;
+    let rss: f64 = (0..obs.len())
+        .map(|i| (y_w[(i, 0)] - fitted[(i, 0)]).powi(2))
+        .sum();
+    let df_residual = (obs.len() - n_coef) as f64;
+    let sigma = if df_residual > 0.0 {
+        (rss / df_residual).sqrt()
+    } else {
+        f64::NAN
+    };
+
+    // Unscaled covariance of the coefficients: (X'WX)^-1 = R^-1 R^-T
+    let r_inv = upper_triangular_inverse(qr.thin_R());
+    let cov_unscaled = &r_inv * r_inv.transpose();
+
+    let coefficients = contrasts.transpose() * &beta;
+    let cov_contrasts = contrasts.transpose() * &cov_unscaled * contrasts;
+
+    GeneFit {
+        coefficients: (0..n_contrasts).map(|j| coefficients[(j, 0)]).collect(),
+        stdev_unscaled: (0..n_contrasts)
+            .map(|j| cov_contrasts[(j, j)].sqrt())
+            .collect(),
+        sigma,
+        df_residual,
+        amean,
+    }
+}
+
+/// Fits the linear model for all genes (rows of y) in parallel. The contrasts
+/// matrix is coefficients x contrasts; use the identity matrix to return the
+/// coefficients of the design.
+pub fn lm_fit(
+    y: MatRef<f64>,
+    design: MatRef<f64>,
+    weights: Option<MatRef<f64>>,
+    contrasts: MatRef<f64>,
+) -> LinearModelFit {
+    let n_genes = y.nrows();
+    let n_contrasts = contrasts.ncols();
+
+    let fits: Vec<GeneFit> = (0..n_genes)
+        .into_par_iter()
+        .map(|i| {
+            let y_i: Vec<f64> = y.row(i).iter().copied().collect();
+            let w_i: Option<Vec<f64>> = weights.map(|w| w.row(i).iter().copied().collect());
+            fit_gene(&y_i, design, w_i.as_deref(), contrasts)
+        })
+        .collect();
+
+    LinearModelFit {
+        coefficients: Mat::from_fn(n_genes, n_contrasts, |i, j| fits[i].coefficients[j]),
+        stdev_unscaled: Mat::from_fn(n_genes, n_contrasts, |i, j| fits[i].stdev_unscaled[j]),
+        sigma: fits.iter().map(|f| f.sigma).collect(),
+        df_residual: fits.iter().map(|f| f.df_residual).collect(),
+        amean: fits.iter().map(|f| f.amean).collect(),
+    }
+}
+
+/////////////////////
+// Empirical Bayes //
+/////////////////////
+
+/// Moment estimation of the scaled F-distribution parameters of the sample
+/// variances (limma::fitFDist() without covariate). Returns the prior
+/// variance (scale) and the prior degrees of freedom.
+pub fn fit_f_dist(x: &[f64], df1: &[f64]) -> (f64, f64) {
+    let (x, df1): (Vec<f64>, Vec<f64>) = x
+        .iter()
+        .zip(df1)
+        .filter(|(x, d)| x.is_finite() && **x > -1e-15 && d.is_finite() && **d > 1e-15)
+        .map(|(x, d)| (x.max(0.0), *d))
+        .unzip();
+    let n = x.len();
+
+    if n == 0 {
+        return (f64::NAN, f64::NAN);
+    }
+    if n == 1 {
+        return (x[0], 0.0);
+    }
+
+    // Avoid exactly zero values
+    let mut m = median(&x);
+    if m == 0.0 {
+        m = 1.0;
+    }
+
+    let e: Vec<f64> = x
+        .iter()
+        .zip(&df1)
+        .map(|(x, d)| x.max(1e-5 * m).ln() - digamma(d / 2.0) + (d / 2.0).ln())
+        .collect();
+    let e_mean = e.iter().sum::<f64>() / n as f64;
+    let e_var = e.iter().map(|e| (e - e_mean).powi(2)).sum::<f64>() / (n - 1) as f64
+        - df1.iter().map(|d| trigamma(d / 2.0)).sum::<f64>() / n as f64;
+
+    if e_var > 0.0 {
+        let df2 = 2.0 * trigamma_inverse(e_var);
+        let s20 = (e_mean + digamma(df2 / 2.0) - (df2 / 2.0).ln()).exp();
+        (s20, df2)
+    } else {
+        (e_mean.exp(), f64::INFINITY)
+    }
+}
+
+/// Empirical Bayes moderation of the gene-wise variances (limma::squeezeVar()).
+/// Returns the posterior variances, the prior variance and the prior degrees
+/// of freedom.
+pub fn squeeze_var(var: &[f64], df: &[f64]) -> (Vec<f64>, f64, f64) {
+    let (var_prior, df_prior) = fit_f_dist(var, df);
+
+    let var_post = var
+        .iter()
+        .zip(df)
+        .map(|(v, d)| {
+            if df_prior.is_infinite() || !v.is_finite() || *d == 0.0 {
+                var_prior
+            } else {
+                (d * v + df_prior * var_prior) / (d + df_prior)
+            }
+        })
+        .collect();
+
+    (var_post, var_prior, df_prior)
+}
+
+/// Estimates the prior variance of the non-zero coefficients from the top
+/// moderated t-statistics (limma's tmixture.vector()).
+fn tmixture_vector(
+    t: &[f64],
+    stdev_unscaled: &[f64],
+    df: &[f64],
+    proportion: f64,
+    v0_lim: (f64, f64),
+) -> f64 {
+    let (mut t, (v1, mut df)): (Vec<f64>, (Vec<f64>, Vec<f64>)) = t
+        .iter()
+        .zip(stdev_unscaled.iter().zip(df))
+        // The t-distribution is not defined for non-positive degrees of freedom
+        .filter(|(t, (_, d))| t.is_finite() && **d > 0.0)
+        .map(|(t, (s, d))| (t.abs(), (s.powi(2), *d)))
+        .unzip();
+
+    let n_genes = t.len();
+    let n_target = (proportion / 2.0 * n_genes as f64).ceil() as usize;
+    if n_target < 1 {
+        return f64::NAN;
+    }
+    let p = (n_target as f64 / n_genes as f64).max(proportion);
+
+    // The method requires equal degrees of freedom
+    let max_df = df.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let t_dist_max = StudentsT::new(0.0, 1.0, max_df).unwrap();
+    for i in 0..n_genes {
+        if df[i] < max_df {
+            let tail_p = StudentsT::new(0.0, 1.0, df[i]).unwrap().sf(t[i]);
+            if tail_p > 0.0 {
+                t[i] = -t_dist_max.inverse_cdf(tail_p);
+            }
+            df[i] = max_df;
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n_genes).collect();
+    order.sort_by(|&a, &b| t[b].partial_cmp(&t[a]).unwrap_or(std::cmp::Ordering::Equal));
+
+    let v0_sum: f64 = order[..n_target]
+        .iter()
+        .enumerate()
+        .map(|(r, &i)| {
+            let p0 = 2.0 * t_dist_max.sf(t[i]);
+            let p_target = ((r as f64 + 0.5) / n_genes as f64 - (1.0 - p) * p0) / p;
+            let v0 = if p_target > p0 {
+                let q_target = -t_dist_max.inverse_cdf(p_target / 2.0);
+                v1[i] * ((t[i] / q_target).powi(2) - 1.0)
+            } else {
+                0.0
+            };
+            v0.clamp(v0_lim.0, v0_lim.1)
+        })
+        .sum();
+
+    v0_sum / n_target as f64
+}
+
+/// Empirical Bayes moderated t-statistics, p-values and log-odds of
+/// differential expression (limma::eBayes() without trend and robust options).
+pub fn ebayes(fit: &LinearModelFit, proportion: f64) -> EbayesRes {
+    let n_genes = fit.coefficients.nrows();
+    let n_contrasts = fit.coefficients.ncols();
+
+    let s2: Vec<f64> = fit.sigma.iter().map(|s| s.powi(2)).collect();
+    let (s2_post, s2_prior, df_prior) = squeeze_var(&s2, &fit.df_residual);
+
+    let df_pooled: f64 = fit.df_residual.iter().sum();
+    let df_total: Vec<f64> = fit
+        .df_residual
+        .iter()
+        .map(|d| (d + df_prior).min(df_pooled))
+        .collect();
+
+    let t = Mat::from_fn(n_genes, n_contrasts, |i, j| {
+        fit.coefficients[(i, j)] / fit.stdev_unscaled[(i, j)] / s2_post[i].sqrt()
+    });
+    let pvals = Mat::from_fn(n_genes, n_contrasts, |i, j| {
+        // Non-positive degrees of freedom give an error and hence NaN
+        match StudentsT::new(0.0, 1.0, df_total[i]) {
+            Ok(t_dist) if t[(i, j)].is_finite() => 2.0 * t_dist.sf(t[(i, j)].abs()),
+            _ => f64::NAN,
+        }
+    });
+
+    // Prior variance of the non-zero coefficients
+    let v0_lim = (0.1_f64.powi(2) / s2_prior, 4.0_f64.powi(2) / s2_prior);
+    let var_prior: Vec<f64> = (0..n_contrasts)
+        .map(|j| {
+            let t_j: Vec<f64> = t.col(j).iter().copied().collect();
+            let s_j: Vec<f64> = fit.stdev_unscaled.col(j).iter().copied().collect();
+            let v0 = tmixture_vector(&t_j, &s_j, &df_total, proportion, v0_lim);
+            if v0.is_nan() {
+                1.0 / s2_prior
+            } else {
+                v0
+            }
+        })
+        .collect();
+
+    // Log-odds of differential expression (B-statistic)
+    let lods = Mat::from_fn(n_genes, n_contrasts, |i, j| {
+        let stdev_sq = fit.stdev_unscaled[(i, j)].powi(2);
+        let r = (stdev_sq + var_prior[j]) / stdev_sq;
+        let t2 = t[(i, j)].powi(2);
+        let kernel = if df_prior > 1e6 {
+            t2 * (1.0 - 1.0 / r) / 2.0
+        } else {
+            (1.0 + df_total[i]) / 2.0 * ((t2 + df_total[i]) / (t2 / r + df_total[i])).ln()
+        };
+        (proportion / (1.0 - proportion)).ln() - r.ln() / 2.0 + kernel
+    });
+
+    EbayesRes {
+        t,
+        pvals,
+        lods,
+        s2_post,
+        df_total,
+        s2_prior,
+        df_prior,
+        var_prior,
+    }
+}
+
+///////////////////////
+// Precision weights //
+///////////////////////
+
+/// Log2 counts per million with an offset of 0.5 to the counts and 1 to the
+/// library sizes (as used by voom).
+pub fn voom_log_cpm(counts: MatRef<f64>, lib_size: &[f64]) -> Mat<f64> {
+    Mat::from_fn(counts.nrows(), counts.ncols(), |i, j| {
+        ((counts[(i, j)] + 0.5) / (lib_size[j] + 1.0) * 1e6).log2()
+    })
+}
+
+/// Estimates the mean-variance trend of the log counts and transforms it into
+/// observation-level precision weights (limma::voom()). A lowess curve is fit
+/// to the square root of the residual standard deviations against the average
+/// log count; the weights are the inverse of the predicted variance for the
+/// fitted log count of each observation. Optional sample weights are used
+/// during the linear model fit. If fewer than 2 genes have residual degrees of
+/// freedom, all weights are set to 1. Returns the log CPM values and the
+/// weights.
+pub fn voom(
+    counts: MatRef<f64>,
+    lib_size: &[f64],
+    design: MatRef<f64>,
+    sample_weights: Option<&[f64]>,
+    span: f64,
+) -> (Mat<f64>, Mat<f64>) {
+    let (n_genes, n_samples) = counts.shape();
+    let e = voom_log_cpm(counts, lib_size);
+
+    let weight_mat = sample_weights.map(|w| Mat::from_fn(n_genes, n_samples, |_, j| w[j]));
+    let identity = Mat::<f64>::identity(design.ncols(), design.ncols());
+    let fit = lm_fit(
+        e.as_ref(),
+        design,
+        weight_mat.as_ref().map(|w| w.as_ref()),
+        identity.as_ref(),
+    );
+
+    // Without replication there is no variance to model; like limma, fall back
+    // to unit weights if fewer than 2 genes have residual degrees of freedom
+    let n_with_reps = fit.df_residual.iter().filter(|df| **df > 0.0).count();
+    if n_with_reps < 2 {
+        return (e, Mat::from_fn(n_genes, n_samples, |_, _| 1.0));
+    }
+
+    // Mean-variance trend on the genes with any counts (and a residual SD)
+    let log_lib_size: Vec<f64> = lib_size.iter().map(|l| (l + 1.0).log2()).collect();
+    let mean_log_lib_size = log_lib_size.iter().sum::<f64>() / n_samples as f64;
+    let mut trend_points: Vec<(f64, f64)> = (0..n_genes)
+        .filter(|&i| counts.row(i).iter().sum::<f64>() > 0.0 && fit.sigma[i].is_finite())
+        .map(|i| {
+            (
+                fit.amean[i] + mean_log_lib_size - 1e6_f64.log2(),
+                fit.sigma[i].sqrt(),
+            )
+        })
+        .collect();
+    trend_points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    let (sx, sy): (Vec<f64>, Vec<f64>) = trend_points.into_iter().unzip();
+    let trend = lowess(&sx, &sy, span, 3);
+
+    // Predicted variance of the fitted log counts
+    let weights_rows: Vec<Vec<f64>> = (0..n_genes)
+        .into_par_iter()
+        .map(|i| {
+            let fitted_log_count: Vec<f64> = (0..n_samples)
+                .map(|j| {
+                    let fitted: f64 = (0..design.ncols())
+                        .map(|k| fit.coefficients[(i, k)] * design[(j, k)])
+                        .sum();
+                    fitted + log_lib_size[j] - 1e6_f64.log2()
+                })
+                .collect();
+            approx_linear(&sx, &trend, &fitted_log_count)
+                .iter()
+                .map(|s| 1.0 / s.powi(4))
+                .collect()
+        })
+        .collect();
+
+    let weights = Mat::from_fn(n_genes, n_samples, |i, j| weights_rows[i][j]);
+
+    (e, weights)
+}
+
+/// Estimates the relative quality weights of the samples via the gene-by-gene
+/// update algorithm (Ritchie et al., 2006). The log variance of each sample is
+/// modelled with sum-to-zero contrasts and updated with a Fisher scoring step
+/// for each gene, with the information accumulated over the genes and
+/// initialised with prior_n genes. Genes with missing values or fewer than 2
+/// residual degrees of freedom are skipped. Returns the sample weights.
+pub fn array_weights(
+    y: MatRef<f64>,
+    design: MatRef<f64>,
+    weights: Option<MatRef<f64>>,
+    prior_n: f64,
+) -> Vec<f64> {
+    let (n_genes, n_samples) = y.shape();
+    let n_coef = design.ncols();
+    if n_samples < 2 {
+        return vec![1.0; n_samples];
+    }
+
+    // Sum-to-zero contrasts for the variance model
+    let z = Mat::from_fn(n_samples, n_samples - 1, |i, k| {
+        if i == k {
+            1.0
+        } else if i == n_samples - 1 {
+            -1.0
+        } else {
+            0.0
+        }
+    });
+    let ztz = z.transpose() * &z;
+    let mut info = Mat::from_fn(ztz.nrows(), ztz.ncols(), |i, j| prior_n * ztz[(i, j)]);
+    let mut gamma = Mat::<f64>::zeros(n_samples - 1, 1);
+
+    for i in 0..n_genes {
+        let log_var = &z * &gamma;
+        let w: Vec<f64> = (0..n_samples)
+            .map(|j| weights.map_or(1.0, |w| w[(i, j)]) / log_var[(j, 0)].exp())
+            .collect();
+        let valid = (0..n_samples).all(|j| y[(i, j)].is_finite() && w[j].is_finite() && w[j] > 0.0);
+        if !valid || n_samples < n_coef + 2 {
+            continue;
+        }
+
+        let x = Mat::from_fn(n_samples, n_coef, |j, k| w[j].sqrt() * design[(j, k)]);
+        let y_w = Mat::from_fn(n_samples, 1, |j, _| w[j].sqrt() * y[(i, j)]);
+        let qr = x.qr();
+        let beta = qr.solve_lstsq(&y_w);
+        let residuals = &y_w - &x * &beta;
+        let s2 = (0..n_samples)
+            .map(|j| residuals[(j, 0)].powi(2))
+            .sum::<f64>()
+            / (n_samples - n_coef) as f64;
+        if s2 < 1e-15 {
+            continue;
+        }
+
+        // Hat matrix of the weighted fit
+        let q = &x * upper_triangular_inverse(qr.thin_R());
+        let hat = &q * q.transpose();
+
+        let a = Mat::from_fn(n_samples, n_samples, |j, k| {
+            let delta = if j == k { 1.0 } else { 0.0 };
+            (delta - hat[(j, k)]).powi(2)
+        });
+        info += z.transpose() * &a * &z;
+
+        let score_terms = Mat::from_fn(n_samples, 1, |j, _| {
+            residuals[(j, 0)].powi(2) / s2 - 1.0 + hat[(j, j)]
+        });
+        let score = z.transpose() * &score_terms;
+        gamma += PartialPivLu::new(info.as_ref()).solve(&score);
+    }
+
+    let log_var = &z * &gamma;
+    (0..n_samples).map(|j| (-log_var[(j, 0)]).exp()).collect()
+}
+
+/// voom with additional sample quality weights (limma::voomWithQualityWeights()).
+/// voom and the estimation of the sample weights are run twice; the final
+/// weights are the product of the precision weights and the sample weights.
+pub fn voom_quality_weights(
+    counts: MatRef<f64>,
+    lib_size: &[f64],
+    design: MatRef<f64>,
+    span: f64,
+    prior_n: f64,
+) -> VoomRes {
+    let (e, weights) = voom(counts, lib_size, design, None, span);
+    let sample_weights = array_weights(e.as_ref(), design, Some(weights.as_ref()), prior_n);
+    let (e, weights) = voom(counts, lib_size, design, Some(&sample_weights), span);
+    let sample_weights = array_weights(e.as_ref(), design, Some(weights.as_ref()), prior_n);
+
+    let weights = Mat::from_fn(weights.nrows(), weights.ncols(), |i, j| {
+        weights[(i, j)] * sample_weights[j]
+    });
+
+    VoomRes {
+        e,
+        weights,
+        sample_weights,
+    }
+}
+
+/////////////////////////
+// Count normalisation //
+/////////////////////////
+
+/// Upper-quartile (or any other quantile) of each sample divided by the
+/// library size.
+fn quantile_factors(counts: MatRef<f64>, lib_size: &[f64], prob: f64) -> Vec<f64> {
+    (0..counts.ncols())
+        .map(|j| {
+            let col: Vec<f64> = counts.col(j).iter().copied().collect();
+            quantile(&col, prob) / lib_size[j]
+        })
+        .collect()
+}
+
+/// DESeq-style relative log expression size factors: the median ratio of each
+/// sample to the geometric mean over the samples. Genes with any zero count
+/// are ignored.
+fn rle_factors(counts: MatRef<f64>) -> Vec<f64> {
+    let geo_means: Vec<f64> = (0..counts.nrows())
+        .map(|i| {
+            let log_sum: f64 = counts.row(i).iter().map(|c| c.ln()).sum();
+            (log_sum / counts.ncols() as f64).exp()
+        })
+        .collect();
+
+    (0..counts.ncols())
+        .map(|j| {
+            let ratios: Vec<f64> = (0..counts.nrows())
+                .filter(|&i| geo_means[i] > 0.0)
+                .map(|i| counts[(i, j)] / geo_means[i])
+                .collect();
+            median(&ratios)
+        })
+        .collect()
+}
+
+/// Trimmed mean of M-values of one sample against the reference sample
+/// (edgeR's .calcFactorTMM()). The log ratios are weighted by the inverse of
+/// their approximate asymptotic variances if do_weighting is set.
+fn tmm_factor(obs: &[f64], reference: &[f64], lib_sizes: (f64, f64), params: &TmmParams) -> f64 {
+    let (lib_obs, lib_ref) = lib_sizes;
+
+    let mut log_r = Vec::with_capacity(obs.len());
+    let mut abs_e = Vec::with_capacity(obs.len());
+    let mut variance = Vec::with_capacity(obs.len());
+    for (o, r) in obs.iter().zip(reference) {
+        let m = ((o / lib_obs) / (r / lib_ref)).log2();
+        let a = ((o / lib_obs).log2() + (r / lib_ref).log2()) / 2.0;
+        if m.is_finite() && a.is_finite() && a > params.a_cutoff {
+            log_r.push(m);
+            abs_e.push(a);
+            variance.push((lib_obs - o) / lib_obs / o + (lib_ref - r) / lib_ref / r);
+        }
+    }
+
+    if log_r.iter().all(|m| m.abs() < 1e-6) {
+        return 1.0;
+    }
+
+    let n = log_r.len() as f64;
+    let lo_l = (n * params.logratio_trim).floor() + 1.0;
+    let hi_l = n + 1.0 - lo_l;
+    let lo_s = (n * params.sum_trim).floor() + 1.0;
+    let hi_s = n + 1.0 - lo_s;
+
+    let rank_r = rank_vector(&log_r);
+    let rank_e = rank_vector(&abs_e);
+    let keep: Vec<usize> = (0..log_r.len())
+        .filter(|&i| {
+            rank_r[i] >= lo_l && rank_r[i] <= hi_l && rank_e[i] >= lo_s && rank_e[i] <= hi_s
+        })
+        .collect();
+
+    let f = if params.do_weighting {
+        let num: f64 = keep.iter().map(|&i| log_r[i] / variance[i]).sum();
+        let denom: f64 = keep.iter().map(|&i| 1.0 / variance[i]).sum();
+        num / denom
+    } else {
+        keep.iter().map(|&i| log_r[i]).sum::<f64>() / keep.len() as f64
+    };
+
+    if f.is_nan() {
+        1.0
+    } else {
+        f.exp2()
+    }
+}
+
+/// Calculates the normalisation factors of the samples (edgeR::normLibSizes()).
+/// Genes with zero counts in all samples are removed beforehand. For TMM, the
+/// reference sample is the one with the upper quartile closest to the mean
+/// upper quartile. The factors are scaled to multiply to one.
+pub fn calc_norm_factors(
+    counts: MatRef<f64>,
+    method: &NormFactorMethod,
+    tmm_params: &TmmParams,
+) -> Vec<f64> {
+    let n_samples = counts.ncols();
+    let lib_size: Vec<f64> = (0..n_samples).map(|j| counts.col(j).sum()).collect();
+
+    let non_zero: Vec<usize> = (0..counts.nrows())
+        .filter(|&i| counts.row(i).iter().any(|&c| c > 0.0))
+        .collect();
+    if non_zero.is_empty() || n_samples == 1 {
+        return vec![1.0; n_samples];
+    }
+    let x = Mat::from_fn(non_zero.len(), n_samples, |i, j| counts[(non_zero[i], j)]);
+
+    let factors = match method {
+        NormFactorMethod::None => return vec![1.0; n_samples],
+        NormFactorMethod::UpperQuartile => quantile_factors(x.as_ref(), &lib_size, 0.75),
+        NormFactorMethod::Rle => rle_factors(x.as_ref())
+            .iter()
+            .zip(&lib_size)
+            .map(|(f, l)| f / l)
+            .collect(),
+        NormFactorMethod::Tmm => {
+            // Samples with a library size of zero have no defined upper
+            // quartile and cannot serve as reference
+            let f75 = quantile_factors(x.as_ref(), &lib_size, 0.75);
+            let finite: Vec<usize> = (0..n_samples).filter(|&j| f75[j].is_finite()).collect();
+            let finite_f75: Vec<f64> = finite.iter().map(|&j| f75[j]).collect();
+            let ref_col = if median(&finite_f75) < 1e-20 {
+                let sqrt_sums: Vec<f64> = (0..n_samples)
+                    .map(|j| x.col(j).iter().map(|c| c.sqrt()).sum())
+                    .collect();
+                (0..n_samples)
+                    .max_by(|&a, &b| sqrt_sums[a].total_cmp(&sqrt_sums[b]))
+                    .unwrap()
+            } else {
+                let mean_f75 = finite_f75.iter().sum::<f64>() / finite_f75.len() as f64;
+                finite
+                    .iter()
+                    .copied()
+                    .min_by(|&a, &b| {
+                        (f75[a] - mean_f75)
+                            .abs()
+                            .total_cmp(&(f75[b] - mean_f75).abs())
+                    })
+                    .unwrap()
+            };
+            let reference: Vec<f64> = x.col(ref_col).iter().copied().collect();
+
+            (0..n_samples)
+                .into_par_iter()
+                .map(|j| {
+                    let obs: Vec<f64> = x.col(j).iter().copied().collect();
+                    tmm_factor(
+                        &obs,
+                        &reference,
+                        (lib_size[j], lib_size[ref_col]),
+                        tmm_params,
+                    )
+                })
+                .collect()
+        }
+    };
+
+    let log_mean = factors.iter().map(|f| f.ln()).sum::<f64>() / n_samples as f64;
+    factors.iter().map(|f| f / log_mean.exp()).collect()
+}
+
+/// Counts per million (edgeR::cpm()). On the log2 scale, a prior count scaled
+/// by the relative library size is added to the counts and twice the scaled
+/// prior count to the library sizes.
+pub fn cpm(counts: MatRef<f64>, lib_size: &[f64], log: bool, prior_count: f64) -> Mat<f64> {
+    if !log {
+        return Mat::from_fn(counts.nrows(), counts.ncols(), |i, j| {
+            counts[(i, j)] / lib_size[j] * 1e6
+        });
+    }
+    let mean_lib_size = lib_size.iter().sum::<f64>() / lib_size.len() as f64;
+    let prior_scaled: Vec<f64> = lib_size
+        .iter()
+        .map(|l| l / mean_lib_size * prior_count)
+        .collect();
+
+    Mat::from_fn(counts.nrows(), counts.ncols(), |i, j| {
+        let lib_size_adj = lib_size[j] + 2.0 * prior_scaled[j];
+        ((counts[(i, j)] + prior_scaled[j]) / lib_size_adj).log2() + 1e6_f64.log2()
+    })
+}
+
+/// Identifies the genes with sufficiently large counts to be retained in the
+/// statistical analysis (edgeR::filterByExpr()). A gene is kept if it has a
+/// CPM above min_count / median library size in at least the size of the
+/// smallest group (interpolated by min_prop beyond large_n) and a total count
+/// of at least min_total_count. The count thresholds are given as
+/// (min_count, min_total_count) and the sample size parameters as
+/// (large_n, min_prop).
+pub fn filter_by_expr(
+    counts: MatRef<f64>,
+    group: &[String],
+    lib_size: &[f64],
+    count_thresholds: (f64, f64),
+    sample_size_params: (f64, f64),
+) -> Vec<bool> {
+    let (min_count, min_total_count) = count_thresholds;
+    let (large_n, min_prop) = sample_size_params;
+    let tol = 1e-14;
+
+    let mut group_sizes: HashMap<&String, usize> = HashMap::new();
+    for g in group {
+        *group_sizes.entry(g).or_insert(0) += 1;
+    }
+    let mut min_sample_size = *group_sizes.values().min().unwrap_or(&0) as f64;
+    if min_sample_size > large_n {
+        min_sample_size = large_n + (min_sample_size - large_n) * min_prop;
+    }
+
+    let cpm_cutoff = min_count / median(lib_size) * 1e6;
+    let cpm_mat = cpm(counts, lib_size, false, 0.0);
+
+    (0..counts.nrows())
+        .map(|i| {
+            let n_above = cpm_mat.row(i).iter().filter(|&&c| c >= cpm_cutoff).count() as f64;
+            let total: f64 = counts.row(i).sum();
+            n_above >= min_sample_size - tol && total >= min_total_count - tol
+        })
+        .collect()
+}
@@ -1,7 +1,7 @@
-// use statrs::distribution::{Discrete, Hypergeometric};
-use statrs::function::gamma::ln_gamma;
 use std::collections::HashSet;
 
+use crate::utils_hypergeom::phyper;
+
 ///////////
 // Types //
 ///////////
@@ -13,55 +13,9 @@ pub type HypergeomResult = (Vec<f64>, Vec<f64>, Vec<u64>, Vec<u64>);
 // Functions //
 ///////////////
 
-/// Calculate the p-value of a hypergeometric test.
+/// Calculate the p-value of a hypergeometric test, i.e., P(X > q).
 pub fn hypergeom_pval(q: u64, m: u64, n: u64, k: u64) -> f64 {
-    if q == 0 {
-        1.0
-    } else {
-        let population = m + n;
-
-        // Always use logarithmic calculation to avoid numerical issues
-        // Convert to f64 once at the start
-        let (n_f, m_f, k_f) = (n as f64, m as f64, k as f64);
-        let population_f = population as f64;
-
-        // Calculate P(X > q) in log space
-        let upper = k.min(m);
-
-        // Use log space to compute probabilities for each value i > q
-        let mut log_probs = Vec::new();
-        for i in (q + 1)..=upper {
-            let i_f = i as f64;
-
-            // Calculate log(PMF(i)) using logarithms
-            let log_pmf = ln_gamma(m_f + 1.0) - ln_gamma(i_f + 1.0) - ln_gamma(m_f - i_f + 1.0)
-                + ln_gamma(n_f + 1.0)
-                - ln_gamma(k_f - i_f + 1.0)
-                - ln_gamma(n_f - (k_f - i_f) + 1.0)
-                - (ln_gamma(population_f + 1.0)
-                    - ln_gamma(k_f + 1.0)
-                    - ln_gamma(population_f - k_f + 1.0));
-
-            log_probs.push(log_pmf);
-        }
-
-        // If there are no probabilities to sum, return 0
-        if log_probs.is_empty() {
-            return 0.0;
-        }
-
-        // Use log-sum-exp trick to calculate sum without overflow
-        let max_log_prob = log_probs.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-
-        // Sum with adjustment to avoid numerical issues
-        let mut sum = 0.0;
-        for log_p in log_probs {
-            sum += (log_p - max_log_prob).exp();
-        }
-
-        // Final result
-        sum * max_log_prob.exp()
-    }
+    phyper(q as i64, m, n, k, false, false)
 }
 
 /// Calculate odds ratios
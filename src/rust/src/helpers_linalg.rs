@@ -2,6 +2,7 @@ use faer::{Mat, MatRef};
 use rand::prelude::*;
 use rand_distr::Normal;
 use rayon::iter::*;
+use statrs::distribution::{ContinuousCDF, Normal as NormalDist, StudentsT};
 
 use crate::utils_rust::*;
 use crate::utils_stats::*;
@@ -10,6 +11,26 @@ use crate::utils_stats::*;
 // ENUMS, TYPES, STRUCTURES //
 //////////////////////////////
 
+/// Enum for the correlation methods. Bicor stores maxPOutliers.
+#[derive(Clone, Debug)]
+pub enum CorMethod {
+    Pearson,
+    Spearman,
+    Bicor(f64),
+    Kendall,
+}
+
+/// Parsing the correlation method
+pub fn parse_cor_method(s: &str, max_p_outliers: f64) -> Option<CorMethod> {
+    match s.to_lowercase().as_str() {
+        "pearson" => Some(CorMethod::Pearson),
+        "spearman" => Some(CorMethod::Spearman),
+        "bicor" => Some(CorMethod::Bicor(max_p_outliers)),
+        "kendall" => Some(CorMethod::Kendall),
+        _ => None,
+    }
+}
+
 /// Structure for random SVD results
 #[derive(Clone, Debug)]
 pub struct RandomSvdResults {
@@ -27,6 +48,17 @@ pub struct DiffCorRes {
     pub p_vals: Vec<f64>,
 }
 
+/// Structure for pairwise-complete correlation results in upper triangle
+/// layout
+#[derive(Clone, Debug)]
+pub struct PairwiseCorRes {
+    pub cor: Vec<f64>,
+    pub n: Vec<usize>,
+    pub p_vals: Vec<f64>,
+    pub ci_lower: Vec<f64>,
+    pub ci_upper: Vec<f64>,
+}
+
 //////////////////////////////
 // SCALING, COVAR, COR, PCA //
 //////////////////////////////
@@ -122,6 +154,74 @@ pub fn scale_matrix_col(mat: MatRef<f64>, scale_sd: bool) -> Mat<f64> {
     result
 }
 
+/// Quantile normalisation of the columns of a matrix. Each column gets the
+/// mean of the sorted columns as target distribution. Tied values receive the
+/// linear interpolation of the target at their average rank (as in
+/// preprocessCore::normalize.quantiles()).
+pub fn quantile_normalise(mat: MatRef<f64>) -> Mat<f64> {
+    let n_rows = mat.nrows();
+    let n_cols = mat.ncols();
+
+    let sorted_cols: Vec<Vec<f64>> = mat
+        .par_col_iter()
+        .map(|col| {
+            let mut col: Vec<f64> = col.iter().copied().collect();
+            col.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            col
+        })
+        .collect();
+    let target: Vec<f64> = (0..n_rows)
+        .map(|i| sorted_cols.iter().map(|col| col[i]).sum::<f64>() / n_cols as f64)
+        .collect();
+
+    let normalised_cols: Vec<Vec<f64>> = mat
+        .par_col_iter()
+        .map(|col| {
+            let col: Vec<f64> = col.iter().copied().collect();
+            rank_vector(&col)
+                .iter()
+                .map(|&rank| {
+                    let lower = rank.floor() as usize;
+                    let frac = rank - lower as f64;
+                    if frac > 0.0 && lower < n_rows {
+                        target[lower - 1] + frac * (target[lower] - target[lower - 1])
+                    } else {
+                        target[lower - 1]
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    nested_vector_to_faer_mat(normalised_cols)
+}
+
+/// Rank-based inverse normal transformation of the columns of a matrix. The
+/// ranks r are transformed via qnorm((r - offset) / (n - 2 * offset + 1)),
+/// with offset = 3/8 (Blom) or 0.5 (Rankit) being common choices. Ties receive
+/// the average rank and NaNs are kept.
+pub fn inverse_normal_transform(mat: MatRef<f64>, offset: f64) -> Mat<f64> {
+    let normal = NormalDist::new(0.0, 1.0).unwrap();
+
+    let transformed_cols: Vec<Vec<f64>> = mat
+        .par_col_iter()
+        .map(|col| {
+            let col: Vec<f64> = col.iter().copied().collect();
+            let finite_idx: Vec<usize> = (0..col.len()).filter(|&i| !col[i].is_nan()).collect();
+            let finite: Vec<f64> = finite_idx.iter().map(|&i| col[i]).collect();
+            let n = finite.len() as f64;
+
+            let mut res = vec![f64::NAN; col.len()];
+            for (&i, rank) in finite_idx.iter().zip(rank_vector(&finite)) {
+                res[i] = normal.inverse_cdf((rank - offset) / (n - 2.0 * offset + 1.0));
+            }
+            res
+        })
+        .collect();
+
+    nested_vector_to_faer_mat(transformed_cols)
+}
+
 /// Calculate the column-wise co-variance
 pub fn column_covariance(mat: &MatRef<f64>) -> Mat<f64> {
     let n_rows = mat.nrows();
@@ -131,29 +231,269 @@ pub fn column_covariance(mat: &MatRef<f64>) -> Mat<f64> {
     covariance
 }
 
-/// Calculate the column-wise correlation. Option to use spearman.
-pub fn column_correlation(mat: MatRef<f64>, spearman: bool) -> Mat<f64> {
-    let mat = if spearman {
-        let ranked_vecs: Vec<Vec<f64>> = mat
+/// Centre a column on its mean and scale it to unit length, so that the
+/// cross-product of two such columns is their Pearson correlation.
+fn pearson_unit_column(col: &[f64]) -> Vec<f64> {
+    let mean = col.iter().sum::<f64>() / col.len() as f64;
+    let centred: Vec<f64> = col.iter().map(|x| x - mean).collect();
+    let norm = centred.iter().map(|x| x * x).sum::<f64>().sqrt();
+    // Constant columns stay at zero
+    let norm = if norm < 1e-10 { 1.0 } else { norm };
+
+    centred.iter().map(|x| x / norm).collect()
+}
+
+/// Transform a column into its biweight form scaled to unit length, so that
+/// the cross-product of two such columns is their biweight midcorrelation.
+/// Follows WGCNA: values are weighted by (1 - u^2)^2 with u = (x - med) /
+/// (9 * mad). If more than max_p_outliers of the values on either side of the
+/// median would be outliers, the width on that side is increased accordingly.
+/// Columns with a MAD of zero fall back to Pearson.
+fn bicor_unit_column(col: &[f64], max_p_outliers: f64) -> Vec<f64> {
+    let med = median(col);
+    let shifted: Vec<f64> = col.iter().map(|x| x - med).collect();
+    let abs_dev: Vec<f64> = shifted.iter().map(|x| x.abs()).collect();
+    let mad = median(&abs_dev);
+
+    if mad == 0.0 {
+        return pearson_unit_column(col);
+    }
+
+    let (mut width_low, mut width_high) = (9.0 * mad, 9.0 * mad);
+    if max_p_outliers < 1.0 {
+        width_low = width_low.max(-quantile(&shifted, max_p_outliers));
+        width_high = width_high.max(quantile(&shifted, 1.0 - max_p_outliers));
+    }
+
+    let weighted: Vec<f64> = shifted
+        .iter()
+        .map(|&x| {
+            let u = if x < 0.0 {
+                x / width_low
+            } else {
+                x / width_high
+            };
+            let w = 1.0 - u.abs().min(1.0).powi(2);
+            x * w * w
+        })
+        .collect();
+    let norm = weighted.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+    weighted.iter().map(|x| x / norm).collect()
+}
+
+/// Calculate the column-wise Kendall's tau-b correlation matrix.
+fn column_kendall(mat: MatRef<f64>) -> Mat<f64> {
+    let ncol = mat.ncols();
+    let cols: Vec<Vec<f64>> = mat
+        .col_iter()
+        .map(|col| col.iter().copied().collect())
+        .collect();
+
+    let (rows, cols_idx) = upper_triangle_indices(ncol, 1);
+    let taus: Vec<f64> = rows
+        .par_iter()
+        .zip(cols_idx.par_iter())
+        .map(|(&r, &c)| kendall_tau_b(&cols[r], &cols[c]))
+        .collect();
+
+    let mut cor = Mat::<f64>::identity(ncol, ncol);
+    for ((&r, &c), tau) in rows.iter().zip(cols_idx.iter()).zip(taus) {
+        cor[(r, c)] = tau;
+        cor[(c, r)] = tau;
+    }
+
+    cor
+}
+
+/// Transform the columns into unit vectors, so that the cross-product of the
+/// resulting matrix yields the correlation matrix. Returns None for Kendall
+/// which cannot be expressed as a cross-product.
+pub fn unit_columns(mat: MatRef<f64>, method: &CorMethod) -> Option<Mat<f64>> {
+    let unit_cols: Vec<Vec<f64>> = match method {
+        CorMethod::Kendall => return None,
+        CorMethod::Pearson => mat
             .par_col_iter()
             .map(|x_i| {
                 let x_i: Vec<f64> = x_i.iter().copied().collect();
-                rank_vector(&x_i)
+                pearson_unit_column(&x_i)
             })
-            .collect();
-
-        nested_vector_to_faer_mat(ranked_vecs)
-    } else {
-        mat.cloned()
+            .collect(),
+        CorMethod::Spearman => mat
+            .par_col_iter()
+            .map(|x_i| {
+                let x_i: Vec<f64> = x_i.iter().copied().collect();
+                pearson_unit_column(&rank_vector(&x_i))
+            })
+            .collect(),
+        CorMethod::Bicor(max_p_outliers) => mat
+            .par_col_iter()
+            .map(|x_i| {
+                let x_i: Vec<f64> = x_i.iter().copied().collect();
+                bicor_unit_column(&x_i, *max_p_outliers)
+            })
+            .collect(),
     };
 
-    let scaled = scale_matrix_col(mat.as_ref(), true);
+    Some(nested_vector_to_faer_mat(unit_cols))
+}
 
-    let nrow = scaled.nrows() as f64;
+/// Calculate the column-wise correlation with the given method.
+pub fn column_correlation(mat: MatRef<f64>, method: &CorMethod) -> Mat<f64> {
+    match unit_columns(mat, method) {
+        Some(unit_mat) => unit_mat.transpose() * &unit_mat,
+        None => column_kendall(mat),
+    }
+}
 
-    let cor = scaled.transpose() * &scaled / (nrow - 1_f64);
+/// Calculate the correlation between two vectors with the given method.
+fn pair_correlation(x: &[f64], y: &[f64], method: &CorMethod) -> f64 {
+    let dot = |a: Vec<f64>, b: Vec<f64>| a.iter().zip(b.iter()).map(|(a, b)| a * b).sum();
+    match method {
+        CorMethod::Pearson => dot(pearson_unit_column(x), pearson_unit_column(y)),
+        CorMethod::Spearman => dot(
+            pearson_unit_column(&rank_vector(x)),
+            pearson_unit_column(&rank_vector(y)),
+        ),
+        CorMethod::Bicor(max_p_outliers) => dot(
+            bicor_unit_column(x, *max_p_outliers),
+            bicor_unit_column(y, *max_p_outliers),
+        ),
+        CorMethod::Kendall => kendall_tau_b(x, y),
+    }
+}
 
-    cor
+/// Variance of the Fisher transformed correlation coefficient, which depends on
+/// the method (see Fieller, Hartley and Pearson (1957) for Spearman and
+/// Kendall). NaN if there are too few observations.
+fn fisher_z_variance(n: usize, method: &CorMethod) -> f64 {
+    let n = n as f64;
+    match method {
+        CorMethod::Pearson | CorMethod::Bicor(_) if n > 3.0 => 1.0 / (n - 3.0),
+        CorMethod::Spearman if n > 3.0 => 1.06 / (n - 3.0),
+        CorMethod::Kendall if n > 4.0 => 0.437 / (n - 4.0),
+        _ => f64::NAN,
+    }
+}
+
+/// Sizes of the groups of tied values.
+fn tie_group_sizes(x: &[f64]) -> Vec<f64> {
+    let mut sorted = x.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+        .chunk_by(|a, b| a == b)
+        .filter(|group| group.len() > 1)
+        .map(|group| group.len() as f64)
+        .collect()
+}
+
+/// Two-sided p-value of Kendall's tau-b via the normal approximation of the
+/// S statistic with the variance corrected for ties (equivalent to
+/// `cor.test(method = "kendall", exact = FALSE)`).
+fn kendall_p_value(x: &[f64], y: &[f64], tau: f64) -> f64 {
+    let n = x.len() as f64;
+    let x_ties = tie_group_sizes(x);
+    let y_ties = tie_group_sizes(y);
+    fn tie_sum(ties: &[f64], f: impl Fn(f64) -> f64) -> f64 {
+        ties.iter().map(|&t| f(t)).sum()
+    }
+
+    let n_pairs = n * (n - 1.0) / 2.0;
+    let x_pairs = tie_sum(&x_ties, |t| t * (t - 1.0) / 2.0);
+    let y_pairs = tie_sum(&y_ties, |t| t * (t - 1.0) / 2.0);
+    let s = tau * ((n_pairs - x_pairs) * (n_pairs - y_pairs)).sqrt();
+
+    let v0 = n * (n - 1.0) * (2.0 * n + 5.0);
+    let vt = tie_sum(&x_ties, |t| t * (t - 1.0) * (2.0 * t + 5.0));
+    let vu = tie_sum(&y_ties, |t| t * (t - 1.0) * (2.0 * t + 5.0));
+    let v1 = tie_sum(&x_ties, |t| t * (t - 1.0)) * tie_sum(&y_ties, |t| t * (t - 1.0));
+    let v2 = tie_sum(&x_ties, |t| t * (t - 1.0) * (t - 2.0))
+        * tie_sum(&y_ties, |t| t * (t - 1.0) * (t - 2.0));
+    let var_s =
+        (v0 - vt - vu) / 18.0 + v1 / (2.0 * n * (n - 1.0)) + v2 / (9.0 * n * (n - 1.0) * (n - 2.0));
+
+    let z = s / var_s.sqrt();
+    2.0 * NormalDist::new(0.0, 1.0).unwrap().sf(z.abs())
+}
+
+/// Calculate the column-wise correlations on the pairwise-complete
+/// observations, i.e., for every pair of columns only the rows in which both
+/// values are finite are used. Besides the coefficients, the number of
+/// observations, p-values and Fisher z confidence intervals are returned in
+/// the upper triangle layout. The p-values are based on the Student t-test of
+/// the coefficients, apart from Kendall which uses the normal approximation of
+/// tau-b. The variance of the Fisher z transformed coefficients depends on the
+/// method. Pairs in which a column is constant return NaN.
+pub fn pairwise_complete_correlation(
+    mat: MatRef<f64>,
+    method: &CorMethod,
+    shift: usize,
+    conf_level: f64,
+) -> PairwiseCorRes {
+    let cols: Vec<Vec<f64>> = mat
+        .col_iter()
+        .map(|col| col.iter().copied().collect())
+        .collect();
+    let z_crit = NormalDist::new(0.0, 1.0)
+        .unwrap()
+        .inverse_cdf(0.5 + conf_level / 2.0);
+
+    let (rows, cols_idx) = upper_triangle_indices(mat.ncols(), shift);
+
+    let res: Vec<(f64, usize, f64, f64, f64)> = rows
+        .par_iter()
+        .zip(cols_idx.par_iter())
+        .map(|(&r, &c)| {
+            let (x, y): (Vec<f64>, Vec<f64>) = cols[r]
+                .iter()
+                .zip(cols[c].iter())
+                .filter(|(a, b)| a.is_finite() && b.is_finite())
+                .map(|(a, b)| (*a, *b))
+                .unzip();
+            let n = x.len();
+            let is_constant = |v: &[f64]| v.iter().all(|a| *a == v[0]);
+            if n < 3 || is_constant(&x) || is_constant(&y) {
+                return (f64::NAN, n, f64::NAN, f64::NAN, f64::NAN);
+            }
+            let cor = pair_correlation(&x, &y, method).clamp(-1.0, 1.0);
+
+            let df = (n - 2) as f64;
+            let p_val = match method {
+                CorMethod::Kendall => kendall_p_value(&x, &y, cor),
+                _ if cor.abs() >= 1.0 => 0.0,
+                _ => {
+                    let t = cor * (df / (1.0 - cor * cor)).sqrt();
+                    2.0 * StudentsT::new(0.0, 1.0, df).unwrap().sf(t.abs())
+                }
+            };
+            let se = fisher_z_variance(n, method).sqrt();
+            let z = cor.atanh();
+            let (ci_lower, ci_upper) = ((z - z_crit * se).tanh(), (z + z_crit * se).tanh());
+
+            (cor, n, p_val, ci_lower, ci_upper)
+        })
+        .collect();
+
+    let mut cor = Vec::with_capacity(res.len());
+    let mut n = Vec::with_capacity(res.len());
+    let mut p_vals = Vec::with_capacity(res.len());
+    let mut ci_lower = Vec::with_capacity(res.len());
+    let mut ci_upper = Vec::with_capacity(res.len());
+    for (cor_i, n_i, p_i, lower_i, upper_i) in res {
+        cor.push(cor_i);
+        n.push(n_i);
+        p_vals.push(p_i);
+        ci_lower.push(lower_i);
+        ci_upper.push(upper_i);
+    }
+
+    PairwiseCorRes {
+        cor,
+        n,
+        p_vals,
+        ci_lower,
+        ci_upper,
+    }
 }
 
 /// Calculate differential correlations
@@ -162,7 +502,7 @@ pub fn calculate_diff_correlation(
     mat_b: &Mat<f64>,
     no_sample_a: usize,
     no_sample_b: usize,
-    spearman: bool,
+    method: &CorMethod,
 ) -> DiffCorRes {
     let mut cors_a: Vec<f64> = Vec::new();
     let mut cors_b: Vec<f64> = Vec::new();
@@ -185,10 +525,8 @@ pub fn calculate_diff_correlation(
     cors_a.par_iter_mut().for_each(|x| *x = x.atanh());
     cors_b.par_iter_mut().for_each(|x| *x = x.atanh());
 
-    // Constant will depend on if Spearman or Pearson
-    let constant = if spearman { 1.06 } else { 1.0 };
     let denominator =
-        ((constant / (no_sample_a as f64 - 3.0)) + (constant / (no_sample_b as f64 - 3.0))).sqrt();
+        (fisher_z_variance(no_sample_a, method) + fisher_z_variance(no_sample_b, method)).sqrt();
 
     let z_scores: Vec<f64> = cors_a
         .par_iter()
@@ -0,0 +1,251 @@
+use faer::{Mat, MatRef};
+use rayon::prelude::*;
+
+use crate::utils_rust::upper_triangle_indices;
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the mutual information network methods
+#[derive(Clone, Debug)]
+pub enum MiNetworkMethod {
+    Mi,
+    Clr,
+    Aracne,
+}
+
+/// Parsing the mutual information network method
+pub fn parse_mi_network_method(s: &str) -> Option<MiNetworkMethod> {
+    match s.to_lowercase().as_str() {
+        "mi" => Some(MiNetworkMethod::Mi),
+        "clr" => Some(MiNetworkMethod::Clr),
+        "aracne" => Some(MiNetworkMethod::Aracne),
+        _ => None,
+    }
+}
+
+////////////////////////
+// B-spline estimator //
+////////////////////////
+
+/// Knot vector of the B-spline binning (Daub, et al., 2004) for n_bins basis
+/// functions of the given order.
+fn bspline_knots(n_bins: usize, order: usize) -> Vec<f64> {
+    (0..(n_bins + order))
+        .map(|i| {
+            if i < order {
+                0.0
+            } else if i < n_bins {
+                (i - order + 1) as f64
+            } else {
+                (n_bins - order + 1) as f64
+            }
+        })
+        .collect()
+}
+
+/// Values of all B-spline basis functions at z via the Cox-de Boor recursion.
+fn bspline_basis(z: f64, knots: &[f64], n_bins: usize, order: usize) -> Vec<f64> {
+    let max_knot = knots[knots.len() - 1];
+    let mut basis: Vec<f64> = (0..(knots.len() - 1))
+        .map(|i| {
+            let inside = knots[i] <= z && z < knots[i + 1];
+            // The right end belongs to the last non-empty interval
+            let right_end = z >= max_knot && knots[i] < knots[i + 1] && knots[i + 1] == max_knot;
+            if inside || right_end {
+                1.0
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    for k in 2..=order {
+        basis = (0..(knots.len() - k))
+            .map(|i| {
+                let left_denom = knots[i + k - 1] - knots[i];
+                let right_denom = knots[i + k] - knots[i + 1];
+                let left = if left_denom > 0.0 {
+                    (z - knots[i]) / left_denom * basis[i]
+                } else {
+                    0.0
+                };
+                let right = if right_denom > 0.0 {
+                    (knots[i + k] - z) / right_denom * basis[i + 1]
+                } else {
+                    0.0
+                };
+                left + right
+            })
+            .collect();
+    }
+    basis.truncate(n_bins);
+
+    basis
+}
+
+/// B-spline weights of a variable (samples x bins). The values are rescaled
+/// onto the domain of the knots first.
+pub fn bspline_weights(x: &[f64], n_bins: usize, order: usize) -> Mat<f64> {
+    let knots = bspline_knots(n_bins, order);
+    let (min, max) = x
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    let range = max - min;
+    let domain = (n_bins - order + 1) as f64;
+
+    let rows: Vec<Vec<f64>> = x
+        .iter()
+        .map(|&v| {
+            let z = if range > 0.0 {
+                (v - min) / range * domain
+            } else {
+                0.0
+            };
+            bspline_basis(z, &knots, n_bins, order)
+        })
+        .collect();
+
+    Mat::from_fn(x.len(), n_bins, |i, j| rows[i][j])
+}
+
+/// Entropy (in nats) of a discrete probability distribution
+fn entropy(probs: impl Iterator<Item = f64>) -> f64 {
+    -probs.filter(|&p| p > 0.0).map(|p| p * p.ln()).sum::<f64>()
+}
+
+/// Pairwise mutual information (in nats) between the columns of a matrix
+/// based on the B-spline estimator (Daub, et al., 2004). Every sample
+/// contributes to order bins with the weights of the B-spline basis
+/// functions; the joint probabilities of two variables are the cross-product
+/// of their weights. The diagonal contains the entropies.
+pub fn mutual_information_matrix(mat: MatRef<f64>, n_bins: usize, order: usize) -> Mat<f64> {
+    let n = mat.nrows() as f64;
+    let p = mat.ncols();
+
+    let weights: Vec<Mat<f64>> = mat
+        .par_col_iter()
+        .map(|col| {
+            let col: Vec<f64> = col.iter().copied().collect();
+            bspline_weights(&col, n_bins, order)
+        })
+        .collect();
+    let entropies: Vec<f64> = weights
+        .iter()
+        .map(|w| entropy((0..n_bins).map(|b| w.col(b).iter().sum::<f64>() / n)))
+        .collect();
+
+    let (rows, cols) = upper_triangle_indices(p, 1);
+    let mi: Vec<f64> = rows
+        .par_iter()
+        .zip(cols.par_iter())
+        .map(|(&i, &j)| {
+            let joint = weights[i].transpose() * &weights[j];
+            let joint_entropy =
+                entropy((0..n_bins * n_bins).map(|idx| joint[(idx / n_bins, idx % n_bins)] / n));
+            (entropies[i] + entropies[j] - joint_entropy).max(0.0)
+        })
+        .collect();
+
+    let mut res = Mat::<f64>::zeros(p, p);
+    for i in 0..p {
+        res[(i, i)] = entropies[i];
+    }
+    for ((&i, &j), value) in rows.iter().zip(cols.iter()).zip(mi) {
+        res[(i, j)] = value;
+        res[(j, i)] = value;
+    }
+
+    res
+}
+
+///////////////////////
+// Network inference //
+///////////////////////
+
+/// Context likelihood of relatedness (Faith, et al., 2007). Every mutual
+/// information value is z-scored against the background distribution of both
+/// variables (the off-diagonal values of their rows; negative z-scores are set
+/// to 0) and combined via sqrt(z_i^2 + z_j^2).
+pub fn clr(mi: MatRef<f64>) -> Mat<f64> {
+    let p = mi.ncols();
+    let (means, sds): (Vec<f64>, Vec<f64>) = (0..p)
+        .map(|i| {
+            let values: Vec<f64> = (0..p).filter(|&j| j != i).map(|j| mi[(i, j)]).collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            (mean, var.sqrt())
+        })
+        .unzip();
+
+    let z = |i: usize, j: usize| {
+        if sds[i] > 0.0 {
+            ((mi[(i, j)] - means[i]) / sds[i]).max(0.0)
+        } else {
+            0.0
+        }
+    };
+
+    Mat::from_fn(p, p, |i, j| {
+        if i == j {
+            0.0
+        } else {
+            (z(i, j).powi(2) + z(j, i).powi(2)).sqrt()
+        }
+    })
+}
+
+/// ARACNE (Margolin, et al., 2006). For every triplet of variables, the edge
+/// with the lowest mutual information is removed if it is lower than the
+/// other two by more than eps (data processing inequality). Removed edges are
+/// set to 0.
+pub fn aracne(mi: MatRef<f64>, eps: f64) -> Mat<f64> {
+    let p = mi.ncols();
+    let (rows, cols) = upper_triangle_indices(p, 1);
+
+    let keep: Vec<bool> = rows
+        .par_iter()
+        .zip(cols.par_iter())
+        .map(|(&i, &j)| {
+            let mi_ij = mi[(i, j)];
+            !(0..p)
+                .filter(|&k| k != i && k != j)
+                .any(|k| mi_ij < mi[(i, k)].min(mi[(j, k)]) - eps)
+        })
+        .collect();
+
+    let mut res = Mat::<f64>::zeros(p, p);
+    for ((&i, &j), keep_ij) in rows.iter().zip(cols.iter()).zip(keep) {
+        if keep_ij {
+            res[(i, j)] = mi[(i, j)];
+            res[(j, i)] = mi[(i, j)];
+        }
+    }
+
+    res
+}
+
+/// Extract the pairs in the upper triangle with a weight > min_weight as
+/// sparse edges (from, to, weight).
+pub fn upper_triangle_edges(
+    weights: MatRef<f64>,
+    min_weight: f64,
+) -> (Vec<usize>, Vec<usize>, Vec<f64>) {
+    let (rows, cols) = upper_triangle_indices(weights.ncols(), 1);
+    let mut from = Vec::new();
+    let mut to = Vec::new();
+    let mut weight = Vec::new();
+    for (&i, &j) in rows.iter().zip(cols.iter()) {
+        if weights[(i, j)] > min_weight {
+            from.push(i);
+            to.push(j);
+            weight.push(weights[(i, j)]);
+        }
+    }
+
+    (from, to, weight)
+}
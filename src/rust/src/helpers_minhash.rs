@@ -0,0 +1,265 @@
+use rand::prelude::*;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::utils_rust::{flatten_vector, string_vec_to_set};
+use crate::utils_stats::set_similarity;
+
+///////////
+// Types //
+///////////
+
+/// Type alias for the verified LSH pairs: index set 1, index set 2 and the
+/// exact Jaccard similarity.
+pub type LshPairs = (Vec<usize>, Vec<usize>, Vec<f64>);
+
+/// Mersenne prime 2^61 - 1 used for the universal hash functions.
+const MERSENNE_PRIME: u64 = (1 << 61) - 1;
+
+/// Offset basis of the 64-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Prime of the 64-bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x100000001b3;
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the MinHash permutations. Each permutation is represented by
+/// a universal hash function h(x) = (a * x + b) mod p.
+#[derive(Clone, Debug)]
+pub struct MinHasher {
+    a: Vec<u64>,
+    b: Vec<u64>,
+}
+
+/// Structure for the LSH banding parameters.
+#[derive(Clone, Debug)]
+pub struct LshParams {
+    pub bands: usize,
+    pub rows: usize,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl MinHasher {
+    /// Create a new MinHasher with num_perm hash functions.
+    pub fn new(num_perm: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let a: Vec<u64> = (0..num_perm)
+            .map(|_| rng.random_range(1..MERSENNE_PRIME))
+            .collect();
+        let b: Vec<u64> = (0..num_perm)
+            .map(|_| rng.random_range(0..MERSENNE_PRIME))
+            .collect();
+
+        MinHasher { a, b }
+    }
+
+    /// Generate the MinHash signature for a given set of genes.
+    pub fn signature(&self, set: &[String]) -> Vec<u64> {
+        let hashes: Vec<u64> = set.iter().map(|s| hash_string(s)).collect();
+
+        self.a
+            .iter()
+            .zip(self.b.iter())
+            .map(|(&a, &b)| {
+                hashes
+                    .iter()
+                    .map(|&x| ((a as u128 * x as u128 + b as u128) % MERSENNE_PRIME as u128) as u64)
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+/// 64-bit FNV-1a hash over a stream of bytes. Unlike the std DefaultHasher,
+/// the output is fixed across Rust releases and platforms, so the MinHash
+/// signatures (and hence the LSH results) are reproducible for a given seed.
+fn fnv1a_hash<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Hash a string into the range of the Mersenne prime.
+pub fn hash_string(s: &str) -> u64 {
+    fnv1a_hash(s.bytes()) % MERSENNE_PRIME
+}
+
+/// Probability that a pair with Jaccard similarity s becomes a candidate given
+/// b bands and r rows.
+fn lsh_candidate_prob(s: f64, bands: usize, rows: usize) -> f64 {
+    1.0 - (1.0 - s.powi(rows as i32)).powi(bands as i32)
+}
+
+/// Simpson integration of the LSH candidate probability between lower and upper.
+fn integrate_candidate_prob(
+    lower: f64,
+    upper: f64,
+    bands: usize,
+    rows: usize,
+    complement: bool,
+) -> f64 {
+    let n = 100;
+    let h = (upper - lower) / n as f64;
+    let f = |s: f64| {
+        let p = lsh_candidate_prob(s, bands, rows);
+        if complement {
+            1.0 - p
+        } else {
+            p
+        }
+    };
+
+    let mut sum = f(lower) + f(upper);
+    for i in 1..n {
+        let weight = if i % 2 == 0 { 2.0 } else { 4.0 };
+        sum += weight * f(lower + i as f64 * h);
+    }
+
+    sum * h / 3.0
+}
+
+/// Identify the number of bands and rows that minimise the (equally weighted)
+/// false positive and false negative probabilities for a Jaccard threshold.
+pub fn lsh_optimal_params(threshold: f64, num_perm: usize) -> LshParams {
+    let mut min_error = f64::INFINITY;
+    let mut best = LshParams {
+        bands: 1,
+        rows: num_perm,
+    };
+
+    for bands in 1..=num_perm {
+        let max_rows = num_perm / bands;
+        for rows in 1..=max_rows {
+            let false_pos = integrate_candidate_prob(0.0, threshold, bands, rows, false);
+            let false_neg = integrate_candidate_prob(threshold, 1.0, bands, rows, true);
+            let error = 0.5 * false_pos + 0.5 * false_neg;
+            if error < min_error {
+                min_error = error;
+                best = LshParams { bands, rows };
+            }
+        }
+    }
+
+    best
+}
+
+/// Hash a band (slice) of a MinHash signature into a bucket key.
+fn hash_band(band: &[u64]) -> u64 {
+    fnv1a_hash(band.iter().flat_map(|x| x.to_le_bytes()))
+}
+
+/// Identify the candidate pairs between two sets of MinHash signatures via LSH
+/// banding. Empty sets will never be returned as candidates.
+pub fn lsh_candidate_pairs(
+    signatures_1: &[Vec<u64>],
+    signatures_2: &[Vec<u64>],
+    empty_1: &[bool],
+    empty_2: &[bool],
+    params: &LshParams,
+) -> Vec<(usize, usize)> {
+    let band_candidates: Vec<Vec<(usize, usize)>> = (0..params.bands)
+        .into_par_iter()
+        .map(|band| {
+            let start = band * params.rows;
+            let end = start + params.rows;
+
+            let mut buckets: HashMap<u64, (Vec<usize>, Vec<usize>)> = HashMap::new();
+
+            for (i, sig) in signatures_1.iter().enumerate() {
+                if !empty_1[i] {
+                    buckets
+                        .entry(hash_band(&sig[start..end]))
+                        .or_default()
+                        .0
+                        .push(i);
+                }
+            }
+            for (j, sig) in signatures_2.iter().enumerate() {
+                if !empty_2[j] {
+                    buckets
+                        .entry(hash_band(&sig[start..end]))
+                        .or_default()
+                        .1
+                        .push(j);
+                }
+            }
+
+            let mut candidates = Vec::new();
+            for (idx_1, idx_2) in buckets.values() {
+                for &i in idx_1 {
+                    for &j in idx_2 {
+                        candidates.push((i, j));
+                    }
+                }
+            }
+
+            candidates
+        })
+        .collect();
+
+    let candidates: HashSet<(usize, usize)> = flatten_vector(band_candidates).into_iter().collect();
+    let mut candidates: Vec<(usize, usize)> = candidates.into_iter().collect();
+    candidates.sort_unstable();
+
+    candidates
+}
+
+/// Find all pairs between two lists of gene sets with a Jaccard similarity
+/// above the threshold. MinHash signatures with LSH banding are used to
+/// identify candidate pairs; the candidates are then verified with the exact
+/// Jaccard similarity.
+pub fn lsh_similar_sets(
+    s_1: &[Vec<String>],
+    s_2: &[Vec<String>],
+    threshold: f64,
+    num_perm: usize,
+    seed: u64,
+) -> LshPairs {
+    let hasher = MinHasher::new(num_perm, seed);
+    let params = lsh_optimal_params(threshold, num_perm);
+
+    let signatures_1: Vec<Vec<u64>> = s_1.par_iter().map(|s| hasher.signature(s)).collect();
+    let signatures_2: Vec<Vec<u64>> = s_2.par_iter().map(|s| hasher.signature(s)).collect();
+    let empty_1: Vec<bool> = s_1.iter().map(|s| s.is_empty()).collect();
+    let empty_2: Vec<bool> = s_2.iter().map(|s| s.is_empty()).collect();
+
+    let candidates = lsh_candidate_pairs(&signatures_1, &signatures_2, &empty_1, &empty_2, &params);
+
+    let sets_1: Vec<HashSet<&String>> = s_1.iter().map(|s| string_vec_to_set(s)).collect();
+    let sets_2: Vec<HashSet<&String>> = s_2.iter().map(|s| string_vec_to_set(s)).collect();
+
+    let verified: Vec<(usize, usize, f64)> = candidates
+        .par_iter()
+        .filter_map(|&(i, j)| {
+            let sim = set_similarity(&sets_1[i], &sets_2[j], false);
+            if sim >= threshold {
+                Some((i, j, sim))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let mut idx_1 = Vec::with_capacity(verified.len());
+    let mut idx_2 = Vec::with_capacity(verified.len());
+    let mut similarities = Vec::with_capacity(verified.len());
+
+    for (i, j, sim) in verified {
+        idx_1.push(i);
+        idx_2.push(j);
+        similarities.push(sim);
+    }
+
+    (idx_1, idx_2, similarities)
+}
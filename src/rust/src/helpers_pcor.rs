@@ -0,0 +1,475 @@
+use faer::{
+    linalg::solvers::{PartialPivLu, Solve},
+    Mat, MatRef, Side,
+};
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::helpers_linalg::{column_correlation, scale_matrix_col, CorMethod};
+use crate::utils_rust::upper_triangle_indices;
+
+////////////////////////
+// Enums & structures //
+////////////////////////
+
+/// Enum for the graphical lasso model selection
+#[derive(Clone, Debug)]
+pub enum GlassoSelection {
+    Ebic,
+    Stars,
+}
+
+/// Parsing the model selection
+pub fn parse_glasso_selection(s: &str) -> Option<GlassoSelection> {
+    match s.to_lowercase().as_str() {
+        "ebic" => Some(GlassoSelection::Ebic),
+        "stars" => Some(GlassoSelection::Stars),
+        _ => None,
+    }
+}
+
+/// Structure for the graphical lasso parameters
+#[derive(Clone, Debug)]
+pub struct GlassoParams {
+    pub n_lambda: usize,
+    pub lambda_min_ratio: f64,
+    pub ebic_gamma: f64,
+    pub stars_beta: f64,
+    pub stars_subsamples: usize,
+    pub max_iter: usize,
+    pub tol: f64,
+}
+
+/// Structure for a single graphical lasso fit. beta stores in column j the
+/// lasso coefficients of the j-th regression (used for warm starts).
+#[derive(Clone, Debug)]
+pub struct GlassoFit {
+    pub w: Mat<f64>,
+    pub beta: Mat<f64>,
+    pub theta: Mat<f64>,
+}
+
+/// Structure for the graphical lasso path results
+#[derive(Clone, Debug)]
+pub struct GlassoPathRes {
+    pub lambdas: Vec<f64>,
+    pub n_edges: Vec<usize>,
+    pub criterion: Vec<f64>,
+    pub selected: usize,
+    pub theta: Mat<f64>,
+}
+
+/// Structure for the sparse partial correlation edges
+#[derive(Clone, Debug)]
+pub struct PcorEdges {
+    pub from: Vec<usize>,
+    pub to: Vec<usize>,
+    pub pcor: Vec<f64>,
+    pub precision: Vec<f64>,
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Soft-thresholding operator
+fn soft_threshold(x: f64, lambda: f64) -> f64 {
+    x.signum() * (x.abs() - lambda).max(0.0)
+}
+
+/// Mean absolute off-diagonal value of a symmetric matrix
+fn mean_abs_off_diagonal(s: MatRef<f64>) -> f64 {
+    let p = s.ncols();
+    if p < 2 {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for j in 0..p {
+        for i in 0..p {
+            if i != j {
+                sum += s[(i, j)].abs();
+            }
+        }
+    }
+    sum / (p * (p - 1)) as f64
+}
+
+/// Number of non-zero off-diagonal entries in the upper triangle
+fn count_edges(theta: MatRef<f64>) -> usize {
+    let p = theta.ncols();
+    let mut n_edges = 0;
+    for j in 0..p {
+        for i in 0..j {
+            if theta[(i, j)] != 0.0 {
+                n_edges += 1;
+            }
+        }
+    }
+    n_edges
+}
+
+/// Log determinant of a positive definite matrix via the Cholesky
+/// decomposition. Returns NaN if the matrix is not positive definite.
+fn log_det(mat: MatRef<f64>) -> f64 {
+    match mat.llt(Side::Lower) {
+        Ok(llt) => {
+            let l = llt.L();
+            2.0 * (0..mat.nrows()).map(|i| l[(i, i)].ln()).sum::<f64>()
+        }
+        Err(_) => f64::NAN,
+    }
+}
+
+/// Transform a precision matrix into the partial correlation matrix
+pub fn precision_to_pcor(theta: MatRef<f64>) -> Mat<f64> {
+    let p = theta.ncols();
+    Mat::from_fn(p, p, |i, j| {
+        if i == j {
+            1.0
+        } else {
+            -theta[(i, j)] / (theta[(i, i)] * theta[(j, j)]).sqrt()
+        }
+    })
+}
+
+/// Extract the sparse edges from a precision matrix
+pub fn precision_to_edges(theta: MatRef<f64>, min_pcor: f64) -> PcorEdges {
+    let pcor = precision_to_pcor(theta);
+    let (rows, cols) = upper_triangle_indices(theta.ncols(), 1);
+
+    let mut edges = PcorEdges {
+        from: Vec::new(),
+        to: Vec::new(),
+        pcor: Vec::new(),
+        precision: Vec::new(),
+    };
+    for (&r, &c) in rows.iter().zip(cols.iter()) {
+        if theta[(r, c)] != 0.0 && pcor[(r, c)].abs() >= min_pcor {
+            edges.from.push(r);
+            edges.to.push(c);
+            edges.pcor.push(pcor[(r, c)]);
+            edges.precision.push(theta[(r, c)]);
+        }
+    }
+
+    edges
+}
+
+/////////////////////
+// Graphical lasso //
+/////////////////////
+
+/// Graphical lasso via block coordinate descent (Friedman, Hastie and
+/// Tibshirani, 2008). s is the covariance (or correlation) matrix. A previous
+/// fit (e.g., for a larger lambda) can be provided as warm start. The
+/// iterations stop once the mean absolute change of W falls below tol times
+/// the mean absolute off-diagonal value of s.
+pub fn glasso(
+    s: MatRef<f64>,
+    lambda: f64,
+    warm_start: Option<&GlassoFit>,
+    max_iter: usize,
+    tol: f64,
+) -> GlassoFit {
+    let p = s.ncols();
+    let (mut w, mut beta) = match warm_start {
+        Some(fit) => (fit.w.clone(), fit.beta.clone()),
+        None => (s.to_owned(), Mat::<f64>::zeros(p, p)),
+    };
+    for i in 0..p {
+        w[(i, i)] = s[(i, i)] + lambda;
+    }
+    let threshold = tol * mean_abs_off_diagonal(s).max(f64::EPSILON);
+
+    for _ in 0..max_iter {
+        let mut delta_w = 0.0;
+
+        for j in 0..p {
+            // Lasso problem: min 1/2 b' W11 b - b' s12 + lambda |b|
+            let mut b: Vec<f64> = (0..p).map(|k| beta[(k, j)]).collect();
+            b[j] = 0.0;
+            let mut wb: Vec<f64> = (0..p)
+                .map(|m| (0..p).filter(|&k| k != j).map(|k| w[(m, k)] * b[k]).sum())
+                .collect();
+
+            for _ in 0..max_iter {
+                let mut max_change: f64 = 0.0;
+                for k in 0..p {
+                    if k == j {
+                        continue;
+                    }
+                    let residual = s[(k, j)] - (wb[k] - w[(k, k)] * b[k]);
+                    let new_b = soft_threshold(residual, lambda) / w[(k, k)];
+                    let change = new_b - b[k];
+                    if change != 0.0 {
+                        for (m, wb_m) in wb.iter_mut().enumerate() {
+                            if m != j {
+                                *wb_m += w[(m, k)] * change;
+                            }
+                        }
+                        b[k] = new_b;
+                        max_change = max_change.max(change.abs());
+                    }
+                }
+                if max_change < threshold {
+                    break;
+                }
+            }
+
+            for k in 0..p {
+                if k == j {
+                    continue;
+                }
+                delta_w += (wb[k] - w[(k, j)]).abs();
+                w[(k, j)] = wb[k];
+                w[(j, k)] = wb[k];
+                beta[(k, j)] = b[k];
+            }
+        }
+
+        let n_off_diagonal = (p * p.saturating_sub(1)).max(1) as f64;
+        if delta_w / n_off_diagonal < threshold {
+            break;
+        }
+    }
+
+    // Recover the precision matrix
+    let mut theta = Mat::<f64>::zeros(p, p);
+    for j in 0..p {
+        let w12_b: f64 = (0..p)
+            .filter(|&k| k != j)
+            .map(|k| w[(k, j)] * beta[(k, j)])
+            .sum();
+        let theta_jj = 1.0 / (w[(j, j)] - w12_b);
+        theta[(j, j)] = theta_jj;
+        for k in 0..p {
+            if k != j {
+                theta[(k, j)] = -beta[(k, j)] * theta_jj;
+            }
+        }
+    }
+    let theta = Mat::from_fn(p, p, |i, j| (theta[(i, j)] + theta[(j, i)]) / 2.0);
+
+    GlassoFit { w, beta, theta }
+}
+
+/// Log-spaced lambda path from the largest absolute off-diagonal value of s
+/// (empty graph) down to lambda_min_ratio times that value.
+fn lambda_path(s: MatRef<f64>, n_lambda: usize, lambda_min_ratio: f64) -> Vec<f64> {
+    let p = s.ncols();
+    let mut lambda_max: f64 = 0.0;
+    for j in 0..p {
+        for i in 0..j {
+            lambda_max = lambda_max.max(s[(i, j)].abs());
+        }
+    }
+    if n_lambda == 1 {
+        return vec![lambda_max];
+    }
+    let log_max = lambda_max.ln();
+    let log_min = (lambda_max * lambda_min_ratio).ln();
+    (0..n_lambda)
+        .map(|i| (log_max + (log_min - log_max) * i as f64 / (n_lambda - 1) as f64).exp())
+        .collect()
+}
+
+/// Fit the graphical lasso over the lambda path with warm starts.
+fn glasso_path(s: MatRef<f64>, lambdas: &[f64], max_iter: usize, tol: f64) -> Vec<GlassoFit> {
+    let mut fits: Vec<GlassoFit> = Vec::with_capacity(lambdas.len());
+    for &lambda in lambdas {
+        let fit = glasso(s, lambda, fits.last(), max_iter, tol);
+        fits.push(fit);
+    }
+    fits
+}
+
+/// Extended BIC of a graphical lasso fit (Foygel and Drton, 2010).
+fn ebic(s: MatRef<f64>, theta: MatRef<f64>, n: usize, gamma: f64) -> f64 {
+    let p = s.ncols();
+    let trace: f64 = (s * theta).diagonal().column_vector().iter().sum();
+    let log_lik = n as f64 / 2.0 * (log_det(theta) - trace);
+    let n_edges = count_edges(theta) as f64;
+    let ebic = -2.0 * log_lik + n_edges * (n as f64).ln() + 4.0 * gamma * n_edges * (p as f64).ln();
+
+    if ebic.is_nan() {
+        f64::INFINITY
+    } else {
+        ebic
+    }
+}
+
+/// StARS instability along the lambda path (Liu, Roeder and Wasserman,
+/// 2010). The graphical lasso is fitted on subsamples of size
+/// min(10 * sqrt(n), 0.8 * n) and the instability is the average of
+/// 2 * f * (1 - f) over all edges with f being the edge frequency. The
+/// instabilities are monotonised from the sparsest graph onwards.
+fn stars_instability(
+    x: MatRef<f64>,
+    lambdas: &[f64],
+    params: &GlassoParams,
+    seed: u64,
+) -> Vec<f64> {
+    let n = x.nrows();
+    let p = x.ncols();
+    let n_pairs = (p * p.saturating_sub(1) / 2).max(1);
+    let subsample_size = ((10.0 * (n as f64).sqrt()).min(0.8 * n as f64) as usize).max(3);
+
+    let counts: Vec<Vec<u32>> = (0..params.stars_subsamples)
+        .into_par_iter()
+        .map(|iter| {
+            let mut rng = StdRng::seed_from_u64(seed + iter as u64);
+            let idx = rand::seq::index::sample(&mut rng, n, subsample_size.min(n)).into_vec();
+            let sub = Mat::from_fn(idx.len(), p, |i, j| x[(idx[i], j)]);
+            let s = column_correlation(sub.as_ref(), &CorMethod::Pearson);
+            glasso_path(s.as_ref(), lambdas, params.max_iter, params.tol)
+                .iter()
+                .map(|fit| {
+                    let mut edges = vec![0_u32; n_pairs];
+                    let mut pos = 0;
+                    for i in 0..p {
+                        for j in (i + 1)..p {
+                            if fit.theta[(i, j)] != 0.0 {
+                                edges[pos] = 1;
+                            }
+                            pos += 1;
+                        }
+                    }
+                    edges
+                })
+                .collect::<Vec<Vec<u32>>>()
+        })
+        .reduce(
+            || vec![vec![0_u32; n_pairs]; lambdas.len()],
+            |mut a, b| {
+                for (a_l, b_l) in a.iter_mut().zip(b.iter()) {
+                    for (a_e, b_e) in a_l.iter_mut().zip(b_l.iter()) {
+                        *a_e += b_e;
+                    }
+                }
+                a
+            },
+        )
+        .into_iter()
+        .collect();
+
+    let mut instability: Vec<f64> = counts
+        .iter()
+        .map(|lambda_counts| {
+            lambda_counts
+                .iter()
+                .map(|&c| {
+                    let f = c as f64 / params.stars_subsamples as f64;
+                    2.0 * f * (1.0 - f)
+                })
+                .sum::<f64>()
+                / n_pairs as f64
+        })
+        .collect();
+
+    // Monotonise
+    for i in 1..instability.len() {
+        instability[i] = instability[i].max(instability[i - 1]);
+    }
+
+    instability
+}
+
+/// Graphical lasso on the correlation matrix of x (samples x features) over a
+/// lambda path with model selection via EBIC or StARS. Returns the path
+/// summary and the precision matrix of the selected model.
+pub fn glasso_select(
+    x: MatRef<f64>,
+    selection: &GlassoSelection,
+    params: &GlassoParams,
+    seed: u64,
+) -> GlassoPathRes {
+    let n = x.nrows();
+    let s = column_correlation(x, &CorMethod::Pearson);
+    let lambdas = lambda_path(s.as_ref(), params.n_lambda, params.lambda_min_ratio);
+    let fits = glasso_path(s.as_ref(), &lambdas, params.max_iter, params.tol);
+    let n_edges: Vec<usize> = fits
+        .iter()
+        .map(|fit| count_edges(fit.theta.as_ref()))
+        .collect();
+
+    let (criterion, selected) = match selection {
+        GlassoSelection::Ebic => {
+            let criterion: Vec<f64> = fits
+                .iter()
+                .map(|fit| ebic(s.as_ref(), fit.theta.as_ref(), n, params.ebic_gamma))
+                .collect();
+            let selected = criterion
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            (criterion, selected)
+        }
+        GlassoSelection::Stars => {
+            let criterion = stars_instability(x, &lambdas, params, seed);
+            let selected = criterion
+                .iter()
+                .rposition(|&d| d <= params.stars_beta)
+                .unwrap_or(0);
+            (criterion, selected)
+        }
+    };
+
+    GlassoPathRes {
+        lambdas,
+        n_edges,
+        criterion,
+        selected,
+        theta: fits[selected].theta.clone(),
+    }
+}
+
+///////////////////////////////////
+// Shrinkage partial correlation //
+///////////////////////////////////
+
+/// Shrinkage estimate of the partial correlations (Schaefer and Strimmer,
+/// 2005), equivalent to corpcor::pcor.shrink(). The correlation matrix is
+/// shrunk towards the identity with the analytically optimal intensity and
+/// subsequently inverted. Returns the partial correlation matrix, the
+/// precision matrix (of the standardised data) and the shrinkage intensity.
+pub fn pcor_shrink(x: MatRef<f64>) -> (Mat<f64>, Mat<f64>, f64) {
+    let n = x.nrows() as f64;
+    let p = x.ncols();
+    let xs = scale_matrix_col(x, true);
+    let xs2 = Mat::from_fn(xs.nrows(), p, |i, j| xs[(i, j)] * xs[(i, j)]);
+
+    let xtx = xs.transpose() * &xs;
+    let x2tx2 = xs2.transpose() * &xs2;
+
+    // Sum of the variances and squares of the off-diagonal correlations
+    let mut numerator = 0.0;
+    let mut denominator = 0.0;
+    for j in 0..p {
+        for i in 0..p {
+            if i != j {
+                let w_bar = xtx[(i, j)] / n;
+                numerator += x2tx2[(i, j)] / n - w_bar * w_bar;
+                denominator += w_bar * w_bar;
+            }
+        }
+    }
+    let lambda = if denominator > 0.0 {
+        (numerator / denominator / (n - 1.0)).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+
+    let shrunk = Mat::from_fn(p, p, |i, j| {
+        if i == j {
+            1.0
+        } else {
+            (1.0 - lambda) * xtx[(i, j)] / (n - 1.0)
+        }
+    });
+    let omega = PartialPivLu::new(shrunk.as_ref()).solve(Mat::<f64>::identity(p, p));
+    let omega = Mat::from_fn(p, p, |i, j| (omega[(i, j)] + omega[(j, i)]) / 2.0);
+
+    (precision_to_pcor(omega.as_ref()), omega, lambda)
+}
@@ -1,8 +1,30 @@
-use faer::Mat;
-use std::collections::{BTreeMap, HashSet};
+use faer::{Mat, MatRef};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
+use crate::helpers_hypergeom::hypergeom_pval;
+use crate::helpers_linalg::{column_correlation, CorMethod};
 use crate::utils_rust::*;
-use crate::utils_stats::set_similarity;
+use crate::utils_stats::{set_similarity, weighted_set_similarity, WeightedSimType};
+
+/// Enum for the different matching types between modules
+#[derive(Clone, Debug)]
+pub enum MatchingType {
+    Rbh,
+    Hungarian,
+}
+
+/// Parsing the matching type
+pub fn parse_matching_type(s: &str) -> Option<MatchingType> {
+    match s.to_lowercase().as_str() {
+        "rbh" => Some(MatchingType::Rbh),
+        "hungarian" => Some(MatchingType::Hungarian),
+        _ => None,
+    }
+}
+
+/// Type alias for the consensus module groups: the node indices within the
+/// group and the mean similarity of the RBH edges within the group.
+pub type ConsensusGroup = (Vec<usize>, f64);
 
 /// Structure for an Rbh triplet Result
 #[derive(Clone, Debug)]
@@ -12,17 +34,37 @@ pub struct RbhTripletStruc<'a> {
     pub sim: f64,
 }
 
-/// Calculates the reciprocal best hits based on set similarities.
+/// Calculates the reciprocal best hits based on the (set) similarity matrix
+/// between the origin (rows) and target (columns) modules.
 pub fn calculate_rbh_set<'a>(
     origin_modules: &'a BTreeMap<String, HashSet<String>>,
     target_modules: &'a BTreeMap<String, HashSet<String>>,
-    overlap_coefficient: bool,
+    sim_mat: MatRef<f64>,
     min_similarity: f64,
     debug: bool,
 ) -> Vec<RbhTripletStruc<'a>> {
     let names_targets: Vec<&String> = target_modules.keys().collect();
     let names_origin: Vec<&String> = origin_modules.keys().collect();
 
+    rbh_from_similarity(
+        &names_origin,
+        &names_targets,
+        sim_mat,
+        min_similarity,
+        debug,
+    )
+}
+
+/// Calculates the reciprocal best hits based on a similarity matrix with the
+/// origin names representing the rows and the target names the columns.
+/// Returns an empty vector if no pair passes the threshold.
+pub fn rbh_from_similarity<'a>(
+    names_origin: &[&'a String],
+    names_targets: &[&'a String],
+    sim_mat: MatRef<f64>,
+    min_similarity: f64,
+    debug: bool,
+) -> Vec<RbhTripletStruc<'a>> {
     if debug {
         println!("Target names: {:?}", names_targets)
     }
@@ -31,22 +73,11 @@ pub fn calculate_rbh_set<'a>(
         println!("Origin names: {:?}", names_origin)
     }
 
-    let similarities_flat: Vec<Vec<f64>> = origin_modules
-        .values()
-        .map(|v1| {
-            let v1_refs: HashSet<&String> = v1.iter().collect();
-            target_modules
-                .values()
-                .map(|v2| {
-                    let v2_refs: HashSet<&String> = v2.iter().collect();
-                    set_similarity(&v1_refs, &v2_refs, overlap_coefficient)
-                })
-                .collect()
-        })
+    let mat_data: Vec<f64> = sim_mat
+        .row_iter()
+        .flat_map(|row| row.iter().copied().collect::<Vec<f64>>())
         .collect();
 
-    let mat_data: Vec<f64> = flatten_vector(similarities_flat);
-
     if debug {
         println!("Flat data {:?}", mat_data)
     };
@@ -57,17 +88,11 @@ pub fn calculate_rbh_set<'a>(
         if debug {
             println!("No similarity passed the threshold.\n\n")
         }
-        vec![RbhTripletStruc {
-            t1: "NA",
-            t2: "NA",
-            sim: 0.0,
-        }]
+        Vec::new()
     } else {
         let nrow = names_origin.len();
         let ncol = names_targets.len();
 
-        let sim_mat = Mat::from_fn(nrow, ncol, |i, j| mat_data[j + i * ncol]);
-
         if debug {
             println!("The matrix looks like: {:?}", sim_mat)
         };
@@ -116,16 +141,538 @@ pub fn calculate_rbh_set<'a>(
             );
         }
 
-        if !matching_pairs.is_empty() {
-            matching_pairs
+        matching_pairs
+    };
+
+    result
+}
+
+/// Calculates the similarity matrix between two sets of modules. Rows
+/// represent the origin modules, columns the target modules.
+pub fn set_similarity_matrix(
+    origin_modules: &BTreeMap<String, HashSet<String>>,
+    target_modules: &BTreeMap<String, HashSet<String>>,
+    overlap_coefficient: bool,
+) -> Mat<f64> {
+    let similarities_flat: Vec<Vec<f64>> = origin_modules
+        .values()
+        .map(|v1| {
+            let v1_refs: HashSet<&String> = v1.iter().collect();
+            target_modules
+                .values()
+                .map(|v2| {
+                    let v2_refs: HashSet<&String> = v2.iter().collect();
+                    set_similarity(&v1_refs, &v2_refs, overlap_coefficient)
+                })
+                .collect()
+        })
+        .collect();
+
+    let nrow = origin_modules.len();
+    let ncol = target_modules.len();
+    let mat_data: Vec<f64> = flatten_vector(similarities_flat);
+
+    Mat::from_fn(nrow, ncol, |i, j| mat_data[j + i * ncol])
+}
+
+/// Calculates the weighted similarity matrix between two sets of modules with
+/// gene weights. Rows represent the origin modules, columns the target modules.
+pub fn weighted_similarity_matrix(
+    origin_modules: &BTreeMap<String, HashMap<String, f64>>,
+    target_modules: &BTreeMap<String, HashMap<String, f64>>,
+    sim_type: &WeightedSimType,
+) -> Mat<f64> {
+    let similarities_flat: Vec<Vec<f64>> = origin_modules
+        .values()
+        .map(|w1| {
+            target_modules
+                .values()
+                .map(|w2| weighted_set_similarity(w1, w2, sim_type))
+                .collect()
+        })
+        .collect();
+
+    let nrow = origin_modules.len();
+    let ncol = target_modules.len();
+    let mat_data: Vec<f64> = flatten_vector(similarities_flat);
+
+    Mat::from_fn(nrow, ncol, |i, j| mat_data[j + i * ncol])
+}
+
+/// Calculates the hypergeometric p-values of the overlaps between two sets of
+/// modules given the size of the gene universe. Rows represent the origin
+/// modules, columns the target modules.
+pub fn set_hypergeom_pval_matrix(
+    origin_modules: &BTreeMap<String, HashSet<String>>,
+    target_modules: &BTreeMap<String, HashSet<String>>,
+    universe_size: u64,
+) -> Mat<f64> {
+    let pvals_flat: Vec<Vec<f64>> = origin_modules
+        .values()
+        .map(|v1| {
+            let m = v1.len() as u64;
+            target_modules
+                .values()
+                .map(|v2| {
+                    let hits = v1.intersection(v2).count() as u64;
+                    if hits == 0 {
+                        1.0
+                    } else {
+                        hypergeom_pval(
+                            hits - 1,
+                            m,
+                            universe_size.saturating_sub(m),
+                            v2.len() as u64,
+                        )
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let nrow = origin_modules.len();
+    let ncol = target_modules.len();
+    let mat_data: Vec<f64> = flatten_vector(pvals_flat);
+
+    Mat::from_fn(nrow, ncol, |i, j| mat_data[j + i * ncol])
+}
+
+/// Solves the maximum weight assignment problem for a (rectangular) weight
+/// matrix via the Hungarian algorithm with shortest augmenting paths
+/// (Jonker-Volgenant style potentials). Returns the assigned (row, col) pairs.
+pub fn max_weight_assignment(weights: MatRef<f64>) -> Vec<(usize, usize)> {
+    let transposed = weights.nrows() > weights.ncols();
+    let w = if transposed {
+        weights.transpose()
+    } else {
+        weights
+    };
+
+    let n = w.nrows();
+    let m = w.ncols();
+
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // Minimisation on the negative weights; 1-based indexing with index 0
+    // acting as the virtual source.
+    let cost = |i: usize, j: usize| -w[(i - 1, j - 1)];
+
+    let mut u = vec![0_f64; n + 1];
+    let mut v = vec![0_f64; m + 1];
+    let mut p = vec![0_usize; m + 1];
+    let mut way = vec![0_usize; m + 1];
+
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = cost(i0, j) - u[i0] - v[j];
+                    if cur < minv[j] {
+                        minv[j] = cur;
+                        way[j] = j0;
+                    }
+                    if minv[j] < delta {
+                        delta = minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+
+            j0 = j1;
+
+            if p[j0] == 0 {
+                break;
+            }
+        }
+
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (1..=m)
+        .filter(|&j| p[j] != 0)
+        .map(|j| {
+            if transposed {
+                (j - 1, p[j] - 1)
+            } else {
+                (p[j] - 1, j - 1)
+            }
+        })
+        .collect();
+
+    pairs.sort_unstable();
+
+    pairs
+}
+
+/// Calculates the optimal one-to-one matching between two sets of modules
+/// based on the (set) similarity matrix. Similarities below min_similarity are
+/// set to 0 before solving the assignment problem, hence, cannot be matched.
+pub fn calculate_hungarian_set<'a>(
+    origin_modules: &'a BTreeMap<String, HashSet<String>>,
+    target_modules: &'a BTreeMap<String, HashSet<String>>,
+    sim_mat: MatRef<f64>,
+    min_similarity: f64,
+    debug: bool,
+) -> Vec<RbhTripletStruc<'a>> {
+    let names_targets: Vec<&String> = target_modules.keys().collect();
+    let names_origin: Vec<&String> = origin_modules.keys().collect();
+
+    hungarian_from_similarity(
+        &names_origin,
+        &names_targets,
+        sim_mat,
+        min_similarity,
+        debug,
+    )
+}
+
+/// Calculates the optimal one-to-one matching based on a similarity matrix
+/// with the origin names representing the rows and the target names the
+/// columns. Returns an empty vector if no pair can be matched.
+pub fn hungarian_from_similarity<'a>(
+    names_origin: &[&'a String],
+    names_targets: &[&'a String],
+    sim_mat: MatRef<f64>,
+    min_similarity: f64,
+    debug: bool,
+) -> Vec<RbhTripletStruc<'a>> {
+    if debug {
+        println!("The matrix looks like: {:?}", sim_mat)
+    };
+
+    // Eligibility is tracked separately, so that pairs with a similarity of
+    // exactly 0 can still be matched if min_similarity is 0
+    let eligible = |i: usize, j: usize| sim_mat[(i, j)] >= min_similarity;
+    let weights = Mat::from_fn(sim_mat.nrows(), sim_mat.ncols(), |i, j| {
+        if eligible(i, j) {
+            sim_mat[(i, j)]
+        } else {
+            0.0
+        }
+    });
+
+    let matching_pairs: Vec<RbhTripletStruc> = max_weight_assignment(weights.as_ref())
+        .into_iter()
+        .filter(|&(r, c)| eligible(r, c))
+        .map(|(r, c)| RbhTripletStruc {
+            t1: names_origin[r],
+            t2: names_targets[c],
+            sim: sim_mat[(r, c)],
+        })
+        .collect();
+
+    if debug {
+        println!(
+            "A total of {} matched pairs were identified.\n\n",
+            matching_pairs.len()
+        );
+    }
+
+    matching_pairs
+}
+
+//////////////
+// Loadings //
+//////////////
+
+/// Calculates the correlations between the components (columns) of two
+/// loading matrices on their shared genes (rows). Returns the signed
+/// correlation matrix with the components of the origin as rows and the
+/// components of the target as columns and the number of shared genes.
+pub fn loading_correlations(
+    genes_origin: &[String],
+    loadings_origin: MatRef<f64>,
+    genes_target: &[String],
+    loadings_target: MatRef<f64>,
+    spearman: bool,
+) -> (Mat<f64>, usize) {
+    let target_idx: HashMap<&String, usize> = genes_target
+        .iter()
+        .enumerate()
+        .map(|(i, gene)| (gene, i))
+        .collect();
+
+    let shared: Vec<(usize, usize)> = genes_origin
+        .iter()
+        .enumerate()
+        .filter_map(|(i, gene)| target_idx.get(gene).map(|&j| (i, j)))
+        .collect();
+
+    let n_origin = loadings_origin.ncols();
+    let n_target = loadings_target.ncols();
+
+    let combined = Mat::from_fn(shared.len(), n_origin + n_target, |i, j| {
+        if j < n_origin {
+            loadings_origin[(shared[i].0, j)]
         } else {
-            vec![RbhTripletStruc {
-                t1: "NA",
-                t2: "NA",
-                sim: 0.0,
-            }]
+            loadings_target[(shared[i].1, j - n_origin)]
         }
+    });
+
+    let method = if spearman {
+        CorMethod::Spearman
+    } else {
+        CorMethod::Pearson
     };
+    let cor = column_correlation(combined.as_ref(), &method);
 
-    result
+    let cross_cor = Mat::from_fn(n_origin, n_target, |i, j| cor[(i, n_origin + j)]);
+
+    (cross_cor, shared.len())
+}
+
+///////////////////////
+// Consensus modules //
+///////////////////////
+
+/// Structure for the RBH graph to identify consensus modules. The nodes are
+/// the (origin, module) pairs, the edges the RBH pairs with their similarity.
+#[derive(Clone, Debug)]
+pub struct RbhGraph {
+    pub nodes: Vec<(String, String)>,
+    pub adjacency: Vec<BTreeMap<usize, f64>>,
+}
+
+impl RbhGraph {
+    /// Generate the RBH graph from the edges.
+    pub fn new(
+        origin: &[String],
+        origin_modules: &[String],
+        target: &[String],
+        target_modules: &[String],
+        similarity: &[f64],
+    ) -> Self {
+        let mut node_idx: HashMap<(String, String), usize> = HashMap::new();
+        let mut nodes: Vec<(String, String)> = Vec::new();
+        let mut adjacency: Vec<BTreeMap<usize, f64>> = Vec::new();
+
+        let mut get_idx = |node: (String, String)| -> usize {
+            *node_idx.entry(node.clone()).or_insert_with(|| {
+                nodes.push(node);
+                adjacency.push(BTreeMap::new());
+                nodes.len() - 1
+            })
+        };
+
+        let edges: Vec<(usize, usize, f64)> = (0..origin.len())
+            .map(|i| {
+                let from = get_idx((origin[i].clone(), origin_modules[i].clone()));
+                let to = get_idx((target[i].clone(), target_modules[i].clone()));
+                (from, to, similarity[i])
+            })
+            .collect();
+
+        for (from, to, sim) in edges {
+            if from != to {
+                adjacency[from].insert(to, sim);
+                adjacency[to].insert(from, sim);
+            }
+        }
+
+        RbhGraph { nodes, adjacency }
+    }
+
+    /// Returns the mean similarity of the edges within a group of nodes.
+    fn mean_similarity(&self, group: &[usize]) -> f64 {
+        let mut sum = 0.0;
+        let mut n = 0;
+        for (i, &a) in group.iter().enumerate() {
+            for &b in &group[i + 1..] {
+                if let Some(sim) = self.adjacency[a].get(&b) {
+                    sum += sim;
+                    n += 1;
+                }
+            }
+        }
+        if n == 0 {
+            0.0
+        } else {
+            sum / n as f64
+        }
+    }
+
+    /// Identify all maximal cliques via the Bron-Kerbosch algorithm with
+    /// pivoting.
+    pub fn maximal_cliques(&self) -> Vec<Vec<usize>> {
+        let mut cliques = Vec::new();
+        let p: BTreeSet<usize> = (0..self.nodes.len()).collect();
+        self.bron_kerbosch(Vec::new(), p, BTreeSet::new(), &mut cliques);
+        cliques
+    }
+
+    fn bron_kerbosch(
+        &self,
+        r: Vec<usize>,
+        mut p: BTreeSet<usize>,
+        mut x: BTreeSet<usize>,
+        cliques: &mut Vec<Vec<usize>>,
+    ) {
+        if p.is_empty() {
+            if x.is_empty() {
+                cliques.push(r);
+            }
+            return;
+        }
+
+        let pivot = *p
+            .union(&x)
+            .max_by_key(|&&u| {
+                p.iter()
+                    .filter(|v| self.adjacency[u].contains_key(v))
+                    .count()
+            })
+            .unwrap();
+
+        let candidates: Vec<usize> = p
+            .iter()
+            .filter(|v| !self.adjacency[pivot].contains_key(v))
+            .copied()
+            .collect();
+
+        for v in candidates {
+            let mut r_new = r.clone();
+            r_new.push(v);
+            let p_new: BTreeSet<usize> = p
+                .iter()
+                .filter(|u| self.adjacency[v].contains_key(u))
+                .copied()
+                .collect();
+            let x_new: BTreeSet<usize> = x
+                .iter()
+                .filter(|u| self.adjacency[v].contains_key(u))
+                .copied()
+                .collect();
+            self.bron_kerbosch(r_new, p_new, x_new, cliques);
+            p.remove(&v);
+            x.insert(v);
+        }
+    }
+
+    /// Greedily extends a clique into a near-clique. Nodes from origins not yet
+    /// part of the group are added if they are connected to at least
+    /// min_density of the group members.
+    fn extend_group(&self, mut group: Vec<usize>, min_density: f64) -> Vec<usize> {
+        loop {
+            let origins: HashSet<&String> = group.iter().map(|&i| &self.nodes[i].0).collect();
+            let required = (min_density * group.len() as f64).ceil() as usize;
+
+            let best = group
+                .iter()
+                .flat_map(|&i| self.adjacency[i].keys().copied())
+                .filter(|c| !group.contains(c) && !origins.contains(&self.nodes[*c].0))
+                .collect::<BTreeSet<usize>>()
+                .into_iter()
+                .map(|c| {
+                    let links: Vec<f64> = group
+                        .iter()
+                        .filter_map(|g| self.adjacency[c].get(g).copied())
+                        .collect();
+                    (c, links.len(), links.iter().sum::<f64>())
+                })
+                .filter(|&(_, n_links, _)| n_links >= required.max(1))
+                .max_by(|a, b| {
+                    a.1.cmp(&b.1)
+                        .then(a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal))
+                        .then(b.0.cmp(&a.0))
+                });
+
+            match best {
+                Some((c, _, _)) => group.push(c),
+                None => break,
+            }
+        }
+
+        group.sort_unstable();
+        group
+    }
+
+    /// Identify non-overlapping consensus groups, i.e., cliques or near-cliques
+    /// of RBH edges spanning at least min_origins origins. Larger groups and
+    /// within size, groups with higher mean similarity are preferred.
+    pub fn consensus_groups(&self, min_origins: usize, min_density: f64) -> Vec<ConsensusGroup> {
+        let mut candidates: Vec<ConsensusGroup> = self
+            .maximal_cliques()
+            .into_iter()
+            .map(|clique| {
+                let group = self.extend_group(clique, min_density);
+                let sim = self.mean_similarity(&group);
+                (group, sim)
+            })
+            .filter(|(group, _)| group.len() >= min_origins.max(2))
+            .collect();
+
+        candidates.sort_by(|a, b| {
+            b.0.len()
+                .cmp(&a.0.len())
+                .then(b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal))
+                .then(a.0.cmp(&b.0))
+        });
+
+        let mut used: HashSet<usize> = HashSet::new();
+        let mut groups = Vec::new();
+
+        for (group, sim) in candidates {
+            if group.iter().all(|i| !used.contains(i)) {
+                used.extend(group.iter().copied());
+                groups.push((group, sim));
+            }
+        }
+
+        groups
+    }
+}
+
+/// Calculates the consensus gene set of a group of modules. Returns the genes
+/// (sorted by support and name) and the number of modules (i.e., origins) that
+/// contain the gene. Only genes with a support of at least min_support are
+/// returned.
+pub fn consensus_gene_support(
+    modules: &[&HashSet<String>],
+    min_support: usize,
+) -> (Vec<String>, Vec<usize>) {
+    let mut counts: HashMap<&String, usize> = HashMap::new();
+    for module in modules {
+        for gene in module.iter() {
+            *counts.entry(gene).or_insert(0) += 1;
+        }
+    }
+
+    let mut support: Vec<(&String, usize)> = counts
+        .into_iter()
+        .filter(|&(_, n)| n >= min_support)
+        .collect();
+    support.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    support
+        .into_iter()
+        .map(|(gene, n)| (gene.clone(), n))
+        .unzip()
 }
@@ -0,0 +1,725 @@
+use faer::linalg::solvers::{PartialPivLu, Solve};
+use faer::{Mat, MatRef};
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use statrs::distribution::{ContinuousCDF, StudentsT};
+
+use crate::helpers_linalg::{
+    column_correlation, randomised_svd, scale_matrix_col, unit_columns, CorMethod,
+};
+use crate::utils_rust::{median, rank_vector, upper_triangle_to_sym_faer};
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the WGCNA adjacency types
+#[derive(Clone, Debug)]
+pub enum AdjacencyType {
+    Unsigned,
+    Signed,
+    SignedHybrid,
+}
+
+/// Parsing the adjacency type
+pub fn parse_adjacency_type(s: &str) -> Option<AdjacencyType> {
+    match s.to_lowercase().as_str() {
+        "unsigned" => Some(AdjacencyType::Unsigned),
+        "signed" => Some(AdjacencyType::Signed),
+        "signed_hybrid" => Some(AdjacencyType::SignedHybrid),
+        _ => None,
+    }
+}
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the scale-free topology fit of a given soft threshold power
+#[derive(Clone, Debug)]
+pub struct ScaleFreeFit {
+    pub power: f64,
+    pub sft_r2: f64,
+    pub slope: f64,
+    pub truncated_r2: f64,
+    pub mean_k: f64,
+    pub median_k: f64,
+    pub max_k: f64,
+}
+
+/// Structure for the module eigengenes
+#[derive(Clone, Debug)]
+pub struct ModuleEigengenes {
+    pub eigengenes: Mat<f64>,
+    pub var_explained: Vec<f64>,
+}
+
+/// Structure for the module membership (kME)
+#[derive(Clone, Debug)]
+pub struct ModuleMembership {
+    pub kme: Mat<f64>,
+    pub p_vals: Mat<f64>,
+}
+
+/// Structure for the intramodular connectivity
+#[derive(Clone, Debug)]
+pub struct IntramodularConnectivity {
+    pub k_total: Vec<f64>,
+    pub k_within: Vec<f64>,
+    pub k_out: Vec<f64>,
+    pub k_diff: Vec<f64>,
+}
+
+/// Structure for the module preservation results. The columns of observed and
+/// z_scores are the preservation statistics in the order mean_cor, mean_adj,
+/// prop_var_explained, mean_kme, cor_kim, cor_kme and cor_cor.
+#[derive(Clone, Debug)]
+pub struct ModulePreservationRes {
+    pub observed: Mat<f64>,
+    pub z_scores: Mat<f64>,
+    pub z_density: Vec<f64>,
+    pub z_connectivity: Vec<f64>,
+    pub z_summary: Vec<f64>,
+    pub median_rank: Vec<f64>,
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Soft thresholding of a correlation coefficient
+pub fn soft_threshold(r: f64, power: f64, adjacency_type: &AdjacencyType) -> f64 {
+    match adjacency_type {
+        AdjacencyType::Unsigned => r.abs().powf(power),
+        AdjacencyType::Signed => ((1.0 + r) / 2.0).powf(power),
+        AdjacencyType::SignedHybrid => {
+            if r > 0.0 {
+                r.powf(power)
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+/// Connectivity of every feature (sum of the adjacencies to all other
+/// features) based on the upper triangle of the correlation matrix.
+fn connectivity(
+    cor: &[f64],
+    shift: usize,
+    n: usize,
+    power: f64,
+    adjacency_type: &AdjacencyType,
+) -> Vec<f64> {
+    let mut k = vec![0.0; n];
+    let mut idx = 0;
+    for i in 0..n {
+        for j in (i + shift)..n {
+            if i != j {
+                let a = soft_threshold(cor[idx], power, adjacency_type);
+                k[i] += a;
+                k[j] += a;
+            }
+            idx += 1;
+        }
+    }
+
+    k
+}
+
+/// Goodness of fit of the connectivity to a scale-free topology, equivalent to
+/// `WGCNA::scaleFreeFitIndex()`. The connectivities are binned into n_breaks
+/// equal-width bins and log10(p(k)) is regressed on log10(k). Returns the R2,
+/// the slope and the adjusted R2 of the truncated exponential fit (with k as
+/// additional covariate).
+pub fn scale_free_fit(k: &[f64], n_breaks: usize) -> (f64, f64, f64) {
+    let (min, max) = k
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    let range = max - min;
+    if range <= 0.0 {
+        return (f64::NAN, f64::NAN, f64::NAN);
+    }
+    let width = range / n_breaks as f64;
+
+    // Right-closed bins, the outer breaks are widened by 0.1 % like in cut()
+    let mut breaks: Vec<f64> = (0..=n_breaks).map(|t| min + t as f64 * width).collect();
+    breaks[n_breaks] = max + range / 1000.0;
+    breaks[0] = min - range / 1000.0;
+
+    let mut sums = vec![0.0; n_breaks];
+    let mut counts = vec![0_usize; n_breaks];
+    for &v in k {
+        let bin = (0..n_breaks)
+            .find(|&t| v <= breaks[t + 1])
+            .unwrap_or(n_breaks - 1);
+        sums[bin] += v;
+        counts[bin] += 1;
+    }
+
+    // Empty bins are represented by their mid points
+    let mut dk: Vec<f64> = (0..n_breaks)
+        .map(|t| {
+            if counts[t] > 0 {
+                sums[t] / counts[t] as f64
+            } else {
+                min + (t as f64 + 0.5) * width
+            }
+        })
+        .collect();
+    let min_positive = dk
+        .iter()
+        .copied()
+        .filter(|&v| v > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    for v in dk.iter_mut() {
+        if *v == 0.0 {
+            *v = min_positive;
+        }
+    }
+
+    let log_dk: Vec<f64> = dk.iter().map(|v| v.log10()).collect();
+    let log_p_dk: Vec<f64> = counts
+        .iter()
+        .map(|&c| (c as f64 / k.len() as f64 + 1e-9).log10())
+        .collect();
+
+    // Simple linear regression
+    let n = n_breaks as f64;
+    let mean_x = log_dk.iter().sum::<f64>() / n;
+    let mean_y = log_p_dk.iter().sum::<f64>() / n;
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in log_dk.iter().zip(log_p_dk.iter()) {
+        sxy += (x - mean_x) * (y - mean_y);
+        sxx += (x - mean_x).powi(2);
+        syy += (y - mean_y).powi(2);
+    }
+    let slope = sxy / sxx;
+    let r2 = sxy * sxy / (sxx * syy);
+
+    // Truncated exponential fit: log10(p(k)) ~ log10(k) + k
+    let design = Mat::from_fn(n_breaks, 3, |i, j| match j {
+        0 => 1.0,
+        1 => log_dk[i],
+        _ => dk[i],
+    });
+    let y = Mat::from_fn(n_breaks, 1, |i, _| log_p_dk[i]);
+    let xtx = design.transpose() * &design;
+    let xty = design.transpose() * &y;
+    let beta = PartialPivLu::new(xtx.as_ref()).solve(&xty);
+    let fitted = &design * &beta;
+    let rss: f64 = (0..n_breaks)
+        .map(|i| (y[(i, 0)] - fitted[(i, 0)]).powi(2))
+        .sum();
+    let truncated_r2 = 1.0 - (rss / syy) * (n - 1.0) / (n - 3.0);
+
+    (r2, slope, truncated_r2)
+}
+
+//////////
+// Main //
+//////////
+
+/// Scale-free topology fit and connectivity statistics for a set of soft
+/// threshold powers, equivalent to `WGCNA::pickSoftThreshold()`. Works on the
+/// upper triangle of the correlation matrix; the powers are processed in
+/// parallel.
+pub fn soft_threshold_powers(
+    cor: &[f64],
+    shift: usize,
+    n: usize,
+    powers: &[f64],
+    adjacency_type: &AdjacencyType,
+    n_breaks: usize,
+) -> Vec<ScaleFreeFit> {
+    powers
+        .par_iter()
+        .map(|&power| {
+            let k = connectivity(cor, shift, n, power, adjacency_type);
+            let (r2, slope, truncated_r2) = scale_free_fit(&k, n_breaks);
+
+            ScaleFreeFit {
+                power,
+                sft_r2: -slope.signum() * r2,
+                slope,
+                truncated_r2,
+                mean_k: k.iter().sum::<f64>() / n as f64,
+                median_k: median(&k),
+                max_k: k.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            }
+        })
+        .collect()
+}
+
+/// Generates the soft thresholded adjacency matrix from the upper triangle of
+/// the correlation matrix. The diagonal is set to 1.
+pub fn adjacency_matrix(
+    cor: &[f64],
+    shift: usize,
+    n: usize,
+    power: f64,
+    adjacency_type: &AdjacencyType,
+) -> Mat<f64> {
+    let cor_mat = upper_triangle_to_sym_faer(cor, shift, n);
+
+    Mat::from_fn(n, n, |i, j| {
+        if i == j {
+            1.0
+        } else {
+            soft_threshold(cor_mat[(i, j)], power, adjacency_type)
+        }
+    })
+}
+
+/// Calculates the topological overlap matrix (TOM) of an adjacency matrix,
+/// i.e., (l_ij + a_ij) / (min(k_i, k_j) + 1 - a_ij) with l_ij being the shared
+/// neighbourhood and k_i the connectivity. The shared neighbourhood is
+/// calculated in column blocks of block_size via GEMMs to avoid a second
+/// n x n temporary matrix. The diagonal of the adjacency is ignored.
+pub fn topological_overlap(adj: MatRef<f64>, block_size: usize) -> Mat<f64> {
+    let n = adj.ncols();
+    let a = Mat::from_fn(n, n, |i, j| if i == j { 0.0 } else { adj[(i, j)] });
+    let k: Vec<f64> = (0..n).map(|j| a.col(j).iter().sum()).collect();
+    let block_size = block_size.clamp(1, n.max(1));
+
+    let mut tom = Mat::<f64>::zeros(n, n);
+    for start in (0..n).step_by(block_size) {
+        let end = (start + block_size).min(n);
+        let shared = a.as_ref() * a.get(.., start..end);
+
+        tom.get_mut(.., start..end)
+            .par_col_iter_mut()
+            .enumerate()
+            .for_each(|(jj, mut col)| {
+                let j = start + jj;
+                for i in 0..n {
+                    col[i] = if i == j {
+                        1.0
+                    } else {
+                        (shared[(i, jj)] + a[(i, j)]) / (k[i].min(k[j]) + 1.0 - a[(i, j)])
+                    };
+                }
+            });
+    }
+
+    tom
+}
+
+///////////////////////
+// Module statistics //
+///////////////////////
+
+/// Transforms the (1-indexed) module assignment from R into 0-indexed modules.
+/// Values < 1 (including NA) are treated as unassigned.
+pub fn parse_module_assignment(modules: &[i32]) -> (Vec<Option<usize>>, usize) {
+    let assignment: Vec<Option<usize>> = modules
+        .iter()
+        .map(|&m| if m >= 1 { Some(m as usize - 1) } else { None })
+        .collect();
+    let n_modules = assignment
+        .iter()
+        .flatten()
+        .map(|m| m + 1)
+        .max()
+        .unwrap_or(0);
+
+    (assignment, n_modules)
+}
+
+/// Calculates the eigengene of every module, i.e., the first principal
+/// component of the standardised expression of the module members, equivalent
+/// to `WGCNA::moduleEigengenes()`. The sign is aligned with the average
+/// standardised expression. The variance explained is the proportion of the
+/// total variance of the module captured by the eigengene. Modules are
+/// processed in parallel.
+pub fn module_eigengenes(
+    mat: MatRef<f64>,
+    modules: &[Option<usize>],
+    n_modules: usize,
+    fast_svd: bool,
+    seed: usize,
+) -> ModuleEigengenes {
+    let n = mat.nrows();
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); n_modules];
+    for (gene, module) in modules.iter().enumerate() {
+        if let Some(m) = module {
+            members[*m].push(gene);
+        }
+    }
+
+    let res: Vec<(Vec<f64>, f64)> = members
+        .par_iter()
+        .map(|genes| {
+            if genes.is_empty() {
+                return (vec![f64::NAN; n], f64::NAN);
+            }
+            let sub = Mat::from_fn(n, genes.len(), |i, j| mat[(i, genes[j])]);
+            let scaled = scale_matrix_col(sub.as_ref(), true);
+            let total_var = scaled.squared_norm_l2();
+
+            let (mut eigengene, d): (Vec<f64>, f64) = if fast_svd {
+                let svd_res = randomised_svd(scaled.as_ref(), 1, seed, None, None);
+                (svd_res.u.col(0).iter().copied().collect(), svd_res.s[0])
+            } else {
+                let svd_res = scaled.thin_svd().unwrap();
+                (
+                    svd_res.U().col(0).iter().copied().collect(),
+                    svd_res.S().column_vector()[0],
+                )
+            };
+
+            let alignment: f64 = (0..n)
+                .map(|i| eigengene[i] * scaled.row(i).iter().sum::<f64>())
+                .sum();
+            if alignment < 0.0 {
+                eigengene.iter_mut().for_each(|v| *v = -*v);
+            }
+
+            (eigengene, d * d / total_var)
+        })
+        .collect();
+
+    ModuleEigengenes {
+        eigengenes: Mat::from_fn(n, n_modules, |i, j| res[j].0[i]),
+        var_explained: res.iter().map(|r| r.1).collect(),
+    }
+}
+
+/// Calculates the module membership (kME), i.e., the correlations of every
+/// feature with every module eigengene, together with Student t-test p-values,
+/// equivalent to `WGCNA::corPvalueStudent()`. The p-values are NaN with two or
+/// fewer samples. Returns None for Kendall's tau.
+pub fn module_membership(
+    mat: MatRef<f64>,
+    eigengenes: MatRef<f64>,
+    method: &CorMethod,
+) -> Option<ModuleMembership> {
+    let unit_mat = unit_columns(mat, method)?;
+    let unit_eigengenes = unit_columns(eigengenes, method)?;
+    let kme = unit_mat.transpose() * &unit_eigengenes;
+
+    // The Student t-test needs more than two samples
+    if mat.nrows() <= 2 {
+        let p_vals = Mat::from_fn(kme.nrows(), kme.ncols(), |_, _| f64::NAN);
+        return Some(ModuleMembership { kme, p_vals });
+    }
+
+    let df = (mat.nrows() - 2) as f64;
+    let t_dist = StudentsT::new(0.0, 1.0, df).unwrap();
+    let p_vals = Mat::from_fn(kme.nrows(), kme.ncols(), |i, j| {
+        let r = kme[(i, j)].clamp(-1.0, 1.0);
+        if r.is_nan() {
+            f64::NAN
+        } else if r.abs() >= 1.0 {
+            0.0
+        } else {
+            let t = r * (df / (1.0 - r * r)).sqrt();
+            2.0 * t_dist.sf(t.abs())
+        }
+    });
+
+    Some(ModuleMembership { kme, p_vals })
+}
+
+/// Calculates the whole network (k_total) and the intramodular (k_within)
+/// connectivity of every feature based on the soft thresholded correlations,
+/// equivalent to `WGCNA::intramodularConnectivity()`. The correlations are
+/// calculated in column blocks of block_size via GEMMs, so the adjacency
+/// matrix is never materialised. Unassigned features have NaN within-module
+/// statistics. Returns None for Kendall's tau.
+pub fn intramodular_connectivity(
+    mat: MatRef<f64>,
+    modules: &[Option<usize>],
+    method: &CorMethod,
+    power: f64,
+    adjacency_type: &AdjacencyType,
+    block_size: usize,
+) -> Option<IntramodularConnectivity> {
+    let unit = unit_columns(mat, method)?;
+    let p = unit.ncols();
+    let block_size = block_size.clamp(1, p.max(1));
+
+    let mut k_total = Vec::with_capacity(p);
+    let mut k_within = Vec::with_capacity(p);
+    for start in (0..p).step_by(block_size) {
+        let end = (start + block_size).min(p);
+        let tile = unit.transpose() * unit.get(.., start..end);
+
+        let block_res: Vec<(f64, f64)> = tile
+            .par_col_iter()
+            .enumerate()
+            .map(|(jj, col)| {
+                let j = start + jj;
+                let mut total = 0.0;
+                let mut within = 0.0;
+                for (i, &r) in col.iter().enumerate() {
+                    if i == j {
+                        continue;
+                    }
+                    let a = soft_threshold(r.clamp(-1.0, 1.0), power, adjacency_type);
+                    total += a;
+                    if modules[j].is_some() && modules[i] == modules[j] {
+                        within += a;
+                    }
+                }
+                if modules[j].is_none() {
+                    within = f64::NAN;
+                }
+                (total, within)
+            })
+            .collect();
+
+        for (total, within) in block_res {
+            k_total.push(total);
+            k_within.push(within);
+        }
+    }
+
+    let k_out: Vec<f64> = k_total
+        .iter()
+        .zip(k_within.iter())
+        .map(|(t, w)| t - w)
+        .collect();
+    let k_diff: Vec<f64> = k_within
+        .iter()
+        .zip(k_out.iter())
+        .map(|(w, o)| w - o)
+        .collect();
+
+    Some(IntramodularConnectivity {
+        k_total,
+        k_within,
+        k_out,
+        k_diff,
+    })
+}
+
+/////////////////////////
+// Module preservation //
+/////////////////////////
+
+/// Number of preservation statistics (four density- and three
+/// connectivity-based ones)
+const N_PRESERVATION_STATS: usize = 7;
+const N_DENSITY_STATS: usize = 4;
+
+/// Pearson correlation between two vectors. Returns NaN if one of them is
+/// constant.
+fn vector_correlation(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y.iter()) {
+        sxy += (a - mean_x) * (b - mean_y);
+        sxx += (a - mean_x).powi(2);
+        syy += (b - mean_y).powi(2);
+    }
+
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Correlations between the features of a gene set, their kME and the
+/// variance explained by the eigengene of the gene set.
+fn gene_set_network(
+    mat: MatRef<f64>,
+    genes: &[usize],
+    method: &CorMethod,
+) -> (Mat<f64>, Vec<f64>, f64) {
+    let n = mat.nrows();
+    let m = genes.len();
+    let sub = Mat::from_fn(n, m, |i, j| mat[(i, genes[j])]);
+    let eigengene = module_eigengenes(sub.as_ref(), &vec![Some(0); m], 1, false, 0);
+
+    // The eigengene is added as last column to get the kME in one go
+    let with_eigengene = Mat::from_fn(n, m + 1, |i, j| {
+        if j < m {
+            sub[(i, j)]
+        } else {
+            eigengene.eigengenes[(i, 0)]
+        }
+    });
+    let cor = column_correlation(with_eigengene.as_ref(), method);
+
+    (
+        cor.get(0..m, 0..m).to_owned(),
+        (0..m).map(|i| cor[(i, m)]).collect(),
+        eigengene.var_explained[0],
+    )
+}
+
+/// Calculates the preservation statistics of a gene set between the reference
+/// and test data (Langfelder, et al., 2011). Density-based: the mean
+/// sign-aware correlation, the mean adjacency, the variance explained by the
+/// eigengene and the mean sign-aware kME in the test data. Connectivity-based:
+/// the correlations of the intramodular connectivity, the kME and the
+/// pairwise correlations between reference and test data.
+fn preservation_stats(
+    ref_mat: MatRef<f64>,
+    test_mat: MatRef<f64>,
+    genes: &[usize],
+    method: &CorMethod,
+    power: f64,
+    adjacency_type: &AdjacencyType,
+) -> [f64; N_PRESERVATION_STATS] {
+    let m = genes.len();
+    if m < 3 {
+        return [f64::NAN; N_PRESERVATION_STATS];
+    }
+    let (cor_ref, kme_ref, _) = gene_set_network(ref_mat, genes, method);
+    let (cor_test, kme_test, var_explained) = gene_set_network(test_mat, genes, method);
+
+    let mut pairs_ref = Vec::with_capacity(m * (m - 1) / 2);
+    let mut pairs_test = Vec::with_capacity(m * (m - 1) / 2);
+    let mut kim_ref = vec![0.0; m];
+    let mut kim_test = vec![0.0; m];
+    for i in 0..m {
+        for j in (i + 1)..m {
+            pairs_ref.push(cor_ref[(i, j)]);
+            pairs_test.push(cor_test[(i, j)]);
+            let a_ref = soft_threshold(cor_ref[(i, j)], power, adjacency_type);
+            let a_test = soft_threshold(cor_test[(i, j)], power, adjacency_type);
+            kim_ref[i] += a_ref;
+            kim_ref[j] += a_ref;
+            kim_test[i] += a_test;
+            kim_test[j] += a_test;
+        }
+    }
+    let n_pairs = pairs_ref.len() as f64;
+
+    let mean_cor = pairs_ref
+        .iter()
+        .zip(pairs_test.iter())
+        .map(|(r, t)| r.signum() * t)
+        .sum::<f64>()
+        / n_pairs;
+    let mean_adj = pairs_test
+        .iter()
+        .map(|&t| soft_threshold(t, power, adjacency_type))
+        .sum::<f64>()
+        / n_pairs;
+    let mean_kme = kme_ref
+        .iter()
+        .zip(kme_test.iter())
+        .map(|(r, t)| r.signum() * t)
+        .sum::<f64>()
+        / m as f64;
+
+    [
+        mean_cor,
+        mean_adj,
+        var_explained,
+        mean_kme,
+        vector_correlation(&kim_ref, &kim_test),
+        vector_correlation(&kme_ref, &kme_test),
+        vector_correlation(&pairs_ref, &pairs_test),
+    ]
+}
+
+/// Module preservation statistics between a reference and a test data set
+/// (same features in the same order), equivalent to
+/// `WGCNA::modulePreservation()`. For every module, the observed statistics
+/// are compared against the statistics of n_perm random gene sets of the same
+/// size to get Z-scores. Z density (median of the density Z-scores) and Z
+/// connectivity (median of the connectivity Z-scores) are averaged to Z
+/// summary. The median rank is based on the ranks of the observed statistics
+/// across modules (lower = better preserved). The permutations are seeded and
+/// processed in parallel.
+#[allow(clippy::too_many_arguments)]
+pub fn module_preservation(
+    ref_mat: MatRef<f64>,
+    test_mat: MatRef<f64>,
+    modules: &[Option<usize>],
+    n_modules: usize,
+    method: &CorMethod,
+    power: f64,
+    adjacency_type: &AdjacencyType,
+    n_perm: usize,
+    seed: usize,
+) -> ModulePreservationRes {
+    let p = ref_mat.ncols();
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); n_modules];
+    for (gene, module) in modules.iter().enumerate() {
+        if let Some(m) = module {
+            members[*m].push(gene);
+        }
+    }
+
+    let observed: Vec<[f64; N_PRESERVATION_STATS]> = members
+        .par_iter()
+        .map(|genes| preservation_stats(ref_mat, test_mat, genes, method, power, adjacency_type))
+        .collect();
+
+    let permuted: Vec<[f64; N_PRESERVATION_STATS]> = (0..(n_modules * n_perm))
+        .into_par_iter()
+        .map(|idx| {
+            let size = members[idx / n_perm].len();
+            let mut rng = StdRng::seed_from_u64((seed + idx) as u64);
+            let genes = sample(&mut rng, p, size.min(p)).into_vec();
+            preservation_stats(ref_mat, test_mat, &genes, method, power, adjacency_type)
+        })
+        .collect();
+
+    let z_scores = Mat::from_fn(n_modules, N_PRESERVATION_STATS, |module, stat| {
+        let null: Vec<f64> = permuted[(module * n_perm)..((module + 1) * n_perm)]
+            .iter()
+            .map(|x| x[stat])
+            .filter(|x| x.is_finite())
+            .collect();
+        let n = null.len() as f64;
+        let mean = null.iter().sum::<f64>() / n;
+        let sd = (null.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+        (observed[module][stat] - mean) / sd
+    });
+
+    let z_density: Vec<f64> = (0..n_modules)
+        .map(|module| {
+            let z: Vec<f64> = (0..N_DENSITY_STATS)
+                .map(|stat| z_scores[(module, stat)])
+                .collect();
+            median(&z)
+        })
+        .collect();
+    let z_connectivity: Vec<f64> = (0..n_modules)
+        .map(|module| {
+            let z: Vec<f64> = (N_DENSITY_STATS..N_PRESERVATION_STATS)
+                .map(|stat| z_scores[(module, stat)])
+                .collect();
+            median(&z)
+        })
+        .collect();
+    let z_summary: Vec<f64> = z_density
+        .iter()
+        .zip(z_connectivity.iter())
+        .map(|(d, c)| (d + c) / 2.0)
+        .collect();
+
+    // Rank 1 = highest observed statistic
+    let ranks: Vec<Vec<f64>> = (0..N_PRESERVATION_STATS)
+        .map(|stat| {
+            let values: Vec<f64> = observed.iter().map(|x| -x[stat]).collect();
+            rank_vector(&values)
+        })
+        .collect();
+    let median_rank: Vec<f64> = (0..n_modules)
+        .map(|module| {
+            let density: Vec<f64> = ranks[..N_DENSITY_STATS].iter().map(|r| r[module]).collect();
+            let connectivity: Vec<f64> =
+                ranks[N_DENSITY_STATS..].iter().map(|r| r[module]).collect();
+            (median(&density) + median(&connectivity)) / 2.0
+        })
+        .collect();
+
+    ModulePreservationRes {
+        observed: Mat::from_fn(n_modules, N_PRESERVATION_STATS, |i, j| observed[i][j]),
+        z_scores,
+        z_density,
+        z_connectivity,
+        z_summary,
+        median_rank,
+    }
+}
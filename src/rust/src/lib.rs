@@ -1,11 +1,22 @@
+mod helpers_auc;
+mod helpers_combat;
+mod helpers_community;
+mod helpers_cor_blocked;
+mod helpers_dge;
 mod helpers_fgsea;
 mod helpers_geom_elim;
 mod helpers_hypergeom;
 mod helpers_ica;
 mod helpers_linalg;
+mod helpers_mi;
+mod helpers_minhash;
 mod helpers_ontology;
+mod helpers_pcor;
 mod helpers_rbh;
+mod helpers_wgcna;
 
+mod fun_community;
+mod fun_dge;
 mod fun_fgsea;
 mod fun_helpers;
 mod fun_hypergeom;
@@ -14,7 +25,11 @@ mod fun_linalg;
 mod fun_ontology;
 mod fun_rbh;
 mod fun_stats;
+mod fun_wgcna;
 
+mod utils_hypergeom;
+mod utils_meta_analysis;
+mod utils_multiple_testing;
 mod utils_r_rust;
 mod utils_rust;
 mod utils_stats;
@@ -31,4 +46,7 @@ extendr_module! {
     use fun_helpers;
     use fun_ica;
     use fun_ontology;
+    use fun_dge;
+    use fun_wgcna;
+    use fun_community;
 }
@@ -0,0 +1,150 @@
+use statrs::function::gamma::ln_gamma;
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the hypergeometric distribution. m is the number of white
+/// balls in the urn, n the number of black balls in the urn and k the number
+/// of balls drawn out of the urn.
+#[derive(Clone, Debug)]
+pub struct Hypergeometric {
+    m: u64,
+    n: u64,
+    k: u64,
+    ln_denominator: f64,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl Hypergeometric {
+    /// Create a new hypergeometric distribution. Returns None if more balls
+    /// are drawn than are in the urn.
+    pub fn new(m: u64, n: u64, k: u64) -> Option<Self> {
+        if k > m + n {
+            return None;
+        }
+        let ln_denominator = ln_binomial(m + n, k);
+
+        Some(Hypergeometric {
+            m,
+            n,
+            k,
+            ln_denominator,
+        })
+    }
+
+    /// Lower bound of the support
+    pub fn min(&self) -> u64 {
+        self.k.saturating_sub(self.n)
+    }
+
+    /// Upper bound of the support
+    pub fn max(&self) -> u64 {
+        self.k.min(self.m)
+    }
+
+    /// Log of the probability mass function
+    pub fn ln_pmf(&self, x: i64) -> f64 {
+        if x < self.min() as i64 || x > self.max() as i64 {
+            return f64::NEG_INFINITY;
+        }
+        let x = x as u64;
+
+        ln_binomial(self.m, x) + ln_binomial(self.n, self.k - x) - self.ln_denominator
+    }
+
+    /// Probability mass function
+    pub fn pmf(&self, x: i64, log_p: bool) -> f64 {
+        let ln_pmf = self.ln_pmf(x);
+        if log_p {
+            ln_pmf
+        } else {
+            ln_pmf.exp()
+        }
+    }
+
+    /// Log of P(X <= q), summed in log space.
+    pub fn ln_cdf(&self, q: i64) -> f64 {
+        let (min, max) = (self.min() as i64, self.max() as i64);
+        if q < min {
+            return f64::NEG_INFINITY;
+        }
+        if q >= max {
+            return 0.0;
+        }
+        let ln_pmfs: Vec<f64> = (min..=q).map(|x| self.ln_pmf(x)).collect();
+
+        log_sum_exp(&ln_pmfs).min(0.0)
+    }
+
+    /// Log of P(X > q), summed in log space.
+    pub fn ln_sf(&self, q: i64) -> f64 {
+        let (min, max) = (self.min() as i64, self.max() as i64);
+        if q < min {
+            return 0.0;
+        }
+        if q >= max {
+            return f64::NEG_INFINITY;
+        }
+        let ln_pmfs: Vec<f64> = (q + 1..=max).map(|x| self.ln_pmf(x)).collect();
+
+        log_sum_exp(&ln_pmfs).min(0.0)
+    }
+
+    /// Cumulative distribution function. With lower_tail P(X <= q), otherwise
+    /// P(X > q). The requested tail is always summed directly to avoid the
+    /// cancellation of 1 - P.
+    pub fn cdf(&self, q: i64, lower_tail: bool, log_p: bool) -> f64 {
+        let ln_p = if lower_tail {
+            self.ln_cdf(q)
+        } else {
+            self.ln_sf(q)
+        };
+        if log_p {
+            ln_p
+        } else {
+            ln_p.exp()
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+/// Log of the binomial coefficient
+fn ln_binomial(n: u64, k: u64) -> f64 {
+    ln_gamma(n as f64 + 1.0) - ln_gamma(k as f64 + 1.0) - ln_gamma((n - k) as f64 + 1.0)
+}
+
+/// Numerically stable log(sum(exp(x))).
+pub fn log_sum_exp(x: &[f64]) -> f64 {
+    let max = x.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return f64::NEG_INFINITY;
+    }
+    let sum: f64 = x.iter().map(|v| (v - max).exp()).sum();
+
+    max + sum.ln()
+}
+
+/// Hypergeometric probability mass function. Returns NaN for invalid
+/// parameters.
+pub fn dhyper(x: i64, m: u64, n: u64, k: u64, log_p: bool) -> f64 {
+    match Hypergeometric::new(m, n, k) {
+        Some(dist) => dist.pmf(x, log_p),
+        None => f64::NAN,
+    }
+}
+
+/// Hypergeometric distribution function. With lower_tail P(X <= q), otherwise
+/// P(X > q). Returns NaN for invalid parameters.
+pub fn phyper(q: i64, m: u64, n: u64, k: u64, lower_tail: bool, log_p: bool) -> f64 {
+    match Hypergeometric::new(m, n, k) {
+        Some(dist) => dist.cdf(q, lower_tail, log_p),
+        None => f64::NAN,
+    }
+}
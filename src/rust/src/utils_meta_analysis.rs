@@ -0,0 +1,432 @@
+use statrs::distribution::{ChiSquared, ContinuousCDF, Normal};
+use std::f64::consts::PI;
+
+///////////
+// Types //
+///////////
+
+/// Type alias for the combined p-value results: the test statistic and the
+/// combined p-value.
+pub type CombinedPval = (f64, f64);
+
+////////////////
+// Structures //
+////////////////
+
+/// Structure for the results of a meta-analysis of a single feature.
+#[derive(Clone, Debug)]
+pub struct MetaAnalysisRes {
+    pub estimate: f64,
+    pub se: f64,
+    pub z: f64,
+    pub pval: f64,
+    pub ci_lower: f64,
+    pub ci_upper: f64,
+    pub tau2: f64,
+    pub q: f64,
+    pub q_pval: f64,
+    pub i2: f64,
+    pub k: usize,
+}
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the p-value combination methods
+#[derive(Clone, Debug)]
+pub enum PvalCombination {
+    Fisher,
+    Stouffer,
+    Cauchy,
+    Harmonic,
+}
+
+/// Enum for the meta-analysis methods
+#[derive(Clone, Debug)]
+pub enum MetaAnalysisMethod {
+    Fixed,
+    DerSimonianLaird,
+    Reml,
+}
+
+/// Parsing the p-value combination method
+pub fn parse_pval_combination(s: &str) -> Option<PvalCombination> {
+    match s.to_lowercase().as_str() {
+        "fisher" => Some(PvalCombination::Fisher),
+        "stouffer" => Some(PvalCombination::Stouffer),
+        "cauchy" | "acat" => Some(PvalCombination::Cauchy),
+        "harmonic" | "hmp" => Some(PvalCombination::Harmonic),
+        _ => None,
+    }
+}
+
+/// Parsing the meta-analysis method
+pub fn parse_meta_analysis_method(s: &str) -> Option<MetaAnalysisMethod> {
+    match s.to_lowercase().as_str() {
+        "fixed" => Some(MetaAnalysisMethod::Fixed),
+        "dl" | "dersimonian_laird" => Some(MetaAnalysisMethod::DerSimonianLaird),
+        "reml" => Some(MetaAnalysisMethod::Reml),
+        _ => None,
+    }
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Adaptive Simpson integration of f over [a, b] with absolute tolerance tol.
+fn adaptive_simpson<F>(f: &F, a: f64, b: f64, tol: f64) -> f64
+where
+    F: Fn(f64) -> f64,
+{
+    /// Recursive step; interval holds (a, b) and values (f(a), f(m), f(b)).
+    fn simpson_step<F>(
+        f: &F,
+        interval: (f64, f64),
+        values: (f64, f64, f64),
+        whole: f64,
+        tol: f64,
+        depth: usize,
+    ) -> f64
+    where
+        F: Fn(f64) -> f64,
+    {
+        let (a, b) = interval;
+        let (fa, fm, fb) = values;
+        let m = (a + b) / 2.0;
+        let (flm, frm) = (f((a + m) / 2.0), f((m + b) / 2.0));
+        let left = (m - a) / 6.0 * (fa + 4.0 * flm + fm);
+        let right = (b - m) / 6.0 * (fm + 4.0 * frm + fb);
+        let delta = left + right - whole;
+
+        if depth == 0 || delta.abs() <= 15.0 * tol {
+            left + right + delta / 15.0
+        } else {
+            simpson_step(f, (a, m), (fa, flm, fm), left, tol / 2.0, depth - 1)
+                + simpson_step(f, (m, b), (fm, frm, fb), right, tol / 2.0, depth - 1)
+        }
+    }
+
+    let (fa, fm, fb) = (f(a), f((a + b) / 2.0), f(b));
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+
+    simpson_step(f, (a, b), (fa, fm, fb), whole, tol, 50)
+}
+
+/// Upper tail probability of the Landau distribution (stable distribution with
+/// alpha = 1 and beta = 1) with location mu and scale sigma. Uses the integral
+/// representation of Nolan (1997) which is bounded and numerically stable. For
+/// tiny tail probabilities the integration is repeated with a tolerance
+/// relative to the first estimate.
+pub fn landau_sf(x: f64, mu: f64, sigma: f64) -> f64 {
+    let z = (x - mu) / sigma;
+    // Far in the tail the grid cannot resolve the integrand anymore; use the
+    // asymptotic tail of the stable distribution instead
+    if z > 1e8 {
+        return 2.0 / (PI * z);
+    }
+    let ln_scale = -PI * z / 2.0 + (2.0 / PI).ln();
+
+    // Evaluated on the log scale to avoid the overflow of the exponential term
+    // close to the upper end point
+    let integrand = |theta: f64| {
+        let a = PI / 2.0 + theta;
+        let ln_v = if a <= 0.0 {
+            // Limit at the lower end point
+            ln_scale - 1.0
+        } else if theta >= PI / 2.0 {
+            f64::INFINITY
+        } else {
+            ln_scale + (a / theta.cos()).ln() + a * theta.tan()
+        };
+        -(-ln_v.exp()).exp_m1()
+    };
+
+    let mut res = adaptive_simpson(&integrand, -PI / 2.0, PI / 2.0, 1e-12) / PI;
+    if res > 0.0 && res < 1e-8 {
+        res = adaptive_simpson(&integrand, -PI / 2.0, PI / 2.0, res * 1e-6) / PI;
+    }
+
+    res.clamp(0.0, 1.0)
+}
+
+/// Returns the non-NA p-values with their (normalised) weights. Missing
+/// weights default to equal weights.
+fn valid_pvals_weights(pvals: &[f64], weights: Option<&[f64]>) -> (Vec<f64>, Vec<f64>) {
+    let mut valid_pvals = Vec::with_capacity(pvals.len());
+    let mut valid_weights = Vec::with_capacity(pvals.len());
+
+    for (i, &p) in pvals.iter().enumerate() {
+        let w = weights.map_or(1.0, |w| w[i]);
+        if !p.is_nan() && !w.is_nan() {
+            valid_pvals.push(p);
+            valid_weights.push(w);
+        }
+    }
+
+    (valid_pvals, valid_weights)
+}
+
+//////////////////////////
+// P-value combinations //
+//////////////////////////
+
+/// Fisher's method. The statistic -2 * sum(log(p)) follows a chi-squared
+/// distribution with 2k degrees of freedom. Weights are ignored.
+pub fn fisher_combination(pvals: &[f64]) -> CombinedPval {
+    let statistic: f64 = -2.0 * pvals.iter().map(|p| p.ln()).sum::<f64>();
+    let chi_sq = ChiSquared::new(2.0 * pvals.len() as f64).unwrap();
+
+    (statistic, chi_sq.sf(statistic))
+}
+
+/// Weighted Stouffer's method with one-sided p-values.
+pub fn stouffer_combination(pvals: &[f64], weights: &[f64]) -> CombinedPval {
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    let weighted_z: f64 = pvals
+        .iter()
+        .zip(weights)
+        .map(|(p, w)| w * -normal.inverse_cdf(*p))
+        .sum();
+    let norm: f64 = weights.iter().map(|w| w.powi(2)).sum::<f64>().sqrt();
+    let statistic = weighted_z / norm;
+
+    (statistic, normal.sf(statistic))
+}
+
+/// Cauchy combination test (ACAT; Liu and Xie, 2020). Very small p-values use
+/// the asymptotic approximation of the tangent to avoid precision loss.
+pub fn cauchy_combination(pvals: &[f64], weights: &[f64]) -> CombinedPval {
+    if pvals.contains(&0.0) {
+        return (f64::INFINITY, 0.0);
+    }
+    let weight_sum: f64 = weights.iter().sum();
+
+    let statistic: f64 = pvals
+        .iter()
+        .zip(weights)
+        .map(|(&p, w)| {
+            let w = w / weight_sum;
+            if p < 1e-16 {
+                w / p / PI
+            } else {
+                w * ((0.5 - p) * PI).tan()
+            }
+        })
+        .sum();
+
+    let pval = if statistic > 1e15 {
+        1.0 / statistic / PI
+    } else {
+        0.5 - statistic.atan() / PI
+    };
+
+    (statistic, pval)
+}
+
+/// Harmonic mean p-value (Wilson, 2019). The statistic is the weighted
+/// harmonic mean of the p-values; the asymptotically exact p-value is derived
+/// from the Landau distribution of its inverse.
+pub fn harmonic_combination(pvals: &[f64], weights: &[f64]) -> CombinedPval {
+    let weight_sum: f64 = weights.iter().sum();
+    let inverse_sum: f64 = pvals
+        .iter()
+        .zip(weights)
+        .map(|(p, w)| (w / weight_sum) / p)
+        .sum();
+    let statistic = 1.0 / inverse_sum;
+    let l = pvals.len() as f64;
+
+    let pval = landau_sf(inverse_sum, l.ln() + 0.874, PI / 2.0);
+
+    (statistic, pval)
+}
+
+/// Combine the p-values with the respective method. NA p-values (and NA
+/// weights) are excluded. Returns NaN if no valid p-values remain.
+pub fn combine_pvals(
+    pvals: &[f64],
+    weights: Option<&[f64]>,
+    method: &PvalCombination,
+) -> CombinedPval {
+    let (pvals, weights) = valid_pvals_weights(pvals, weights);
+    if pvals.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+
+    match method {
+        PvalCombination::Fisher => fisher_combination(&pvals),
+        PvalCombination::Stouffer => stouffer_combination(&pvals, &weights),
+        PvalCombination::Cauchy => cauchy_combination(&pvals, &weights),
+        PvalCombination::Harmonic => harmonic_combination(&pvals, &weights),
+    }
+}
+
+///////////////////
+// Meta-analysis //
+///////////////////
+
+/// Weighted mean and the (Cochran's) Q statistic for given weights.
+fn weighted_mean_q(effect_sizes: &[f64], weights: &[f64]) -> (f64, f64) {
+    let weight_sum: f64 = weights.iter().sum();
+    let mean = effect_sizes
+        .iter()
+        .zip(weights)
+        .map(|(y, w)| w * y)
+        .sum::<f64>()
+        / weight_sum;
+    let q = effect_sizes
+        .iter()
+        .zip(weights)
+        .map(|(y, w)| w * (y - mean).powi(2))
+        .sum::<f64>();
+
+    (mean, q)
+}
+
+/// DerSimonian-Laird estimator of the between-study variance.
+fn tau2_dersimonian_laird(q: f64, weights: &[f64]) -> f64 {
+    let df = (weights.len() - 1) as f64;
+    let weight_sum: f64 = weights.iter().sum();
+    let weight_sq_sum: f64 = weights.iter().map(|w| w.powi(2)).sum();
+
+    ((q - df) / (weight_sum - weight_sq_sum / weight_sum)).max(0.0)
+}
+
+/// REML estimator of the between-study variance via Fisher scoring (as in
+/// metafor), starting from the DerSimonian-Laird estimate.
+fn tau2_reml(effect_sizes: &[f64], variances: &[f64], tau2_init: f64) -> f64 {
+    let max_iter = 100;
+    let tol = 1e-10;
+    let mut tau2 = tau2_init;
+
+    for _ in 0..max_iter {
+        let weights: Vec<f64> = variances.iter().map(|v| 1.0 / (v + tau2)).collect();
+        let (mean, _) = weighted_mean_q(effect_sizes, &weights);
+
+        let w_sum: f64 = weights.iter().sum();
+        let w2_sum: f64 = weights.iter().map(|w| w.powi(2)).sum();
+        let w3_sum: f64 = weights.iter().map(|w| w.powi(3)).sum();
+
+        // P = W - w w' / sum(w) for the intercept-only model
+        let ypppy: f64 = effect_sizes
+            .iter()
+            .zip(&weights)
+            .map(|(y, w)| w.powi(2) * (y - mean).powi(2))
+            .sum();
+        let trace_p = w_sum - w2_sum / w_sum;
+        let trace_pp = w2_sum - 2.0 * w3_sum / w_sum + (w2_sum / w_sum).powi(2);
+
+        let adj = (ypppy - trace_p) / trace_pp;
+        let mut new_tau2 = tau2 + adj;
+        // Step halving to stay within the parameter space
+        let mut step = adj;
+        while new_tau2 < 0.0 && step.abs() > tol {
+            step /= 2.0;
+            new_tau2 = tau2 + step;
+        }
+        let new_tau2 = new_tau2.max(0.0);
+
+        if (new_tau2 - tau2).abs() < tol {
+            return new_tau2;
+        }
+        tau2 = new_tau2;
+    }
+
+    tau2
+}
+
+/// Inverse variance meta-analysis of the effect sizes and their standard
+/// errors for a single feature. Studies with NA effect sizes or standard errors
+/// are excluded. I2 is returned in percent.
+pub fn meta_analysis(
+    effect_sizes: &[f64],
+    standard_errors: &[f64],
+    method: &MetaAnalysisMethod,
+    conf_level: f64,
+) -> MetaAnalysisRes {
+    let (y, v): (Vec<f64>, Vec<f64>) = effect_sizes
+        .iter()
+        .zip(standard_errors)
+        .filter(|(y, se)| !y.is_nan() && !se.is_nan())
+        .map(|(y, se)| (*y, se.powi(2)))
+        .unzip();
+    let k = y.len();
+
+    if k == 0 {
+        return MetaAnalysisRes {
+            estimate: f64::NAN,
+            se: f64::NAN,
+            z: f64::NAN,
+            pval: f64::NAN,
+            ci_lower: f64::NAN,
+            ci_upper: f64::NAN,
+            tau2: f64::NAN,
+            q: f64::NAN,
+            q_pval: f64::NAN,
+            i2: f64::NAN,
+            k,
+        };
+    }
+
+    let fixed_weights: Vec<f64> = v.iter().map(|v| 1.0 / v).collect();
+    let (_, q) = weighted_mean_q(&y, &fixed_weights);
+    let df = (k - 1) as f64;
+
+    let tau2 = if k == 1 {
+        0.0
+    } else {
+        match method {
+            MetaAnalysisMethod::Fixed => 0.0,
+            MetaAnalysisMethod::DerSimonianLaird => tau2_dersimonian_laird(q, &fixed_weights),
+            MetaAnalysisMethod::Reml => {
+                tau2_reml(&y, &v, tau2_dersimonian_laird(q, &fixed_weights))
+            }
+        }
+    };
+
+    let weights: Vec<f64> = v.iter().map(|v| 1.0 / (v + tau2)).collect();
+    let (estimate, _) = weighted_mean_q(&y, &weights);
+    let se = (1.0 / weights.iter().sum::<f64>()).sqrt();
+    let z = estimate / se;
+
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    let pval = 2.0 * normal.sf(z.abs());
+    let margin = normal.inverse_cdf(1.0 - (1.0 - conf_level) / 2.0) * se;
+
+    let q_pval = if k > 1 {
+        ChiSquared::new(df).unwrap().sf(q)
+    } else {
+        f64::NAN
+    };
+
+    // I2 based on the typical within-study variance (Higgins and Thompson)
+    let i2 = if k > 1 {
+        match method {
+            MetaAnalysisMethod::Fixed => (100.0 * (q - df) / q).max(0.0),
+            _ => {
+                let w_sum: f64 = fixed_weights.iter().sum();
+                let w2_sum: f64 = fixed_weights.iter().map(|w| w.powi(2)).sum();
+                let typical_var = df / (w_sum - w2_sum / w_sum);
+                100.0 * tau2 / (tau2 + typical_var)
+            }
+        }
+    } else {
+        f64::NAN
+    };
+
+    MetaAnalysisRes {
+        estimate,
+        se,
+        z,
+        pval,
+        ci_lower: estimate - margin,
+        ci_upper: estimate + margin,
+        tau2,
+        q,
+        q_pval,
+        i2,
+        k,
+    }
+}
@@ -0,0 +1,383 @@
+use faer::{
+    linalg::solvers::{PartialPivLu, Solve},
+    Mat, Side,
+};
+use rayon::prelude::*;
+
+///////////
+// Enums //
+///////////
+
+/// Enum for the estimation of the proportion of true null hypotheses (pi0)
+#[derive(Clone, Debug)]
+pub enum Pi0Method {
+    Smoother,
+    Bootstrap,
+}
+
+/// Enum for the multiple testing corrections
+#[derive(Clone, Debug)]
+pub enum PAdjustMethod {
+    Bonferroni,
+    Holm,
+    Hochberg,
+    Hommel,
+    Bh,
+    By,
+    Storey(Pi0Method),
+}
+
+/// Parsing the multiple testing correction
+pub fn parse_p_adjust_method(s: &str) -> Option<PAdjustMethod> {
+    match s.to_lowercase().as_str() {
+        "bonferroni" => Some(PAdjustMethod::Bonferroni),
+        "holm" => Some(PAdjustMethod::Holm),
+        "hochberg" => Some(PAdjustMethod::Hochberg),
+        "hommel" => Some(PAdjustMethod::Hommel),
+        "bh" | "fdr" => Some(PAdjustMethod::Bh),
+        "by" => Some(PAdjustMethod::By),
+        "storey_smoother" => Some(PAdjustMethod::Storey(Pi0Method::Smoother)),
+        "storey_bootstrap" => Some(PAdjustMethod::Storey(Pi0Method::Bootstrap)),
+        _ => None,
+    }
+}
+
+/// Parsing the pi0 estimation method
+pub fn parse_pi0_method(s: &str) -> Option<Pi0Method> {
+    match s.to_lowercase().as_str() {
+        "smoother" => Some(Pi0Method::Smoother),
+        "bootstrap" => Some(Pi0Method::Bootstrap),
+        _ => None,
+    }
+}
+
+/////////////
+// Helpers //
+/////////////
+
+/// Returns the indices that sort the p-values in ascending order.
+fn order_ascending(pvals: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..pvals.len()).collect();
+    order.sort_by(|&a, &b| {
+        pvals[a]
+            .partial_cmp(&pvals[b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    order
+}
+
+/// Applies a step-down (cumulative maximum over the ascending p-values) or
+/// step-up (cumulative minimum from the largest p-value) procedure with the
+/// given multipliers for the rank i (1-based) of the ascending p-values.
+fn step_adjust<F>(pvals: &[f64], step_up: bool, multiplier: F) -> Vec<f64>
+where
+    F: Fn(usize) -> f64,
+{
+    let n = pvals.len();
+    let order = order_ascending(pvals);
+    let adjusted_sorted: Vec<f64> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &idx)| multiplier(i + 1) * pvals[idx])
+        .collect();
+
+    let mut monotonic = vec![0.0; n];
+    if step_up {
+        let mut current = f64::INFINITY;
+        for i in (0..n).rev() {
+            current = current.min(adjusted_sorted[i]);
+            monotonic[i] = current.min(1.0);
+        }
+    } else {
+        let mut current = f64::NEG_INFINITY;
+        for i in 0..n {
+            current = current.max(adjusted_sorted[i]);
+            monotonic[i] = current.min(1.0);
+        }
+    }
+
+    let mut res = vec![0.0; n];
+    for (i, &idx) in order.iter().enumerate() {
+        res[idx] = monotonic[i];
+    }
+    res
+}
+
+/// Sample quantile (type 7, R default) of a sorted slice.
+fn quantile_sorted(sorted: &[f64], prob: f64) -> f64 {
+    let h = (sorted.len() - 1) as f64 * prob;
+    let lower = h.floor() as usize;
+    let upper = (lower + 1).min(sorted.len() - 1);
+    sorted[lower] + (h - lower as f64) * (sorted[upper] - sorted[lower])
+}
+
+/// Cubic smoothing spline with the given degrees of freedom (trace of the
+/// smoother matrix) and knots at every x, equivalent to
+/// `stats::smooth.spline(x, y, df = df)`. The x values need to be sorted and
+/// unique. Returns the fitted values.
+fn smoothing_spline(x: &[f64], y: &[f64], df: f64) -> Vec<f64> {
+    let n = x.len();
+    if n < 3 {
+        return y.to_vec();
+    }
+
+    // Reinsch form: the penalty matrix of the natural cubic spline is
+    // Q R^-1 Q^T with the tridiagonal matrices Q and R
+    let h: Vec<f64> = x.windows(2).map(|w| w[1] - w[0]).collect();
+    let q = Mat::from_fn(n, n - 2, |i, j| {
+        if i == j {
+            1.0 / h[j]
+        } else if i == j + 1 {
+            -1.0 / h[j] - 1.0 / h[j + 1]
+        } else if i == j + 2 {
+            1.0 / h[j + 1]
+        } else {
+            0.0
+        }
+    });
+    let r = Mat::from_fn(n - 2, n - 2, |i, j| {
+        if i == j {
+            (h[i] + h[i + 1]) / 3.0
+        } else if j == i + 1 {
+            h[i + 1] / 6.0
+        } else if i == j + 1 {
+            h[j + 1] / 6.0
+        } else {
+            0.0
+        }
+    });
+    let penalty = &q * PartialPivLu::new(r.as_ref()).solve(q.transpose());
+
+    let eigen = match penalty.self_adjoint_eigen(Side::Lower) {
+        Ok(eigen) => eigen,
+        Err(_) => return y.to_vec(),
+    };
+    let d: Vec<f64> = eigen
+        .S()
+        .column_vector()
+        .iter()
+        .map(|d_i| d_i.max(0.0))
+        .collect();
+    let u = eigen.U();
+
+    // The degrees of freedom decrease monotonically with the smoothing
+    // parameter; bisection on its log scale
+    let trace = |log_lambda: f64| -> f64 {
+        let lambda = log_lambda.exp();
+        d.iter().map(|d_i| 1.0 / (1.0 + lambda * d_i)).sum()
+    };
+    let (mut lower, mut upper) = (-50.0_f64, 50.0_f64);
+    for _ in 0..200 {
+        let mid = 0.5 * (lower + upper);
+        if trace(mid) > df {
+            lower = mid;
+        } else {
+            upper = mid;
+        }
+    }
+    let lambda = (0.5 * (lower + upper)).exp();
+
+    let response = Mat::from_fn(n, 1, |i, _| y[i]);
+    let projected = u.transpose() * &response;
+    let shrunk = Mat::from_fn(n, 1, |i, _| projected[(i, 0)] / (1.0 + lambda * d[i]));
+    let fitted = u * &shrunk;
+
+    (0..n).map(|i| fitted[(i, 0)]).collect()
+}
+
+//////////////////////////
+// Adjustment functions //
+//////////////////////////
+
+/// Calculate the Benjamini-Hochberg adjusted p-values
+pub fn calc_fdr(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len();
+
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let n_f64 = n as f64;
+
+    let mut indexed_pval: Vec<(usize, f64)> =
+        pvals.par_iter().enumerate().map(|(i, &x)| (i, x)).collect();
+
+    indexed_pval
+        .sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    let adj_pvals_tmp: Vec<f64> = indexed_pval
+        .par_iter()
+        .enumerate()
+        .map(|(i, (_, p))| (n_f64 / (i + 1) as f64) * p)
+        .collect();
+
+    let mut current_min = adj_pvals_tmp[n - 1].min(1.0);
+    let mut monotonic_adj = vec![current_min; n];
+
+    for i in (0..n - 1).rev() {
+        current_min = current_min.min(adj_pvals_tmp[i]).min(1.0);
+        monotonic_adj[i] = current_min;
+    }
+
+    let mut adj_pvals = vec![0.0; n];
+
+    for (i, &(original_idx, _)) in indexed_pval.iter().enumerate() {
+        adj_pvals[original_idx] = monotonic_adj[i];
+    }
+
+    adj_pvals
+}
+
+/// Calculate the Bonferroni adjusted p-values
+pub fn calc_bonferroni(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len() as f64;
+    pvals.iter().map(|p| (p * n).min(1.0)).collect()
+}
+
+/// Calculate the Holm adjusted p-values
+pub fn calc_holm(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len();
+    step_adjust(pvals, false, |i| (n - i + 1) as f64)
+}
+
+/// Calculate the Hochberg adjusted p-values
+pub fn calc_hochberg(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len();
+    step_adjust(pvals, true, |i| (n - i + 1) as f64)
+}
+
+/// Calculate the Benjamini-Yekutieli adjusted p-values
+pub fn calc_by(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len();
+    let q: f64 = (1..=n).map(|i| 1.0 / i as f64).sum();
+    step_adjust(pvals, true, |i| q * n as f64 / i as f64)
+}
+
+/// Calculate the Hommel adjusted p-values (same algorithm as stats::p.adjust).
+/// For two p-values this is identical to Hochberg.
+pub fn calc_hommel(pvals: &[f64]) -> Vec<f64> {
+    let n = pvals.len();
+    if n <= 1 {
+        return pvals.to_vec();
+    }
+    if n == 2 {
+        return calc_hochberg(pvals);
+    }
+
+    let order = order_ascending(pvals);
+    let p: Vec<f64> = order.iter().map(|&i| pvals[i]).collect();
+
+    let init = (0..n)
+        .map(|i| n as f64 * p[i] / (i + 1) as f64)
+        .fold(f64::INFINITY, f64::min);
+    let mut q = vec![init; n];
+    let mut pa = vec![init; n];
+
+    for m in (2..n).rev() {
+        // i1 = 0..(n - m + 1), i2 = (n - m + 1)..n (0-based)
+        let split = n - m + 1;
+        let q1 = (split..n)
+            .enumerate()
+            .map(|(j, i)| m as f64 * p[i] / (j + 2) as f64)
+            .fold(f64::INFINITY, f64::min);
+        for i in 0..split {
+            q[i] = (m as f64 * p[i]).min(q1);
+        }
+        let q_fill = q[split - 1];
+        for q_i in q.iter_mut().skip(split) {
+            *q_i = q_fill;
+        }
+        for i in 0..n {
+            pa[i] = pa[i].max(q[i]);
+        }
+    }
+
+    let mut res = vec![0.0; n];
+    for (i, &idx) in order.iter().enumerate() {
+        res[idx] = pa[i].max(p[i]);
+    }
+    res
+}
+
+/// Estimate the proportion of true null hypotheses (pi0) according to Storey
+/// over a grid of lambda = 0.05, 0.10, ..., 0.95. The smoother fits a cubic
+/// smoothing spline (3 degrees of freedom) through the pi0 estimates and uses
+/// the fitted value at the largest lambda. The bootstrap version chooses
+/// the lambda that minimises the (closed-form) bootstrap mean squared error
+/// (as in the qvalue package). Returns 1 if the estimation fails.
+pub fn storey_pi0(pvals: &[f64], method: &Pi0Method) -> f64 {
+    let m = pvals.len() as f64;
+    if pvals.is_empty() {
+        return 1.0;
+    }
+
+    let lambda: Vec<f64> = (1..=19).map(|i| i as f64 * 0.05).collect();
+    let counts: Vec<f64> = lambda
+        .iter()
+        .map(|l| pvals.iter().filter(|&&p| p >= *l).count() as f64)
+        .collect();
+    let pi0_lambda: Vec<f64> = lambda
+        .iter()
+        .zip(counts.iter())
+        .map(|(l, w)| w / (m * (1.0 - l)))
+        .collect();
+
+    let pi0 = match method {
+        Pi0Method::Smoother => *smoothing_spline(&lambda, &pi0_lambda, 3.0).last().unwrap(),
+        Pi0Method::Bootstrap => {
+            let mut sorted = pi0_lambda.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let min_pi0 = quantile_sorted(&sorted, 0.1);
+
+            let mse: Vec<f64> = (0..lambda.len())
+                .map(|i| {
+                    (counts[i] / (m.powi(2) * (1.0 - lambda[i]).powi(2))) * (1.0 - counts[i] / m)
+                        + (pi0_lambda[i] - min_pi0).powi(2)
+                })
+                .collect();
+            let min_mse = mse.iter().cloned().fold(f64::INFINITY, f64::min);
+
+            (0..lambda.len())
+                .filter(|&i| mse[i] == min_mse)
+                .map(|i| pi0_lambda[i])
+                .fold(f64::INFINITY, f64::min)
+        }
+    };
+
+    if pi0.is_finite() && pi0 > 0.0 {
+        pi0.min(1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Calculate the Storey q-values. Returns the q-values and the estimated pi0.
+pub fn calc_qvalues(pvals: &[f64], method: &Pi0Method) -> (Vec<f64>, f64) {
+    let pi0 = storey_pi0(pvals, method);
+    let qvals = calc_fdr(pvals).iter().map(|q| q * pi0).collect();
+
+    (qvals, pi0)
+}
+
+/// Adjust the p-values for multiple testing. NA (NaN) p-values are excluded
+/// from the correction and returned as NaN.
+pub fn p_adjust(pvals: &[f64], method: &PAdjustMethod) -> Vec<f64> {
+    let valid_idx: Vec<usize> = (0..pvals.len()).filter(|&i| !pvals[i].is_nan()).collect();
+    let valid: Vec<f64> = valid_idx.iter().map(|&i| pvals[i]).collect();
+
+    let adjusted = match method {
+        PAdjustMethod::Bonferroni => calc_bonferroni(&valid),
+        PAdjustMethod::Holm => calc_holm(&valid),
+        PAdjustMethod::Hochberg => calc_hochberg(&valid),
+        PAdjustMethod::Hommel => calc_hommel(&valid),
+        PAdjustMethod::Bh => calc_fdr(&valid),
+        PAdjustMethod::By => calc_by(&valid),
+        PAdjustMethod::Storey(pi0_method) => calc_qvalues(&valid, pi0_method).0,
+    };
+
+    let mut res = vec![f64::NAN; pvals.len()];
+    for (adj, &i) in adjusted.into_iter().zip(valid_idx.iter()) {
+        res[i] = adj;
+    }
+    res
+}
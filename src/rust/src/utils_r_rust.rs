@@ -1,5 +1,5 @@
 use extendr_api::prelude::*;
-use faer::MatRef;
+use faer::{Mat, MatRef};
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 ///////////////////
@@ -12,6 +12,9 @@ pub type NestedHashMap = HashMap<String, HashMap<String, HashSet<String>>>;
 /// Type alias for double nested BtreeMap
 pub type NestedBtreeMap = BTreeMap<String, BTreeMap<String, HashSet<String>>>;
 
+/// Type alias for double nested BtreeMap with gene weights
+pub type NestedBtreeWeightMap = BTreeMap<String, BTreeMap<String, HashMap<String, f64>>>;
+
 /// Transforms a Robj List into a Hashmap
 pub fn r_list_to_hashmap(r_list: List) -> extendr_api::Result<HashMap<String, Vec<String>>> {
     let mut result = HashMap::with_capacity(r_list.len());
@@ -101,6 +104,58 @@ pub fn r_nested_list_to_btree_nest(r_nested_list: List) -> extendr_api::Result<N
     Ok(result)
 }
 
+/// Transform a Robj List of named numeric vectors into a BTreeMap with the
+/// values as name -> weight HashMap
+pub fn r_list_to_btree_weights(
+    r_list: List,
+) -> extendr_api::Result<BTreeMap<String, HashMap<String, f64>>> {
+    let mut result = BTreeMap::new();
+    for (n, s) in r_list {
+        let (names, values) = r_named_vec_data(s).map_err(|e| {
+            Error::Other(format!(
+                "Failed to convert value for key '{}' to named numeric vector: {}",
+                n, e
+            ))
+        })?;
+        let weights: HashMap<String, f64> = names.into_iter().zip(values).collect();
+        result.insert(n.to_string(), weights);
+    }
+    Ok(result)
+}
+
+/// Transform an Robj nested list of named numeric vectors into a nested
+/// Btreemap with the weights
+pub fn r_nested_list_to_btree_weight_nest(
+    r_nested_list: List,
+) -> extendr_api::Result<NestedBtreeWeightMap> {
+    let mut result = BTreeMap::new();
+
+    for (n, obj) in r_nested_list {
+        let inner_list = obj.as_list().ok_or_else(|| {
+            Error::Other(format!("Failed to convert value for key '{}' to list", n))
+        })?;
+        let inner_tree = r_list_to_btree_weights(inner_list)?;
+        result.insert(n.to_string(), inner_tree);
+    }
+
+    Ok(result)
+}
+
+/// Transform a nested Btreemap with weights into a nested Btreemap with the
+/// sets of the names.
+pub fn btree_weight_nest_to_sets(weights: &NestedBtreeWeightMap) -> NestedBtreeMap {
+    weights
+        .iter()
+        .map(|(origin, modules)| {
+            let sets: BTreeMap<String, HashSet<String>> = modules
+                .iter()
+                .map(|(module, w)| (module.clone(), w.keys().cloned().collect()))
+                .collect();
+            (origin.clone(), sets)
+        })
+        .collect()
+}
+
 /////////////
 // Vectors //
 /////////////
@@ -151,6 +206,19 @@ pub fn r_list_to_str_vec(r_list: List) -> extendr_api::Result<Vec<Vec<String>>>
     Ok(result)
 }
 
+/// Transforms a Robj List of named numeric vectors into an array of
+/// name -> weight HashMaps.
+pub fn r_list_to_weight_maps(r_list: List) -> extendr_api::Result<Vec<HashMap<String, f64>>> {
+    let mut result = Vec::with_capacity(r_list.len());
+
+    for (_, s) in r_list.into_iter() {
+        let (names, values) = r_named_vec_data(s)?;
+        result.push(names.into_iter().zip(values).collect());
+    }
+
+    Ok(result)
+}
+
 /// Get the names and numeric values from a named R vector
 pub fn r_named_vec_data(named_vec: Robj) -> extendr_api::Result<NamedNumericVec> {
     let values = named_vec
@@ -169,7 +237,7 @@ pub fn r_named_vec_data(named_vec: Robj) -> extendr_api::Result<NamedNumericVec>
 //////////////
 
 /// Transform an R matrix to a Faer one
-pub fn r_matrix_to_faer(x: &RMatrix<f64>) -> faer::MatRef<f64> {
+pub fn r_matrix_to_faer(x: &RMatrix<f64>) -> faer::MatRef<'_, f64> {
     let ncol = x.ncols();
     let nrow = x.nrows();
     let data = x.data();
@@ -177,6 +245,34 @@ pub fn r_matrix_to_faer(x: &RMatrix<f64>) -> faer::MatRef<f64> {
     MatRef::from_column_major_slice(data, nrow, ncol)
 }
 
+/// Type alias for a named matrix: the row names, the column names and the
+/// data as a faer matrix.
+pub type NamedMatrix = (Vec<String>, Vec<String>, Mat<f64>);
+
+/// Get the row names, column names and the data from an R matrix with
+/// dimnames.
+pub fn r_named_matrix_data(x: Robj) -> extendr_api::Result<NamedMatrix> {
+    let dimnames = x
+        .get_attrib("dimnames")
+        .and_then(|d| d.as_list())
+        .ok_or_else(|| Error::Other("Matrix has no dimnames".to_string()))?;
+
+    let mut names = dimnames.values().map(|n| n.as_string_vector());
+    let (row_names, col_names) = match (names.next(), names.next()) {
+        (Some(Some(row_names)), Some(Some(col_names))) => (row_names, col_names),
+        _ => {
+            return Err(Error::Other(
+                "Matrix needs to have row and column names".to_string(),
+            ))
+        }
+    };
+
+    let mat = RMatrix::<f64>::try_from(x)?;
+    let data = r_matrix_to_faer(&mat).to_owned();
+
+    Ok((row_names, col_names, data))
+}
+
 /// Transform a faer into an R matrix
 pub fn faer_to_r_matrix(x: faer::MatRef<f64>) -> extendr_api::RArray<f64, [usize; 2]> {
     let nrow = x.nrows();
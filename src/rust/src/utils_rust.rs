@@ -1,5 +1,5 @@
 use std::cmp::PartialOrd;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 
@@ -62,6 +62,45 @@ pub fn string_vec_to_set(x: &[String]) -> HashSet<&String> {
     set
 }
 
+/// Intern the strings of a list of string vectors into integers. Returns for
+/// each vector the sorted, deduplicated integer representation.
+pub fn intern_string_vecs(x: &[Vec<String>]) -> Vec<Vec<u32>> {
+    let mut dictionary: HashMap<&str, u32> = HashMap::new();
+
+    x.iter()
+        .map(|s| {
+            let mut interned: Vec<u32> = s
+                .iter()
+                .map(|item| {
+                    let next_id = dictionary.len() as u32;
+                    *dictionary.entry(item.as_str()).or_insert(next_id)
+                })
+                .collect();
+            interned.sort_unstable();
+            interned.dedup();
+            interned
+        })
+        .collect()
+}
+
+/// Count the number of shared elements between two sorted, deduplicated
+/// slices.
+pub fn sorted_intersection_count<T: Ord>(a: &[T], b: &[T]) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    count
+}
+
 /// Generate the rank of a vector with tie correction.
 pub fn rank_vector(vec: &[f64]) -> Vec<f64> {
     let mut vec_index: Vec<(f64, usize)> = vec
@@ -110,6 +149,36 @@ where
         .collect()
 }
 
+/// Calculate the median of a vector. Returns NaN for empty vectors.
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let n = sorted.len();
+    if n.is_multiple_of(2) {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    } else {
+        sorted[n / 2]
+    }
+}
+
+/// Calculate the sample quantile (type 7, R default) of a vector. Returns NaN
+/// for empty vectors.
+pub fn quantile(values: &[f64], prob: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let h = (sorted.len() - 1) as f64 * prob;
+    let lower = h.floor() as usize;
+    let upper = (lower + 1).min(sorted.len() - 1);
+
+    sorted[lower] + (h - lower as f64) * (sorted[upper] - sorted[lower])
+}
+
 /// Calculate the cumulative sum over a vector
 pub fn cumsum(values: &[f64]) -> Vec<f64> {
     let mut sum = 0.0;
@@ -122,6 +191,16 @@ pub fn cumsum(values: &[f64]) -> Vec<f64> {
         .collect()
 }
 
+/// Length of the output when recycling vectors (R-style). Returns 0 if any of
+/// the vectors is empty.
+pub fn recycled_length(lengths: &[usize]) -> usize {
+    if lengths.contains(&0) {
+        0
+    } else {
+        lengths.iter().copied().max().unwrap_or(0)
+    }
+}
+
 //////////////////
 // MATRIX STUFF //
 //////////////////
@@ -2,7 +2,9 @@ use rand::prelude::*;
 use rand::seq::SliceRandom;
 use rayon::prelude::*;
 use statrs::distribution::{Continuous, ContinuousCDF, Normal};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+use crate::utils_rust::{flatten_vector, sorted_intersection_count};
 
 ///////////
 // Types //
@@ -43,6 +45,106 @@ pub fn set_similarity(
     i as f64 / u as f64
 }
 
+/// Calculate the set similarity on sorted, deduplicated (interned) sets.
+/// Options are Jaccard (overlap_coefficient = False) or the overlap
+/// coefficient.
+pub fn set_similarity_sorted(s_1: &[u32], s_2: &[u32], overlap_coefficient: bool) -> f64 {
+    let i = sorted_intersection_count(s_1, s_2);
+    let u = if overlap_coefficient {
+        std::cmp::min(s_1.len(), s_2.len())
+    } else {
+        s_1.len() + s_2.len() - i
+    };
+    i as f64 / u as f64
+}
+
+/// Calculate the all-vs-all set similarities of a collection of sorted,
+/// deduplicated (interned) sets. Returns the upper triangle of the similarity
+/// matrix iterating through the rows. If shift is 1, the diagonal will not be
+/// included.
+pub fn set_similarity_upper_triangle(
+    sets: &[Vec<u32>],
+    overlap_coefficient: bool,
+    shift: usize,
+) -> Vec<f64> {
+    let n = sets.len();
+
+    let res: Vec<Vec<f64>> = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            (i + shift..n)
+                .map(|j| set_similarity_sorted(&sets[i], &sets[j], overlap_coefficient))
+                .collect()
+        })
+        .collect();
+
+    flatten_vector(res)
+}
+
+/// Enum for the weighted set similarities
+#[derive(Clone, Debug)]
+pub enum WeightedSimType {
+    Ruzicka,
+    Overlap,
+    Cosine,
+}
+
+/// Parsing the weighted set similarity
+pub fn parse_weighted_sim_type(s: &str) -> Option<WeightedSimType> {
+    match s.to_lowercase().as_str() {
+        "ruzicka" => Some(WeightedSimType::Ruzicka),
+        "overlap" => Some(WeightedSimType::Overlap),
+        "cosine" => Some(WeightedSimType::Cosine),
+        _ => None,
+    }
+}
+
+/// Calculate the weighted set similarity between two gene -> weight maps. The
+/// absolute values of the weights are used. Options are the weighted Jaccard
+/// (Ruzicka) similarity, the weighted overlap coefficient or the cosine
+/// similarity. With all weights being 1, Ruzicka and the weighted overlap
+/// reduce to the Jaccard similarity and overlap coefficient, respectively.
+pub fn weighted_set_similarity(
+    w_1: &HashMap<String, f64>,
+    w_2: &HashMap<String, f64>,
+    sim_type: &WeightedSimType,
+) -> f64 {
+    let (smaller, larger) = if w_1.len() <= w_2.len() {
+        (w_1, w_2)
+    } else {
+        (w_2, w_1)
+    };
+
+    let sum_1: f64 = w_1.values().map(|x| x.abs()).sum();
+    let sum_2: f64 = w_2.values().map(|x| x.abs()).sum();
+
+    match sim_type {
+        WeightedSimType::Ruzicka => {
+            let shared_min: f64 = smaller
+                .iter()
+                .filter_map(|(gene, w)| larger.get(gene).map(|w2| w.abs().min(w2.abs())))
+                .sum();
+            shared_min / (sum_1 + sum_2 - shared_min)
+        }
+        WeightedSimType::Overlap => {
+            let shared_min: f64 = smaller
+                .iter()
+                .filter_map(|(gene, w)| larger.get(gene).map(|w2| w.abs().min(w2.abs())))
+                .sum();
+            shared_min / sum_1.min(sum_2)
+        }
+        WeightedSimType::Cosine => {
+            let dot: f64 = smaller
+                .iter()
+                .filter_map(|(gene, w)| larger.get(gene).map(|w2| w.abs() * w2.abs()))
+                .sum();
+            let norm_1: f64 = w_1.values().map(|x| x.powi(2)).sum::<f64>().sqrt();
+            let norm_2: f64 = w_2.values().map(|x| x.powi(2)).sum::<f64>().sqrt();
+            dot / (norm_1 * norm_2)
+        }
+    }
+}
+
 //////////////////
 // Effect sizes //
 //////////////////
@@ -113,6 +215,93 @@ pub fn z_scores_to_pval(z_scores: &[f64]) -> Vec<f64> {
         .collect()
 }
 
+//////////////////
+// Correlations //
+//////////////////
+
+/// Number of pairs within runs of equal values in a sorted slice.
+fn tied_pairs(sorted: &[f64]) -> u64 {
+    let mut ties = 0;
+    let mut i = 0;
+    while i < sorted.len() {
+        let mut j = i + 1;
+        while j < sorted.len() && sorted[j] == sorted[i] {
+            j += 1;
+        }
+        let t = (j - i) as u64;
+        ties += t * (t - 1) / 2;
+        i = j;
+    }
+    ties
+}
+
+/// Merge sort that counts the number of swaps (i.e., strictly discordant
+/// pairs) needed to sort the slice.
+fn merge_sort_swaps(values: &mut [f64], buffer: &mut [f64]) -> u64 {
+    let n = values.len();
+    if n < 2 {
+        return 0;
+    }
+    let mid = n / 2;
+    let mut swaps = merge_sort_swaps(&mut values[..mid], &mut buffer[..mid])
+        + merge_sort_swaps(&mut values[mid..], &mut buffer[mid..]);
+
+    let (mut i, mut j, mut k) = (0, mid, 0);
+    while i < mid && j < n {
+        if values[j] < values[i] {
+            buffer[k] = values[j];
+            swaps += (mid - i) as u64;
+            j += 1;
+        } else {
+            buffer[k] = values[i];
+            i += 1;
+        }
+        k += 1;
+    }
+    buffer[k..k + mid - i].copy_from_slice(&values[i..mid]);
+    k += mid - i;
+    buffer[k..k + n - j].copy_from_slice(&values[j..n]);
+    values.copy_from_slice(&buffer[..n]);
+
+    swaps
+}
+
+/// Kendall's tau-b between two vectors based on Knight's O(n log n)
+/// algorithm. Returns NaN if one of the vectors is constant.
+pub fn kendall_tau_b(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len();
+    let mut idx: Vec<usize> = (0..n).collect();
+    idx.sort_by(|&a, &b| x[a].total_cmp(&x[b]).then(y[a].total_cmp(&y[b])));
+
+    // Ties in x and joint ties in (x, y)
+    let mut x_ties = 0;
+    let mut joint_ties = 0;
+    let mut i = 0;
+    while i < n {
+        let mut j = i + 1;
+        while j < n && x[idx[j]] == x[idx[i]] {
+            j += 1;
+        }
+        let t = (j - i) as u64;
+        x_ties += t * (t - 1) / 2;
+        let y_block: Vec<f64> = idx[i..j].iter().map(|&k| y[k]).collect();
+        joint_ties += tied_pairs(&y_block);
+        i = j;
+    }
+
+    let mut y_sorted: Vec<f64> = idx.iter().map(|&k| y[k]).collect();
+    let mut buffer = vec![0.0; n];
+    let swaps = merge_sort_swaps(&mut y_sorted, &mut buffer);
+    let y_ties = tied_pairs(&y_sorted);
+
+    let n_pairs = (n * n.saturating_sub(1) / 2) as u64;
+    let numerator =
+        n_pairs as f64 - x_ties as f64 - y_ties as f64 + joint_ties as f64 - 2.0 * swaps as f64;
+    let denominator = ((n_pairs - x_ties) as f64 * (n_pairs - y_ties) as f64).sqrt();
+
+    numerator / denominator
+}
+
 ////////////////////////////
 // Radial Basis functions //
 ////////////////////////////
@@ -153,3 +342,262 @@ pub fn rbf_bump(dist: &[f64], epsilon: &f64) -> Vec<f64> {
         })
         .collect()
 }
+
+///////////////////////
+// Special functions //
+///////////////////////
+
+/// Trigamma function, i.e., the second derivative of log(gamma(x)). Uses the
+/// recurrence relation to shift x above 10 and the asymptotic expansion.
+pub fn trigamma(x: f64) -> f64 {
+    let mut x = x;
+    let mut res = 0.0;
+    while x < 10.0 {
+        res += 1.0 / (x * x);
+        x += 1.0;
+    }
+    let x2 = 1.0 / (x * x);
+    res + 1.0 / x
+        + x2 / 2.0
+        + x2 / x
+            * (1.0 / 6.0
+                - x2 * (1.0 / 30.0 - x2 * (1.0 / 42.0 - x2 * (1.0 / 30.0 - x2 * 5.0 / 66.0))))
+}
+
+/// Tetragamma function, i.e., the third derivative of log(gamma(x)). Uses the
+/// recurrence relation to shift x above 10 and the asymptotic expansion.
+pub fn tetragamma(x: f64) -> f64 {
+    let mut x = x;
+    let mut res = 0.0;
+    while x < 10.0 {
+        res -= 2.0 / (x * x * x);
+        x += 1.0;
+    }
+    let x2 = 1.0 / (x * x);
+    res - x2 - x2 / x - x2 * x2 / 2.0
+        + x2 * x2 * x2 * (1.0 / 6.0 - x2 * (1.0 / 6.0 - x2 * (3.0 / 10.0 - x2 * 5.0 / 6.0)))
+}
+
+/// Inverse of the trigamma function via Newton's method (Smyth, 2002; as in
+/// limma::trigammaInverse()).
+pub fn trigamma_inverse(x: f64) -> f64 {
+    if x > 1e7 {
+        return 1.0 / x.sqrt();
+    }
+    if x < 1e-6 {
+        return 1.0 / x;
+    }
+    let mut y = 0.5 + 1.0 / x;
+    for _ in 0..50 {
+        let tri = trigamma(y);
+        let dif = tri * (1.0 - tri / x) / tetragamma(y);
+        y += dif;
+        if -dif / y < 1e-8 {
+            break;
+        }
+    }
+    y
+}
+
+///////////////
+// Smoothing //
+///////////////
+
+/// Local weighted linear fit at xs for the points nleft..=nright (and any
+/// ties to the right). Helper for `lowess()`, see R's clowess.
+fn lowest(
+    x: &[f64],
+    y: &[f64],
+    xs: f64,
+    window: (usize, usize),
+    w: &mut [f64],
+    rw: Option<&[f64]>,
+) -> Option<f64> {
+    let n = x.len();
+    let (nleft, nright) = window;
+    let range = x[n - 1] - x[0];
+    let h = (xs - x[nleft]).max(x[nright] - xs);
+    let h9 = 0.999 * h;
+    let h1 = 0.001 * h;
+
+    let mut a = 0.0;
+    let mut j = nleft;
+    while j < n {
+        w[j] = 0.0;
+        let r = (x[j] - xs).abs();
+        if r <= h9 {
+            w[j] = if r <= h1 {
+                1.0
+            } else {
+                (1.0 - (r / h).powi(3)).powi(3)
+            };
+            if let Some(rw) = rw {
+                w[j] *= rw[j];
+            }
+            a += w[j];
+        } else if x[j] > xs {
+            break;
+        }
+        j += 1;
+    }
+    let nrt = j - 1;
+
+    if a <= 0.0 {
+        return None;
+    }
+
+    for w_j in &mut w[nleft..=nrt] {
+        *w_j /= a;
+    }
+    if h > 0.0 {
+        let a: f64 = (nleft..=nrt).map(|j| w[j] * x[j]).sum();
+        let c: f64 = (nleft..=nrt).map(|j| w[j] * (x[j] - a).powi(2)).sum();
+        if c.sqrt() > 0.001 * range {
+            let b = (xs - a) / c;
+            for j in nleft..=nrt {
+                w[j] *= b * (x[j] - a) + 1.0;
+            }
+        }
+    }
+
+    Some((nleft..=nrt).map(|j| w[j] * y[j]).sum())
+}
+
+/// Robust locally weighted scatterplot smoothing (same algorithm as
+/// stats::lowess()). x needs to be sorted in ascending order. Returns the
+/// fitted values at x.
+pub fn lowess(x: &[f64], y: &[f64], f: f64, n_iter: usize) -> Vec<f64> {
+    let n = x.len();
+    if n < 2 {
+        return y.to_vec();
+    }
+    let delta = 0.01 * (x[n - 1] - x[0]);
+    let ns = ((f * n as f64 + 1e-7) as usize).clamp(2, n);
+
+    let mut ys = vec![0.0; n];
+    let mut rw = vec![1.0; n];
+    let mut res = vec![0.0; n];
+
+    for iter in 0..=n_iter {
+        let mut nleft = 0;
+        let mut nright = ns - 1;
+        let mut last: Option<usize> = None;
+        let mut i = 0;
+
+        loop {
+            if nright < n - 1 {
+                let d1 = x[i] - x[nleft];
+                let d2 = x[nright + 1] - x[i];
+                if d1 > d2 {
+                    nleft += 1;
+                    nright += 1;
+                    continue;
+                }
+            }
+
+            let robustness = if iter > 0 { Some(rw.as_slice()) } else { None };
+            ys[i] = lowest(x, y, x[i], (nleft, nright), &mut res, robustness).unwrap_or(y[i]);
+
+            // Interpolate skipped points
+            if let Some(last) = last {
+                if last + 1 < i {
+                    let denom = x[i] - x[last];
+                    for j in last + 1..i {
+                        let alpha = (x[j] - x[last]) / denom;
+                        ys[j] = alpha * ys[i] + (1.0 - alpha) * ys[last];
+                    }
+                }
+            }
+
+            let mut last_i = i;
+            let cut = x[last_i] + delta;
+            i = last_i + 1;
+            while i < n {
+                if x[i] > cut {
+                    break;
+                }
+                if x[i] == x[last_i] {
+                    ys[i] = ys[last_i];
+                    last_i = i;
+                }
+                i += 1;
+            }
+            last = Some(last_i);
+            i = (last_i + 1).max(i - 1);
+            if last_i >= n - 1 {
+                break;
+            }
+        }
+
+        for i in 0..n {
+            res[i] = y[i] - ys[i];
+        }
+        if iter == n_iter {
+            break;
+        }
+
+        // Bisquare robustness weights based on six times the median absolute
+        // residual
+        let sc = res.iter().map(|r| r.abs()).sum::<f64>() / n as f64;
+        let mut abs_res: Vec<f64> = res.iter().map(|r| r.abs()).collect();
+        abs_res.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let m1 = n / 2;
+        let cmad = if n.is_multiple_of(2) {
+            3.0 * (abs_res[m1] + abs_res[n - m1 - 1])
+        } else {
+            6.0 * abs_res[m1]
+        };
+        if cmad < 1e-7 * sc {
+            break;
+        }
+        let c9 = 0.999 * cmad;
+        let c1 = 0.001 * cmad;
+        for i in 0..n {
+            let r = res[i].abs();
+            rw[i] = if r <= c1 {
+                1.0
+            } else if r <= c9 {
+                (1.0 - (r / cmad).powi(2)).powi(2)
+            } else {
+                0.0
+            };
+        }
+    }
+
+    ys
+}
+
+/// Linear interpolation of the points (x, y) at xout with constant
+/// extrapolation beyond the range of x (stats::approx() with rule = 2). x
+/// needs to be sorted in ascending order; the y values of tied x are averaged.
+pub fn approx_linear(x: &[f64], y: &[f64], xout: &[f64]) -> Vec<f64> {
+    let mut x_unique: Vec<f64> = Vec::with_capacity(x.len());
+    let mut y_unique: Vec<f64> = Vec::with_capacity(x.len());
+    let mut i = 0;
+    while i < x.len() {
+        let mut j = i;
+        while j < x.len() && x[j] == x[i] {
+            j += 1;
+        }
+        x_unique.push(x[i]);
+        y_unique.push(y[i..j].iter().sum::<f64>() / (j - i) as f64);
+        i = j;
+    }
+    let n = x_unique.len();
+
+    xout.iter()
+        .map(|&v| {
+            if v.is_nan() {
+                f64::NAN
+            } else if v <= x_unique[0] {
+                y_unique[0]
+            } else if v >= x_unique[n - 1] {
+                y_unique[n - 1]
+            } else {
+                let k = x_unique.partition_point(|&xi| xi <= v);
+                let t = (v - x_unique[k - 1]) / (x_unique[k] - x_unique[k - 1]);
+                y_unique[k - 1] + t * (y_unique[k] - y_unique[k - 1])
+            }
+        })
+        .collect()
+}